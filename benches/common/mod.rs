@@ -0,0 +1,76 @@
+// every bench only uses some of these helpers
+#![allow(dead_code)]
+
+use raycaster::map::Map;
+
+pub const TILE_SIZE: f32 = 64.0;
+
+/// Small deterministic generator, so every run benchmarks the same maps
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+}
+
+fn border(width: usize, height: usize, x: usize, y: usize) -> bool {
+    x == 0 || y == 0 || x == width - 1 || y == height - 1
+}
+
+/// Only the border is made of walls
+pub fn open_map(size: usize) -> Map {
+    let tiles = (0..size * size)
+        .map(|i| border(size, size, i % size, i / size) as u8)
+        .collect();
+    Map::new("open".to_string(), size, size, TILE_SIZE, tiles)
+}
+
+/// `density` percent of the tiles are walls, the center is kept empty
+pub fn dense_map(size: usize, density: u64) -> Map {
+    let mut random = Lcg(size as u64);
+    let center = size / 2;
+    let tiles = (0..size * size)
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let wall = border(size, size, x, y) || random.next() % 100 < density;
+            (wall && (x, y) != (center, center)) as u8
+        })
+        .collect();
+    Map::new("dense".to_string(), size, size, TILE_SIZE, tiles)
+}
+
+/// A single corridor, one tile wide
+pub fn corridor_map(length: usize) -> Map {
+    let tiles = (0..length * 3)
+        .map(|i| border(length, 3, i % length, i / length) as u8)
+        .collect();
+    Map::new("corridor".to_string(), length, 3, TILE_SIZE, tiles)
+}
+
+/// Center of the given tile, in world units
+pub fn tile_center(x: usize, y: usize) -> (f32, f32) {
+    ((x as f32 + 0.5) * TILE_SIZE, (y as f32 + 0.5) * TILE_SIZE)
+}
+
+/// Map file text of the given map
+pub fn map_file(map: &Map) -> String {
+    let mut text = format!(
+        "name = {}\nsize = {}x{}\n---\n",
+        map.get_name(),
+        map.get_width(),
+        map.get_height()
+    );
+    for y in 0..map.get_height() {
+        let row: Vec<String> = (0..map.get_width())
+            .map(|x| map[(x, y)].to_string())
+            .collect();
+        text.push_str(&row.join(" "));
+        text.push('\n');
+    }
+    text
+}
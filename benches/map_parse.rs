@@ -0,0 +1,21 @@
+mod common;
+
+use common::{dense_map, map_file, TILE_SIZE};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use raycaster::map::Map;
+
+fn map_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("map_parse");
+    group.sample_size(10);
+    for size in [256, 1024, 2048] {
+        let text = map_file(&dense_map(size, 30));
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &text, |b, text| {
+            b.iter(|| Map::parse(text.as_bytes(), TILE_SIZE).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, map_parse);
+criterion_main!(benches);
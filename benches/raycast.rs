@@ -0,0 +1,50 @@
+mod common;
+
+use common::{corridor_map, dense_map, open_map, tile_center};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use raycaster::camera::Camera;
+use raycaster::map::Map;
+
+/// Rays cast per iteration, one per column of a 320 pixels wide view
+const COLUMNS: usize = 320;
+
+fn cast_view(map: &Map, camera: &Camera) {
+    for column in 0..COLUMNS {
+        let direction = camera.ray_direction(column as f32, COLUMNS as f32);
+        black_box(camera.raycast(map, direction));
+    }
+}
+
+fn raycast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("raycast");
+    for size in [64, 512, 4096] {
+        let map = open_map(size);
+        let (x, y) = tile_center(1, 1);
+        let camera = Camera::new(x, y, std::f32::consts::FRAC_PI_4);
+        group.bench_with_input(BenchmarkId::new("open", size), &map, |b, map| {
+            b.iter(|| cast_view(map, &camera))
+        });
+    }
+    for size in [64, 4096] {
+        let map = dense_map(size, 20);
+        let (x, y) = tile_center(size / 2, size / 2);
+        let camera = Camera::new(x, y, 0.3);
+        group.bench_with_input(BenchmarkId::new("dense", size), &map, |b, map| {
+            b.iter(|| cast_view(map, &camera))
+        });
+    }
+    for length in [256, 4096] {
+        let map = corridor_map(length);
+        let (x, y) = tile_center(1, 1);
+        // looking down the corridor, every ray goes to the far end
+        let mut camera = Camera::new(x, y, 0.0);
+        camera.set_fov(0.2);
+        group.bench_with_input(BenchmarkId::new("corridor", length), &map, |b, map| {
+            b.iter(|| cast_view(map, &camera))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, raycast);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use raycaster::camera::Camera;
+use raycaster::framebuffer::Framebuffer;
+use raycaster::map::Map;
+use raycaster::renderer::Renderer;
+use raycaster::textures::TextureManager;
+
+const RESOLUTIONS: [(usize, usize); 3] = [(320, 200), (640, 400), (1280, 800)];
+
+/// Full frames of the test map, with one thread and with one thread per core
+fn render(c: &mut Criterion) {
+    let map = Map::open("maps/testMap.map", 64.0).unwrap();
+    let textures = TextureManager::for_map(&map);
+    let camera = Camera::new(100.0, 100.0, 0.7);
+
+    let mut group = c.benchmark_group("render");
+    for (name, threads) in [("single-thread", 1), ("all-threads", 0)] {
+        let mut renderer = Renderer::new(threads);
+        for (width, height) in RESOLUTIONS {
+            let mut framebuffer = Framebuffer::new(width, height);
+            let size = format!("{}x{}", width, height);
+            group.bench_function(BenchmarkId::new(name, size), |b| {
+                b.iter(|| renderer.render(&map, &camera, &textures, &mut framebuffer))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
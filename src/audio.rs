@@ -0,0 +1,14 @@
+/// Plays the sounds of the triggers and scripts
+pub trait AudioOutput {
+    /// Starts the sound with the given name, a sound the output doesn't
+    /// know is ignored
+    fn play(&mut self, name: &str);
+}
+
+/// Output used while there is no audio backend, the sounds are dropped
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct NoAudio;
+
+impl AudioOutput for NoAudio {
+    fn play(&mut self, _name: &str) {}
+}
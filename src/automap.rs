@@ -0,0 +1,239 @@
+use crate::camera::Camera;
+use crate::map::Map;
+use crate::render::TopDownView;
+use crate::vector::Vector;
+use macroquad::prelude::{
+    draw_circle, draw_circle_lines, draw_line, draw_rectangle, draw_rectangle_ex, vec2, Color,
+    DrawRectangleParams, Rect, GRAY, RED, WHITE, YELLOW,
+};
+use std::io::{self, BufRead, Write};
+
+/// Radius of the minimap, in tiles
+const MINIMAP_RADIUS: f32 = 6.0;
+
+/// Tiles seen by the player so far (fog of war)
+#[derive(Clone)]
+pub struct Automap {
+    width: usize,
+    height: usize,
+    explored: Vec<bool>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn tile_color(tile: u8) -> Color {
+    match tile {
+        0 => Color::new(0.25, 0.25, 0.25, 1.0),
+        _ => WHITE,
+    }
+}
+
+impl Automap {
+    /// Creates an automap for the given map where nothing is explored
+    pub fn new(map: &Map) -> Self {
+        Self {
+            width: map.get_width(),
+            height: map.get_height(),
+            explored: vec![false; map.get_width() * map.get_height()],
+        }
+    }
+
+    pub fn is_explored(&self, x: usize, y: usize) -> bool {
+        self.explored[y * self.width + x]
+    }
+
+    pub fn reveal(&mut self, x: usize, y: usize) {
+        self.explored[y * self.width + x] = true;
+    }
+
+    /// Reveals every tile traversed or hit by the rays of a `columns` wide view
+    pub fn record_view(&mut self, map: &Map, camera: &Camera, columns: usize) {
+        for column in 0..columns {
+            let direction = camera.ray_direction(column as f32, columns as f32);
+            camera.raycast_traced(map, direction, |tile| self.reveal(tile.x, tile.y));
+        }
+    }
+
+    /// Writes the explored tiles, one line per row
+    /// (`#` for explored tiles, `.` for unexplored ones)
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{}x{}", self.width, self.height)?;
+        for row in self.explored.chunks(self.width) {
+            let line: String = row.iter().map(|e| if *e { '#' } else { '.' }).collect();
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Reads explored tiles written by [Automap::write],
+    /// the size must match the given map
+    pub fn read(reader: impl BufRead, map: &Map) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let size = lines.next().ok_or_else(|| invalid_data("Missing size"))??;
+        if size != format!("{}x{}", map.get_width(), map.get_height()) {
+            return Err(invalid_data("Automap size doesn't match the map"));
+        }
+
+        let mut automap = Automap::new(map);
+        for y in 0..automap.height {
+            let line = lines
+                .next()
+                .ok_or_else(|| invalid_data("Not enough rows"))??;
+            if line.len() != automap.width {
+                return Err(invalid_data("Invalid row length"));
+            }
+            for (x, tile) in line.chars().enumerate() {
+                match tile {
+                    '#' => automap.reveal(x, y),
+                    '.' => {}
+                    _ => return Err(invalid_data("Invalid tile")),
+                }
+            }
+        }
+        Ok(automap)
+    }
+
+    /// Draws the explored part of the map over the whole viewport
+    pub fn draw_automap(&self, map: &Map, camera: &Camera, viewport: Rect) {
+        draw_rectangle(
+            viewport.x,
+            viewport.y,
+            viewport.w,
+            viewport.h,
+            Color::new(0.0, 0.0, 0.0, 0.85),
+        );
+        let view = TopDownView::fit(map, viewport);
+        let size = map.get_tile_size() * view.scale;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.is_explored(x, y) {
+                    let corner = view.project(map.tile_corner(x, y));
+                    draw_rectangle(corner.x, corner.y, size, size, tile_color(map[(x, y)]));
+                }
+            }
+        }
+
+        let pos = view.project(camera.get_position());
+        let end = pos + camera.get_direction() * size;
+        draw_circle(pos.x, pos.y, size / 3.0, RED);
+        draw_line(pos.x, pos.y, end.x, end.y, 2.0, YELLOW);
+    }
+
+    /// Draws a round minimap centered on the camera, if `rotate` is true
+    /// the camera always looks up, otherwise the north is up
+    pub fn draw_minimap(
+        &self,
+        map: &Map,
+        camera: &Camera,
+        center: Vector<f32>,
+        radius: f32,
+        rotate: bool,
+    ) {
+        draw_circle(center.x, center.y, radius, Color::new(0.0, 0.0, 0.0, 0.7));
+
+        let pixels_per_tile = radius / MINIMAP_RADIUS;
+        let camera_pos = camera.get_position() / map.get_tile_size();
+        let rotation = if rotate {
+            -std::f32::consts::FRAC_PI_2 - camera.get_angle()
+        } else {
+            0.0
+        };
+        let (sin, cos) = rotation.sin_cos();
+
+        let min_x = (camera_pos.x - MINIMAP_RADIUS).max(0.0) as usize;
+        let min_y = (camera_pos.y - MINIMAP_RADIUS).max(0.0) as usize;
+        let max_x = ((camera_pos.x + MINIMAP_RADIUS).max(0.0) as usize).min(self.width - 1);
+        let max_y = ((camera_pos.y + MINIMAP_RADIUS).max(0.0) as usize).min(self.height - 1);
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                if !self.is_explored(x, y) {
+                    continue;
+                }
+                let offset = Vector::new(x as f32 + 0.5, y as f32 + 0.5) - camera_pos;
+                let offset = Vector::new(
+                    offset.x * cos - offset.y * sin,
+                    offset.x * sin + offset.y * cos,
+                );
+                if offset.x * offset.x + offset.y * offset.y > (MINIMAP_RADIUS - 0.5).powi(2) {
+                    continue;
+                }
+                let pos = center + offset * pixels_per_tile;
+                draw_rectangle_ex(
+                    pos.x,
+                    pos.y,
+                    pixels_per_tile,
+                    pixels_per_tile,
+                    DrawRectangleParams {
+                        offset: vec2(0.5, 0.5),
+                        rotation,
+                        color: tile_color(map[(x, y)]),
+                    },
+                );
+            }
+        }
+
+        let (dir_sin, dir_cos) = (camera.get_angle() + rotation).sin_cos();
+        let end = center + Vector::new(dir_cos, dir_sin) * pixels_per_tile;
+        draw_circle(center.x, center.y, pixels_per_tile / 3.0, RED);
+        draw_line(center.x, center.y, end.x, end.y, 2.0, YELLOW);
+        draw_circle_lines(center.x, center.y, radius, 2.0, GRAY);
+    }
+}
+
+#[cfg(test)]
+mod automap_tests {
+    use super::*;
+
+    fn corridor() -> Map {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1, 1,
+            1, 0, 0, 1, 0, 1,
+            1, 1, 1, 1, 1, 1,
+        ];
+        Map::new("test".to_string(), 6, 3, 10.0, tiles)
+    }
+
+    #[test]
+    fn record_view_reveals_visible_tiles() {
+        let map = corridor();
+        let mut automap = Automap::new(&map);
+        let camera = Camera::new(15.0, 15.0, 0.0);
+        automap.record_view(&map, &camera, 32);
+        assert!(automap.is_explored(1, 1));
+        assert!(automap.is_explored(2, 1));
+        assert!(automap.is_explored(3, 1));
+        // behind the wall
+        assert!(!automap.is_explored(4, 1));
+        // behind the camera
+        assert!(!automap.is_explored(0, 1));
+    }
+
+    #[test]
+    fn write_read() -> io::Result<()> {
+        let map = corridor();
+        let mut automap = Automap::new(&map);
+        automap.reveal(1, 1);
+        automap.reveal(5, 2);
+
+        let mut data = Vec::new();
+        automap.write(&mut data)?;
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "6x3\n......\n.#....\n.....#\n"
+        );
+
+        let loaded = Automap::read(data.as_slice(), &map)?;
+        assert!(loaded.explored == automap.explored);
+        Ok(())
+    }
+
+    #[test]
+    fn read_wrong_size() {
+        let map = corridor();
+        assert!(Automap::read("5x3\n".as_bytes(), &map).is_err());
+        assert!(Automap::read("6x3\n......\n".as_bytes(), &map).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::dda::Dda;
+use crate::map::Map;
+use crate::vector::Vector;
+
+/// Default horizontal field of view, matches the 0.66 camera plane
+/// used in the Lodev tutorial
+pub const DEFAULT_FOV: f32 = 66.0 * std::f32::consts::PI / 180.0;
+const MIN_FOV: f32 = 10.0 * std::f32::consts::PI / 180.0;
+const MAX_FOV: f32 = 150.0 * std::f32::consts::PI / 180.0;
+
+/// Default eye height, in tiles (walls are one tile high)
+pub const DEFAULT_HEIGHT: f32 = 0.5;
+const MAX_PITCH: f32 = 1.0;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum RayCastResult {
+    Hit(f32, Vector<usize>, bool),
+    NoHit,
+}
+
+/// A point of view in the map, independent of whatever owns it
+/// (player, security camera, cutscene path...).
+/// The projection is based on a direction vector and a camera plane
+/// perpendicular to it (see https://lodev.org/cgtutor/raycasting.html).
+/// Ray directions are spread linearly across the plane so every column
+/// is evenly spaced and the distances returned by the raycaster are
+/// perpendicular to the camera plane by construction.
+#[derive(Clone)]
+pub struct Camera {
+    position: Vector<f32>,
+    direction: Vector<f32>,
+    plane: Vector<f32>,
+    angle: f32,
+    fov: f32,
+    pitch: f32,
+    height: f32,
+}
+
+impl Camera {
+    pub fn new(x: f32, y: f32, angle: f32) -> Self {
+        let mut camera = Self {
+            position: Vector::new(x, y),
+            direction: Vector::new(1.0, 0.0),
+            plane: Vector::new(0.0, 1.0),
+            angle,
+            fov: DEFAULT_FOV,
+            pitch: 0.0,
+            height: DEFAULT_HEIGHT,
+        };
+        camera.update_vectors();
+        camera
+    }
+
+    fn update_vectors(&mut self) {
+        let plane_len = (self.fov / 2.0).tan();
+        self.direction = Vector::new(self.angle.cos(), self.angle.sin());
+        self.plane = Vector::new(-self.direction.y, self.direction.x) * plane_len;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_position(&self) -> Vector<f32> {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vector<f32>) {
+        self.position = position;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_angle(&self) -> f32 {
+        self.angle
+    }
+
+    pub fn set_angle(&mut self, angle: f32) {
+        self.angle = angle;
+        self.update_vectors();
+    }
+
+    pub fn get_fov(&self) -> f32 {
+        self.fov
+    }
+
+    /// Sets the horizontal field of view (in radians),
+    /// the value is clamped to a sensible range
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov.clamp(MIN_FOV, MAX_FOV);
+        self.update_vectors();
+    }
+
+    pub fn get_pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    /// Sets the vertical look offset, as a fraction of the view height.
+    /// Positive values look up
+    pub fn set_pitch(&mut self, pitch: f32) {
+        self.pitch = pitch.clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn get_height(&self) -> f32 {
+        self.height
+    }
+
+    /// Sets the eye height, in tiles, clamped between the floor and the ceiling
+    pub fn set_height(&mut self, height: f32) {
+        self.height = height.clamp(0.01, 0.99);
+    }
+
+    #[allow(dead_code)]
+    pub fn get_direction(&self) -> Vector<f32> {
+        self.direction
+    }
+
+    #[allow(dead_code)]
+    pub fn get_plane(&self) -> Vector<f32> {
+        self.plane
+    }
+
+    /// Distance between the eye and a projection plane of the given width,
+    /// a wall at distance 1 is `focal_length` pixels high
+    pub fn focal_length(&self, width: f32) -> f32 {
+        width / 2.0 / (self.fov / 2.0).tan()
+    }
+
+    /// Vertical position of the horizon line in a view of the given height
+    pub fn horizon(&self, height: f32) -> f32 {
+        height / 2.0 + self.pitch * height
+    }
+
+    /// Direction of the ray going through the given screen column.
+    /// The vector is not normalized: its projection on the camera
+    /// direction is always 1
+    pub fn ray_direction(&self, column: f32, width: f32) -> Vector<f32> {
+        let camera_x = 2.0 * column / width - 1.0;
+        self.direction + self.plane * camera_x
+    }
+
+    /// World position reached by a ray after the given distance,
+    /// `distance` being a value returned by [Camera::raycast]
+    pub fn hit_point(&self, map: &Map, direction: Vector<f32>, distance: f32) -> Vector<f32> {
+        self.position + direction * (distance * map.get_tile_size())
+    }
+
+    /// Horizontal position of a hit on the wall face, between 0 and 1,
+    /// oriented so wall textures are never mirrored.
+    /// `distance` and `side` are values returned by [Camera::raycast]
+    pub fn wall_offset(&self, map: &Map, direction: Vector<f32>, distance: f32, side: bool) -> f32 {
+        let hit = self.position / map.get_tile_size() + direction * distance;
+        let (offset, flip) = if side {
+            (hit.x, direction.y < 0.0)
+        } else {
+            (hit.y, direction.x > 0.0)
+        };
+        let offset = offset - offset.floor();
+        if flip {
+            1.0 - offset
+        } else {
+            offset
+        }
+    }
+
+    /// cast a single ray from the camera position in the given direction
+    /// (usually given by [Camera::ray_direction]) and return the distance
+    /// to the nearest wall, measured along the camera direction
+    /// Return [RayCastResult::Hit] if the ray hit a wall
+    /// Return [RayCastResult::NoHit] if the ray didn't hit a wall
+    pub fn raycast(&self, map: &Map, direction: Vector<f32>) -> RayCastResult {
+        self.raycast_traced(map, direction, |_| {})
+    }
+
+    /// Same as [Camera::raycast] but calls `visit` for every tile
+    /// crossed by the ray, from the camera tile up to the hit tile
+    pub fn raycast_traced(
+        &self,
+        map: &Map,
+        direction: Vector<f32>,
+        mut visit: impl FnMut(Vector<usize>),
+    ) -> RayCastResult {
+        if !map.in_map(&self.position) {
+            return RayCastResult::NoHit;
+        }
+
+        let origin = self.position / map.get_tile_size();
+        for (i, crossing) in Dda::new(origin, direction).enumerate() {
+            let Some(tile) = map.tile_at(crossing.tile) else {
+                break;
+            };
+            visit(tile);
+            // the camera tile is never a hit, even inside a wall
+            if i > 0 && map.is_wall(tile.x, tile.y) {
+                return RayCastResult::Hit(crossing.distance, tile, crossing.side);
+            }
+        }
+        RayCastResult::NoHit
+    }
+}
+
+#[cfg(test)]
+mod camera_tests {
+    use super::*;
+
+    #[test]
+    fn center_ray_is_direction() {
+        let camera = Camera::new(0.0, 0.0, 0.3);
+        let ray = camera.ray_direction(50.0, 100.0);
+        assert!((ray.x - 0.3f32.cos()).abs() < 1e-6);
+        assert!((ray.y - 0.3f32.sin()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn edge_rays_match_fov() {
+        let fov = std::f32::consts::FRAC_PI_2;
+        let mut camera = Camera::new(0.0, 0.0, 0.0);
+        camera.set_fov(fov);
+        let left = camera.ray_direction(0.0, 100.0);
+        let right = camera.ray_direction(100.0, 100.0);
+        assert!((left.y.atan2(left.x) + fov / 2.0).abs() < 1e-5);
+        assert!((right.y.atan2(right.x) - fov / 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fov_is_clamped() {
+        let mut camera = Camera::new(0.0, 0.0, 0.0);
+        camera.set_fov(0.0);
+        assert_eq!(camera.get_fov(), MIN_FOV);
+        camera.set_fov(10.0);
+        assert_eq!(camera.get_fov(), MAX_FOV);
+    }
+
+    #[test]
+    fn perpendicular_distance() {
+        // 5x3 corridor closed on both ends, camera in the middle of it
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1,
+            1, 0, 0, 0, 1,
+            1, 1, 1, 1, 1,
+        ];
+        let map = Map::new("test".to_string(), 5, 3, 1.0, tiles);
+        let camera = Camera::new(1.5, 1.5, 0.0);
+        for column in [40.0, 45.0, 50.0, 55.0, 60.0] {
+            match camera.raycast(&map, camera.ray_direction(column, 100.0)) {
+                RayCastResult::Hit(distance, tile, _) => {
+                    assert_eq!(tile.x, 4);
+                    assert!((distance - 2.5).abs() < 1e-5);
+                }
+                RayCastResult::NoHit => panic!("ray should hit the end wall"),
+            }
+        }
+    }
+
+    #[test]
+    fn traced_cells() {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1,
+            1, 0, 0, 0, 1,
+            1, 1, 1, 1, 1,
+        ];
+        let map = Map::new("test".to_string(), 5, 3, 10.0, tiles);
+        let camera = Camera::new(15.0, 15.0, 0.0);
+        let mut visited = Vec::new();
+        let direction = camera.ray_direction(50.0, 100.0);
+        let ray = camera.raycast_traced(&map, direction, |tile| visited.push((tile.x, tile.y)));
+        assert_eq!(visited, vec![(1, 1), (2, 1), (3, 1), (4, 1)]);
+        match ray {
+            RayCastResult::Hit(distance, _, _) => {
+                let hit = camera.hit_point(&map, direction, distance);
+                assert!((hit.x - 40.0).abs() < 1e-4);
+                assert!((hit.y - 15.0).abs() < 1e-4);
+            }
+            RayCastResult::NoHit => panic!("ray should hit the end wall"),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::entity::Entities;
+use crate::inventory::Inventory;
+use crate::map::Map;
+use crate::player::Player;
+use crate::trigger::Triggers;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Maps played one after the other, the player going to the next one
+/// at the end of each level
+#[derive(Clone, PartialEq, Debug)]
+pub struct Campaign {
+    name: String,
+    maps: Vec<PathBuf>,
+}
+
+impl Campaign {
+    pub fn new(name: String, maps: Vec<PathBuf>) -> Self {
+        Self { name, maps }
+    }
+
+    /// Reads a campaign file: a `name = <name>` header, a `---` separator
+    /// and the maps in order, one per line, relative to the directory
+    pub fn parse(reader: impl BufRead, directory: &Path) -> Result<Self, String> {
+        let mut name = None;
+        let mut maps = Vec::new();
+        let mut in_header = true;
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            if line.is_empty() {
+                continue;
+            }
+            if line == "---" {
+                if !in_header {
+                    return Err(error("unexpected separator"));
+                }
+                in_header = false;
+            } else if in_header {
+                match line.split_once('=') {
+                    Some((key, value)) if key.trim() == "name" => {
+                        name = Some(value.trim().to_string())
+                    }
+                    Some((key, _)) => {
+                        return Err(error(&format!("unknown field \"{}\"", key.trim())))
+                    }
+                    None => return Err(error("expected key = value")),
+                }
+            } else {
+                maps.push(directory.join(line));
+            }
+        }
+        let name = name.ok_or("missing name")?;
+        if maps.is_empty() {
+            return Err("no maps".to_string());
+        }
+        Ok(Self { name, maps })
+    }
+
+    /// Reads a campaign file, checking its maps exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let campaign = Self::parse(BufReader::new(file), directory)?;
+        if let Some(missing) = campaign.maps.iter().find(|map| !map.is_file()) {
+            return Err(format!("missing map {}", missing.display()));
+        }
+        Ok(campaign)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_maps(&self) -> &[PathBuf] {
+        &self.maps
+    }
+}
+
+/// What the player keeps from a level to the next: health, armor,
+/// weapons and items. The keys only open the doors of their level
+#[derive(Clone, Debug)]
+pub struct CarriedPlayer {
+    pub health: i32,
+    pub armor: i32,
+    pub inventory: Inventory,
+}
+
+impl CarriedPlayer {
+    pub fn of(player: &Player) -> Self {
+        let mut inventory = player.get_inventory().clone();
+        inventory.clear_keys();
+        Self {
+            health: player.get_health(),
+            armor: player.get_armor(),
+            inventory,
+        }
+    }
+
+    /// Gives the stats and the inventory to the player of the next level
+    pub fn apply(&self, player: &mut Player) {
+        player.set_health(self.health);
+        player.set_armor(self.armor);
+        *player.get_inventory_mut() = self.inventory.clone();
+    }
+}
+
+/// Statistics shown at the end of a level
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct LevelStats {
+    /// Time played, in seconds
+    pub time: f32,
+    pub kills: usize,
+    pub enemies: usize,
+    pub secrets: usize,
+    pub total_secrets: usize,
+}
+
+impl LevelStats {
+    /// Counts the enemies killed among the ones of the level, spawned by
+    /// the script included, and the secret triggers fired
+    pub fn count(map: &Map, entities: &Entities, triggers: &Triggers, time: f32) -> Self {
+        let enemies: Vec<_> = entities
+            .iter()
+            .filter(|entity| !entity.is_removed())
+            .filter(|entity| {
+                let properties = entity
+                    .placement
+                    .as_ref()
+                    .and_then(|placement| map.get_definitions().get(&placement.kind));
+                properties
+                    .is_some_and(|properties| properties.get("ai").is_some_and(|ai| ai == "enemy"))
+            })
+            .collect();
+        let secrets: Vec<_> = triggers.iter().filter(|t| t.is_secret()).collect();
+        Self {
+            time,
+            kills: enemies.iter().filter(|entity| entity.is_dead()).count(),
+            enemies: enemies.len(),
+            secrets: secrets.iter().filter(|t| t.has_fired()).count(),
+            total_secrets: secrets.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod campaign_tests {
+    use super::*;
+    use crate::enemy::spawn_map_entities;
+    use crate::inventory::Pickup;
+    use crate::textures::TextureManager;
+    use crate::trigger::EventQueue;
+
+    #[test]
+    fn parse_campaigns() {
+        let text = "name = Episode 1\n---\nfirst.map\n\nlevels/second.map\n";
+        let campaign = Campaign::parse(text.as_bytes(), Path::new("maps")).unwrap();
+        assert_eq!(campaign.get_name(), "Episode 1");
+        assert_eq!(
+            campaign.get_maps(),
+            [
+                PathBuf::from("maps/first.map"),
+                PathBuf::from("maps/levels/second.map")
+            ]
+        );
+
+        for text in [
+            "---\nfirst.map\n",
+            "name = a\n---\n",
+            "name = a\nmaps = 2\n---\nfirst.map\n",
+            "name = a\n---\nfirst.map\n---\n",
+        ] {
+            assert!(
+                Campaign::parse(text.as_bytes(), Path::new("")).is_err(),
+                "{}",
+                text
+            );
+        }
+
+        let episode = Campaign::open("maps/episode.campaign").unwrap();
+        for path in episode.get_maps() {
+            Map::open(path, 64.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn carried_player() {
+        let mut player = Player::new(0.0, 0.0, 0.0);
+        player.damage(30);
+        player.collect(&Pickup::Key("red".to_string()));
+        player.collect(&Pickup::Weapon("launcher".to_string()));
+        player.collect(&Pickup::Item {
+            name: "coin".to_string(),
+            count: 3,
+        });
+
+        let mut next = Player::new(100.0, 100.0, 0.0);
+        CarriedPlayer::of(&player).apply(&mut next);
+        assert_eq!(next.get_health(), player.get_health());
+        let inventory = next.get_inventory();
+        assert!(!inventory.has_key("red"));
+        assert_eq!(inventory.get_weapons().len(), 2);
+        assert_eq!(inventory.get_item_count("coin"), 3);
+    }
+
+    #[test]
+    fn level_stats() {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let mut entities = Entities::new(&map);
+        spawn_map_entities(&map, &TextureManager::new(), &mut entities);
+        let (mut triggers, _) = Triggers::from_map(&map);
+        let stats = LevelStats::count(&map, &entities, &triggers, 12.5);
+        assert_eq!((stats.kills, stats.enemies), (0, 2));
+        assert_eq!((stats.secrets, stats.total_secrets), (0, 1));
+
+        let guard = entities.iter().next().unwrap().get_id();
+        entities.get_mut(guard).unwrap().damage(100);
+        // the secret passage right of the start
+        triggers.update(Some((3, 1)), None, false, &mut EventQueue::new());
+        let stats = LevelStats::count(&map, &entities, &triggers, 12.5);
+        assert_eq!((stats.kills, stats.enemies), (1, 2));
+        assert_eq!((stats.secrets, stats.total_secrets), (1, 1));
+        assert_eq!(stats.time, 12.5);
+    }
+}
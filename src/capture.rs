@@ -0,0 +1,206 @@
+use crate::framebuffer::{Framebuffer, Pixel};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Saves the framebuffer as a PNG image
+pub fn save_png(framebuffer: &Framebuffer, path: &Path) -> io::Result<()> {
+    image::save_buffer(
+        path,
+        framebuffer.as_bytes(),
+        framebuffer.get_width() as u32,
+        framebuffer.get_height() as u32,
+        image::ColorType::Rgba8,
+    )
+    .map_err(io::Error::other)
+}
+
+/// First `<directory>/<prefix>-NNNN.png` path which doesn't exist yet,
+/// the directory is created if needed
+pub fn next_screenshot_path(directory: &Path, prefix: &str) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    (0..)
+        .map(|i| directory.join(format!("{}-{:04}.png", prefix, i)))
+        .find(|path| !path.exists())
+        .ok_or_else(|| io::Error::other("No free screenshot name"))
+}
+
+/// Output of a [Recorder]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordFormat {
+    /// Numbered PNG images in a directory
+    Png,
+    /// A single uncompressed YUV4MPEG2 video (4:4:4)
+    Y4m,
+}
+
+/// Dumps the rendered frames to disk, one frame every `every` frames
+pub struct Recorder {
+    format: RecordFormat,
+    path: PathBuf,
+    every: usize,
+    /// Frames given to [Recorder::capture], recorded or not
+    frames: usize,
+    recorded: usize,
+    /// Open video and its frame size, for the Y4M format
+    video: Option<(BufWriter<File>, usize, usize)>,
+    fps: u32,
+}
+
+/// BT.601 limited range conversion
+fn to_yuv(pixel: Pixel) -> [u8; 3] {
+    let [r, g, b] = [pixel[0] as i32, pixel[1] as i32, pixel[2] as i32];
+    [
+        (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8,
+        (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8,
+        (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8,
+    ]
+}
+
+/// Writes a Y4M frame, planes are written one after the other
+fn write_y4m_frame(writer: &mut impl Write, framebuffer: &Framebuffer) -> io::Result<()> {
+    writer.write_all(b"FRAME\n")?;
+    let yuv: Vec<[u8; 3]> = framebuffer
+        .get_pixels()
+        .iter()
+        .map(|p| to_yuv(*p))
+        .collect();
+    for plane in 0..3 {
+        let bytes: Vec<u8> = yuv.iter().map(|pixel| pixel[plane]).collect();
+        writer.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+impl Recorder {
+    /// Creates a recorder writing in `path`: a directory for the PNG format,
+    /// a file for the Y4M format. `fps` is the frame rate stored in the video
+    pub fn new(format: RecordFormat, path: &Path, every: usize, fps: u32) -> Self {
+        Self {
+            format,
+            path: path.to_path_buf(),
+            every: every.max(1),
+            frames: 0,
+            recorded: 0,
+            video: None,
+            fps,
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of frames written so far
+    pub fn get_recorded(&self) -> usize {
+        self.recorded
+    }
+
+    /// Gives a rendered frame to the recorder, only one every `every` frames is written.
+    /// Every frame of a video must have the same size
+    pub fn capture(&mut self, framebuffer: &Framebuffer) -> io::Result<()> {
+        self.frames += 1;
+        if !(self.frames - 1).is_multiple_of(self.every) {
+            return Ok(());
+        }
+
+        match self.format {
+            RecordFormat::Png => {
+                std::fs::create_dir_all(&self.path)?;
+                let path = self.path.join(format!("frame-{:06}.png", self.recorded));
+                save_png(framebuffer, &path)?;
+            }
+            RecordFormat::Y4m => {
+                let (width, height) = (framebuffer.get_width(), framebuffer.get_height());
+                if self.video.is_none() {
+                    if let Some(parent) = self.path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    let mut writer = BufWriter::new(File::create(&self.path)?);
+                    writeln!(
+                        writer,
+                        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                        width, height, self.fps
+                    )?;
+                    self.video = Some((writer, width, height));
+                }
+                let (writer, video_width, video_height) = self.video.as_mut().unwrap();
+                if (*video_width, *video_height) != (width, height) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "The frame size changed during the recording",
+                    ));
+                }
+                write_y4m_frame(writer, framebuffer)?;
+            }
+        }
+        self.recorded += 1;
+        Ok(())
+    }
+
+    /// Stops the recording, flushing the video
+    pub fn finish(self) -> io::Result<()> {
+        if let Some((mut writer, _, _)) = self.video {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod capture_tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("raycaster-capture-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn frame(color: Pixel) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        framebuffer.clear(color);
+        framebuffer
+    }
+
+    #[test]
+    fn png_sequence_every_nth_frame() -> io::Result<()> {
+        let directory = temp_dir("png");
+        let mut recorder = Recorder::new(RecordFormat::Png, &directory, 2, 30);
+        for _ in 0..5 {
+            recorder.capture(&frame([10, 20, 30, 255]))?;
+        }
+        assert_eq!(recorder.get_recorded(), 3);
+        recorder.finish()?;
+
+        let image = image::open(directory.join("frame-000002.png"))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!((image.width(), image.height()), (4, 3));
+        assert_eq!(image.get_pixel(1, 1).0, [10, 20, 30, 255]);
+        assert!(!directory.join("frame-000003.png").exists());
+        std::fs::remove_dir_all(&directory)
+    }
+
+    #[test]
+    fn y4m_video() -> io::Result<()> {
+        let directory = temp_dir("y4m");
+        let path = directory.join("video.y4m");
+        let mut recorder = Recorder::new(RecordFormat::Y4m, &path, 1, 30);
+        recorder.capture(&frame([255, 255, 255, 255]))?;
+        recorder.capture(&frame([0, 0, 0, 255]))?;
+        assert!(recorder.capture(&Framebuffer::new(2, 2)).is_err());
+        recorder.finish()?;
+
+        let data = std::fs::read(&path)?;
+        let header = b"YUV4MPEG2 W4 H3 F30:1 Ip A1:1 C444\n";
+        assert!(data.starts_with(header));
+        let frame_size = b"FRAME\n".len() + 4 * 3 * 3;
+        assert_eq!(data.len(), header.len() + 2 * frame_size);
+        // white then black luma
+        assert_eq!(data[header.len() + 6], 235);
+        assert_eq!(data[header.len() + frame_size + 6], 16);
+        std::fs::remove_dir_all(&directory)
+    }
+}
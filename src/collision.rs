@@ -0,0 +1,123 @@
+use crate::map::Map;
+use crate::vector::Vector;
+
+/// Moves a circle from `pos` toward `target`, sliding along the walls.
+/// Each axis is checked on its own with the edge of the circle in the
+/// direction of the move, so a blocked axis doesn't stop the other one.
+/// Long moves are split in steps no longer than the radius (and half a
+/// tile), so the circle can't go through a thin wall.
+/// Stops where a position would be outside of the map
+pub fn slide(map: &Map, pos: Vector<f32>, target: Vector<f32>, radius: f32) -> Vector<f32> {
+    let tile_size = map.get_tile_size();
+    let max_step = radius.min(tile_size * 0.5).max(tile_size * 0.05);
+    let offset = target - pos;
+    let length = (offset.x * offset.x + offset.y * offset.y).sqrt();
+    let steps = (length / max_step).ceil().max(1.0) as usize;
+    let step = offset / steps as f32;
+    let along = |i: usize| {
+        if i == steps {
+            target
+        } else {
+            pos + offset * (i as f32 / steps as f32)
+        }
+    };
+    let mut result = pos;
+    for i in 1..=steps {
+        // the axes never blocked stay on the line, without rounding errors
+        let (previous, next) = (along(i - 1), along(i));
+        let axis = |result: f32, previous: f32, next: f32, step: f32| {
+            if result == previous {
+                next
+            } else {
+                result + step
+            }
+        };
+        let goal = Vector::new(
+            axis(result.x, previous.x, next.x, step.x),
+            axis(result.y, previous.y, next.y, step.y),
+        );
+        let next = slide_step(map, result, goal, radius);
+        if next == result {
+            break;
+        }
+        result = next;
+    }
+    result
+}
+
+/// One step of [slide], the edge of the circle doesn't go further than the next tile
+fn slide_step(map: &Map, pos: Vector<f32>, target: Vector<f32>, radius: f32) -> Vector<f32> {
+    let edge = |from: f32, to: f32| if to > from { to + radius } else { to - radius };
+    let map_pos = map.to_map_coordinates(&pos);
+    let new_map_pos =
+        map.to_map_coordinates(&Vector::new(edge(pos.x, target.x), edge(pos.y, target.y)));
+    let (Some(map_pos), Some(new_map_pos)) = (map_pos, new_map_pos) else {
+        return pos;
+    };
+
+    let mut result = pos;
+    if !map.is_wall(new_map_pos.x, map_pos.y) {
+        result.x = target.x;
+    }
+    if !map.is_wall(map_pos.x, new_map_pos.y) {
+        result.y = target.y;
+    }
+    result
+}
+
+/// True if the two circles overlap
+pub fn circles_overlap(a: Vector<f32>, radius_a: f32, b: Vector<f32>, radius_b: f32) -> bool {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let radius = radius_a + radius_b;
+    dx * dx + dy * dy < radius * radius
+}
+
+#[cfg(test)]
+mod collision_tests {
+    use super::*;
+
+    fn corridor() -> Map {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1,
+            1, 0, 0, 1,
+            1, 1, 1, 1,
+        ];
+        Map::new("corridor".to_string(), 4, 3, 10.0, tiles)
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let map = corridor();
+        // free move
+        let pos = slide(&map, Vector::new(15.0, 15.0), Vector::new(20.0, 15.0), 2.0);
+        assert_eq!(pos, Vector::new(20.0, 15.0));
+        // stops at the last step before the edge of the circle enters the end wall
+        let pos = slide(&map, Vector::new(25.0, 15.0), Vector::new(29.0, 15.0), 2.0);
+        assert_eq!(pos, Vector::new(27.0, 15.0));
+        // diagonal move into the top wall goes up to it and keeps the horizontal part
+        let pos = slide(&map, Vector::new(15.0, 15.0), Vector::new(18.0, 9.0), 2.0);
+        assert_eq!(pos, Vector::new(18.0, 12.0));
+    }
+
+    #[test]
+    fn long_moves_stop_at_thin_walls() {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1, 1,
+            1, 0, 0, 1, 0, 1,
+            1, 1, 1, 1, 1, 1,
+        ];
+        let map = Map::new("thin wall".to_string(), 6, 3, 10.0, tiles);
+        // the end of the move is in the free tile behind the wall
+        let pos = slide(&map, Vector::new(15.0, 15.0), Vector::new(45.0, 15.0), 2.0);
+        assert_eq!(pos, Vector::new(27.0, 15.0));
+    }
+
+    #[test]
+    fn overlap() {
+        let a = Vector::new(0.0, 0.0);
+        assert!(circles_overlap(a, 2.0, Vector::new(3.0, 0.0), 2.0));
+        assert!(!circles_overlap(a, 2.0, Vector::new(4.0, 0.0), 2.0));
+    }
+}
@@ -0,0 +1,131 @@
+use crate::vector::Vector;
+
+/// A tile entered by a [Dda] traversal
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Crossing {
+    /// Can be outside of the map, the traversal doesn't know its size
+    pub tile: Vector<i32>,
+    /// Ray parameter where the tile is entered: the entry point is
+    /// `origin + direction * distance`, 0 for the first tile
+    pub distance: f32,
+    /// True if the tile was entered through a horizontal grid line
+    /// (the ray moved along y), false for a vertical one or the first tile
+    pub side: bool,
+}
+
+/// Digital differential analyzer: visits every tile crossed by a ray,
+/// in order, starting with the tile of the origin. Positions are in tiles.
+/// The iterator never ends, callers stop when they leave the map, hit
+/// something or go past the distance they care about
+pub struct Dda {
+    tile: Vector<i32>,
+    step: Vector<i32>,
+    /// Ray parameter increase between two vertical (x) or horizontal (y) grid lines
+    step_size: Vector<f32>,
+    /// Ray parameter of the next vertical (x) and horizontal (y) grid lines
+    next: Vector<f32>,
+    started: bool,
+}
+
+impl Dda {
+    /// The direction doesn't need to be normalized, distances are
+    /// in multiples of its length
+    pub fn new(origin: Vector<f32>, direction: Vector<f32>) -> Self {
+        let tile = Vector::new(origin.x.floor() as i32, origin.y.floor() as i32);
+        // a ray parallel to an axis never reaches the grid lines of that axis
+        let axis = |position: f32, tile: i32, direction: f32| {
+            if direction == 0.0 {
+                (0, f32::INFINITY, f32::INFINITY)
+            } else if direction < 0.0 {
+                (-1, -1.0 / direction, (position - tile as f32) / -direction)
+            } else {
+                (
+                    1,
+                    1.0 / direction,
+                    (tile as f32 + 1.0 - position) / direction,
+                )
+            }
+        };
+        let (step_x, size_x, next_x) = axis(origin.x, tile.x, direction.x);
+        let (step_y, size_y, next_y) = axis(origin.y, tile.y, direction.y);
+        Self {
+            tile,
+            step: Vector::new(step_x, step_y),
+            step_size: Vector::new(size_x, size_y),
+            next: Vector::new(next_x, next_y),
+            started: false,
+        }
+    }
+}
+
+impl Iterator for Dda {
+    type Item = Crossing;
+
+    fn next(&mut self) -> Option<Crossing> {
+        if !self.started {
+            self.started = true;
+            return Some(Crossing {
+                tile: self.tile,
+                distance: 0.0,
+                side: false,
+            });
+        }
+        let (distance, side) = if self.next.x < self.next.y {
+            let distance = self.next.x;
+            self.tile.x += self.step.x;
+            self.next.x += self.step_size.x;
+            (distance, false)
+        } else {
+            if self.step.y == 0 {
+                // null direction, the ray stays in its tile
+                return None;
+            }
+            let distance = self.next.y;
+            self.tile.y += self.step.y;
+            self.next.y += self.step_size.y;
+            (distance, true)
+        };
+        Some(Crossing {
+            tile: self.tile,
+            distance,
+            side,
+        })
+    }
+}
+
+#[cfg(test)]
+mod dda_tests {
+    use super::*;
+
+    fn tiles(origin: (f32, f32), direction: (f32, f32), count: usize) -> Vec<(i32, i32)> {
+        Dda::new(origin.into(), direction.into())
+            .take(count)
+            .map(|crossing| (crossing.tile.x, crossing.tile.y))
+            .collect()
+    }
+
+    #[test]
+    fn axis_aligned() {
+        assert_eq!(tiles((0.5, 0.5), (1.0, 0.0), 3), [(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(
+            tiles((0.5, 0.5), (0.0, -2.0), 3),
+            [(0, 0), (0, -1), (0, -2)]
+        );
+        // on a grid line
+        assert_eq!(tiles((1.0, 1.0), (1.0, 0.0), 2), [(1, 1), (2, 1)]);
+        assert_eq!(tiles((1.0, 1.0), (0.0, 0.0), 3), [(1, 1)]);
+    }
+
+    #[test]
+    fn distances_and_sides() {
+        let crossings: Vec<_> = Dda::new(Vector::new(0.5, 0.25), Vector::new(2.0, 1.0))
+            .take(3)
+            .collect();
+        assert_eq!(crossings[1].tile, Vector::new(1, 0));
+        assert_eq!(crossings[1].distance, 0.25);
+        assert!(!crossings[1].side);
+        assert_eq!(crossings[2].tile, Vector::new(1, 1));
+        assert_eq!(crossings[2].distance, 0.75);
+        assert!(crossings[2].side);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::camera::RayCastResult;
+use crate::entity::Entities;
+use crate::map::Map;
+use crate::player::Player;
+use crate::render::{Render, TopDownView};
+use macroquad::prelude::{
+    draw_circle, draw_line, draw_rectangle, Color, Rect, GREEN, ORANGE, SKYBLUE, YELLOW,
+};
+
+/// How the top-down debug view is displayed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugViewMode {
+    Off,
+    /// Drawn over the 3D view with transparency
+    Overlay,
+    /// 3D view on the left half, top-down view on the right half
+    Split,
+}
+
+impl DebugViewMode {
+    pub fn next(self) -> Self {
+        match self {
+            DebugViewMode::Off => DebugViewMode::Overlay,
+            DebugViewMode::Overlay => DebugViewMode::Split,
+            DebugViewMode::Split => DebugViewMode::Off,
+        }
+    }
+}
+
+/// Length of the field of view lines, in tiles
+const FOV_LENGTH: f32 = 3.0;
+
+/// Draws the map, the entities and the player in a top-down view with every ray
+/// cast by the player camera (one per column of a `columns` wide view).
+/// Tiles crossed by the rays are highlighted and the hit points are marked
+pub fn draw_debug_view(
+    map: &Map,
+    entities: &Entities,
+    player: &Player,
+    columns: usize,
+    viewport: Rect,
+    opacity: f32,
+) {
+    let mut view = TopDownView::fit(map, viewport);
+    view.opacity = opacity;
+    map.render(&view);
+
+    let camera = player.get_camera();
+    let origin = view.project(camera.get_position());
+    let tile_size = map.get_tile_size() * view.scale;
+    let mut visited = vec![false; map.get_width() * map.get_height()];
+    let mut hits = Vec::with_capacity(columns);
+
+    for column in 0..columns {
+        let direction = camera.ray_direction(column as f32, columns as f32);
+        let ray = camera.raycast_traced(map, direction, |tile| {
+            visited[tile.y * map.get_width() + tile.x] = true;
+        });
+        if let RayCastResult::Hit(distance, _, _) = ray {
+            hits.push(view.project(camera.hit_point(map, direction, distance)));
+        }
+    }
+
+    for (i, _) in visited.iter().enumerate().filter(|(_, visited)| **visited) {
+        let corner = view.project(map.tile_corner(i % map.get_width(), i / map.get_width()));
+        let color = view.color(Color::new(SKYBLUE.r, SKYBLUE.g, SKYBLUE.b, 0.4));
+        draw_rectangle(corner.x, corner.y, tile_size, tile_size, color);
+    }
+
+    let ray_color = view.color(Color::new(YELLOW.r, YELLOW.g, YELLOW.b, 0.3));
+    for hit in &hits {
+        draw_line(origin.x, origin.y, hit.x, hit.y, 1.0, ray_color);
+    }
+    for hit in &hits {
+        draw_circle(hit.x, hit.y, 2.0, view.color(ORANGE));
+    }
+
+    for column in [0.0, columns as f32] {
+        let direction = camera.ray_direction(column, columns as f32);
+        let end = origin + direction * (FOV_LENGTH * tile_size);
+        draw_line(origin.x, origin.y, end.x, end.y, 2.0, view.color(GREEN));
+    }
+
+    entities.render(&view);
+    player.render(&view);
+}
@@ -0,0 +1,860 @@
+use crate::entity::{Entities, Entity, EntityEvent, SpriteDef, Think, ThinkContext};
+use crate::framebuffer::Pixel;
+use crate::inventory::{Pickup, PickupThink};
+use crate::map::{Map, Placement};
+use crate::pathfinding::Tile;
+use crate::textures::TextureManager;
+use crate::vector::Vector;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Distance to a waypoint under which it is reached, in tiles
+const ARRIVE_DISTANCE: f32 = 0.2;
+/// Turn speed while looking around at the end of a search, in radians per second
+const SEARCH_TURN_SPEED: f32 = 1.5;
+/// Attacks go on a bit past the attack range, so the enemy doesn't switch
+/// between chasing and attacking when the player is right at the limit
+const ATTACK_HYSTERESIS: f32 = 1.2;
+
+/// Properties which can be given to every entity kind
+const SPRITE_PROPERTIES: [&str; 7] = [
+    "radius",
+    "size",
+    "elevation",
+    "color",
+    "texture",
+    "solid",
+    "health",
+];
+/// Properties of the kinds with `ai = enemy`
+const ENEMY_PROPERTIES: [&str; 10] = [
+    "ai",
+    "speed",
+    "sight",
+    "fov",
+    "hearing",
+    "attack_range",
+    "attack_damage",
+    "attack_delay",
+    "memory",
+    "search_time",
+];
+
+/// Parameters of an enemy kind, read from the `entity.<kind>.<property>`
+/// fields of the map. Distances are in tiles, durations in seconds
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EnemyParams {
+    /// World units per second
+    pub speed: f32,
+    /// How far the enemy can see
+    pub sight: f32,
+    /// Width of the view cone, in radians (given in degrees in the map)
+    pub fov: f32,
+    /// How far along the open tiles a noise can be heard
+    pub hearing: f32,
+    pub attack_range: f32,
+    pub attack_damage: i32,
+    pub attack_delay: f32,
+    /// How long the enemy keeps chasing a player it doesn't see anymore
+    pub memory: f32,
+    /// How long the enemy looks around where it last saw or heard something
+    pub search_time: f32,
+}
+
+impl Default for EnemyParams {
+    fn default() -> Self {
+        Self {
+            speed: 80.0,
+            sight: 8.0,
+            fov: 100f32.to_radians(),
+            hearing: 12.0,
+            attack_range: 1.5,
+            attack_damage: 10,
+            attack_delay: 1.0,
+            memory: 4.0,
+            search_time: 3.0,
+        }
+    }
+}
+
+/// Reads the value of a property, or returns the default one when it is missing
+fn property<T: FromStr>(
+    properties: &HashMap<String, String>,
+    name: &str,
+    default: T,
+) -> Result<T, String> {
+    match properties.get(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("invalid {} \"{}\"", name, value)),
+        None => Ok(default),
+    }
+}
+
+/// Same as [property], for the values which can't be negative
+fn non_negative<T: FromStr + PartialOrd + Default>(
+    properties: &HashMap<String, String>,
+    name: &str,
+    default: T,
+) -> Result<T, String> {
+    let value = property(properties, name, default)?;
+    // NaN isn't greater or equal either
+    if value >= T::default() {
+        Ok(value)
+    } else {
+        Err(format!("invalid {} \"{}\"", name, properties[name]))
+    }
+}
+
+fn parse_color(value: &str) -> Result<Pixel, String> {
+    let channels: Vec<_> = value
+        .split(',')
+        .map(|channel| channel.trim().parse::<u8>())
+        .collect();
+    match channels[..] {
+        [Ok(r), Ok(g), Ok(b)] => Ok([r, g, b, 255]),
+        _ => Err(format!("invalid color \"{}\", expected r,g,b", value)),
+    }
+}
+
+impl EnemyParams {
+    pub fn parse(properties: &HashMap<String, String>) -> Result<Self, String> {
+        let default = Self::default();
+        Ok(Self {
+            speed: non_negative(properties, "speed", default.speed)?,
+            sight: non_negative(properties, "sight", default.sight)?,
+            fov: non_negative(properties, "fov", default.fov.to_degrees())?.to_radians(),
+            hearing: non_negative(properties, "hearing", default.hearing)?,
+            attack_range: non_negative(properties, "attack_range", default.attack_range)?,
+            attack_damage: non_negative(properties, "attack_damage", default.attack_damage)?,
+            attack_delay: non_negative(properties, "attack_delay", default.attack_delay)?,
+            memory: non_negative(properties, "memory", default.memory)?,
+            search_time: non_negative(properties, "search_time", default.search_time)?,
+        })
+    }
+}
+
+/// What an enemy is doing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EnemyState {
+    /// Stands still until it sees or hears something
+    Idle,
+    /// Walks along its waypoints
+    Patrol,
+    /// Runs after the player
+    Chase,
+    /// Stands and hits the player when the attack is ready
+    Attack,
+    /// Goes where the player was last seen or a noise was heard, then looks around
+    Search,
+    Dead,
+}
+
+impl EnemyState {
+    pub const ALL: [EnemyState; 6] = [
+        EnemyState::Idle,
+        EnemyState::Patrol,
+        EnemyState::Chase,
+        EnemyState::Attack,
+        EnemyState::Search,
+        EnemyState::Dead,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EnemyState::Idle => "idle",
+            EnemyState::Patrol => "patrol",
+            EnemyState::Chase => "chase",
+            EnemyState::Attack => "attack",
+            EnemyState::Search => "search",
+            EnemyState::Dead => "dead",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EnemyState> {
+        EnemyState::ALL
+            .into_iter()
+            .find(|state| state.name() == name)
+    }
+}
+
+/// State machine driving an enemy entity
+pub struct Enemy {
+    params: EnemyParams,
+    state: EnemyState,
+    /// World positions of the patrol waypoints
+    waypoints: Vec<Vector<f32>>,
+    next_waypoint: usize,
+    /// World positions to go through to reach the current goal
+    path: Vec<Vector<f32>>,
+    /// Last known position of the player, or position of the noise heard
+    target: Vector<f32>,
+    /// Time since the player was last seen while chasing,
+    /// time spent looking around while searching
+    timer: f32,
+    /// Time before the next attack can be made
+    cooldown: f32,
+    last_health: Option<i32>,
+}
+
+fn tile_of(map: &Map, pos: Vector<f32>) -> Option<Tile> {
+    map.to_map_coordinates(&pos)
+        .map(|coordinates| (coordinates.x, coordinates.y))
+}
+
+fn tile_center(map: &Map, (x, y): Tile) -> Vector<f32> {
+    map.tile_corner(x, y) + Vector::new(0.5, 0.5) * map.get_tile_size()
+}
+
+fn length(vector: Vector<f32>) -> f32 {
+    (vector.x * vector.x + vector.y * vector.y).sqrt()
+}
+
+/// Difference between two angles, between -π and π
+fn angle_difference(a: f32, b: f32) -> f32 {
+    (a - b + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI
+}
+
+impl Enemy {
+    pub fn new(params: EnemyParams, waypoints: Vec<Vector<f32>>) -> Self {
+        let state = if waypoints.is_empty() {
+            EnemyState::Idle
+        } else {
+            EnemyState::Patrol
+        };
+        Self {
+            params,
+            state,
+            waypoints,
+            next_waypoint: 0,
+            path: Vec::new(),
+            target: Vector::new(0.0, 0.0),
+            timer: 0.0,
+            cooldown: 0.0,
+            last_health: None,
+        }
+    }
+
+    pub fn get_state(&self) -> EnemyState {
+        self.state
+    }
+
+    fn set_state(&mut self, state: EnemyState) {
+        self.state = state;
+        self.timer = 0.0;
+        self.path.clear();
+    }
+
+    /// The player is close enough, in the view cone and not behind a wall
+    fn sees_player(&self, entity: &Entity, context: &ThinkContext) -> bool {
+        let offset = context.player - entity.pos;
+        let distance = length(offset);
+        let tile_size = context.map.get_tile_size();
+        if distance > self.params.sight * tile_size {
+            return false;
+        }
+        let angle = offset.y.atan2(offset.x);
+        // something right next to the enemy is noticed even from behind
+        let in_cone = angle_difference(angle, entity.angle).abs() <= self.params.fov / 2.0
+            || distance < entity.radius * 2.0;
+        in_cone && context.map.has_line_of_sight(entity.pos, context.player)
+    }
+
+    /// Position of the closest noise the enemy can hear
+    fn heard_noise(&self, entity: &Entity, context: &ThinkContext) -> Option<Vector<f32>> {
+        let (x, y) = tile_of(context.map, entity.pos)?;
+        context
+            .noises
+            .iter()
+            .filter_map(|noise| Some((noise, noise.get_distance(x, y)?)))
+            .filter(|(_, distance)| *distance as f32 <= self.params.hearing)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(noise, _)| noise.origin)
+    }
+
+    /// Plans a path toward `goal`, smoothed and without the current tile
+    fn plan_path(&mut self, entity: &Entity, context: &ThinkContext, goal: Vector<f32>) {
+        self.path.clear();
+        let map = context.map;
+        let (Some(start), Some(end)) = (tile_of(map, entity.pos), tile_of(map, goal)) else {
+            return;
+        };
+        if let Some(path) = context.nav.find_path(start, end) {
+            let path = context.nav.smooth_path(&path);
+            self.path = path
+                .into_iter()
+                .skip(1)
+                .map(|tile| tile_center(map, tile))
+                .collect();
+            if let Some(last) = self.path.last_mut() {
+                *last = goal;
+            }
+        }
+    }
+
+    /// Walks toward the next point of the path, returns true once the path is done
+    fn follow_path(&mut self, entity: &mut Entity, context: &ThinkContext) -> bool {
+        let arrive = ARRIVE_DISTANCE * context.map.get_tile_size();
+        while let Some(next) = self.path.first() {
+            if length(*next - entity.pos) > arrive {
+                let next = *next;
+                self.walk_toward(entity, next);
+                return false;
+            }
+            self.path.remove(0);
+        }
+        entity.velocity = Vector::new(0.0, 0.0);
+        true
+    }
+
+    fn walk_toward(&self, entity: &mut Entity, target: Vector<f32>) {
+        let offset = target - entity.pos;
+        let distance = length(offset);
+        if distance > 0.0 {
+            entity.velocity = offset * (self.params.speed / distance);
+            entity.angle = offset.y.atan2(offset.x);
+        }
+    }
+
+    fn face(&self, entity: &mut Entity, target: Vector<f32>) {
+        let offset = target - entity.pos;
+        entity.angle = offset.y.atan2(offset.x);
+    }
+
+    /// Starts the search of a position, walking to it first
+    fn search(&mut self, entity: &Entity, context: &ThinkContext, target: Vector<f32>) {
+        self.set_state(EnemyState::Search);
+        self.target = target;
+        self.plan_path(entity, context, target);
+    }
+
+    fn die(&mut self, entity: &mut Entity) {
+        self.set_state(EnemyState::Dead);
+        entity.velocity = Vector::new(0.0, 0.0);
+        entity.solid = false;
+        if let Some(sprite) = &mut entity.sprite {
+            // a flattened and darker body
+            sprite.size *= 0.6;
+            sprite.elevation = 0.0;
+            sprite.color = sprite.color.map(|channel| channel / 2);
+            sprite.color[3] = 255;
+        }
+    }
+}
+
+impl Think for Enemy {
+    fn think(&mut self, entity: &mut Entity, context: &mut ThinkContext) {
+        if self.state == EnemyState::Dead {
+            return;
+        }
+        if entity.is_dead() {
+            self.die(entity);
+            return;
+        }
+        let hurt = self.last_health.is_some() && entity.health < self.last_health;
+        self.last_health = entity.health;
+        self.cooldown = (self.cooldown - context.delta).max(0.0);
+
+        let sees = self.sees_player(entity, context);
+        if sees || hurt {
+            self.target = context.player;
+            if !matches!(self.state, EnemyState::Chase | EnemyState::Attack) {
+                self.set_state(EnemyState::Chase);
+            }
+        } else if matches!(
+            self.state,
+            EnemyState::Idle | EnemyState::Patrol | EnemyState::Search
+        ) {
+            if let Some(noise) = self.heard_noise(entity, context) {
+                if self.state != EnemyState::Search || noise != self.target {
+                    self.search(entity, context, noise);
+                }
+            }
+        }
+
+        let tile_size = context.map.get_tile_size();
+        let distance = length(context.player - entity.pos);
+        match self.state {
+            EnemyState::Idle | EnemyState::Dead => entity.velocity = Vector::new(0.0, 0.0),
+            EnemyState::Patrol => {
+                if self.path.is_empty() {
+                    let waypoint = self.waypoints[self.next_waypoint];
+                    self.plan_path(entity, context, waypoint);
+                }
+                if self.follow_path(entity, context) {
+                    self.next_waypoint = (self.next_waypoint + 1) % self.waypoints.len();
+                }
+            }
+            EnemyState::Chase => {
+                if sees && distance <= self.params.attack_range * tile_size {
+                    self.set_state(EnemyState::Attack);
+                    entity.velocity = Vector::new(0.0, 0.0);
+                } else if sees {
+                    self.timer = 0.0;
+                    self.walk_toward(entity, context.player);
+                } else {
+                    self.timer += context.delta;
+                    if self.timer > self.params.memory {
+                        let target = self.target;
+                        self.search(entity, context, target);
+                        return;
+                    }
+                    // around the walls, following the field shared with the others
+                    let next = tile_of(context.map, entity.pos)
+                        .and_then(|tile| context.player_field.next_step(context.nav, tile));
+                    match next {
+                        Some(next) => self.walk_toward(entity, tile_center(context.map, next)),
+                        None => entity.velocity = Vector::new(0.0, 0.0),
+                    }
+                }
+            }
+            EnemyState::Attack => {
+                entity.velocity = Vector::new(0.0, 0.0);
+                self.face(entity, context.player);
+                let range = self.params.attack_range * ATTACK_HYSTERESIS * tile_size;
+                if !sees || distance > range {
+                    self.set_state(EnemyState::Chase);
+                } else if self.cooldown <= 0.0 {
+                    self.cooldown = self.params.attack_delay;
+                    context.events.push(EntityEvent::AttackPlayer {
+                        source: entity.get_id(),
+                        damage: self.params.attack_damage,
+                    });
+                }
+            }
+            EnemyState::Search => {
+                // the path isn't saved, it is planned again after a load
+                if self.path.is_empty() && self.timer == 0.0 {
+                    let target = self.target;
+                    self.plan_path(entity, context, target);
+                }
+                if self.follow_path(entity, context) {
+                    self.timer += context.delta;
+                    entity.angle += SEARCH_TURN_SPEED * context.delta;
+                    if self.timer > self.params.search_time {
+                        let state = if self.waypoints.is_empty() {
+                            EnemyState::Idle
+                        } else {
+                            EnemyState::Patrol
+                        };
+                        self.set_state(state);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `<state>,<target x>,<target y>,<timer>,<cooldown>,<next waypoint>,<health>`,
+    /// the path is planned again
+    fn save_state(&self) -> Option<String> {
+        let health = self.last_health.map_or("-".to_string(), |h| h.to_string());
+        Some(format!(
+            "{},{},{},{},{},{},{}",
+            self.state.name(),
+            self.target.x,
+            self.target.y,
+            self.timer,
+            self.cooldown,
+            self.next_waypoint,
+            health
+        ))
+    }
+
+    fn restore_state(&mut self, state: &str) -> Result<(), String> {
+        let invalid = || format!("invalid enemy state \"{}\"", state);
+        let values: Vec<&str> = state.split(',').collect();
+        let [name, x, y, timer, cooldown, next_waypoint, health] = values[..] else {
+            return Err(invalid());
+        };
+        let number = |value: &str| value.parse::<f32>().map_err(|_| invalid());
+        let next_waypoint: usize = next_waypoint.parse().map_err(|_| invalid())?;
+        if next_waypoint >= self.waypoints.len().max(1) {
+            return Err(invalid());
+        }
+        let state = EnemyState::from_name(name).ok_or_else(invalid)?;
+        if state == EnemyState::Patrol && self.waypoints.is_empty() {
+            return Err(invalid());
+        }
+        self.set_state(state);
+        self.target = Vector::new(number(x)?, number(y)?);
+        self.timer = number(timer)?;
+        self.cooldown = number(cooldown)?;
+        self.next_waypoint = next_waypoint;
+        self.last_health = match health {
+            "-" => None,
+            health => Some(health.parse().map_err(|_| invalid())?),
+        };
+        Ok(())
+    }
+}
+
+/// Creates the entity of a placement: its sprite and health come from the
+/// properties of its kind, the kinds with `ai = enemy` get an [Enemy] behaviour
+/// and the ones with a `pickup` are collected by the player
+pub fn create_entity(
+    map: &Map,
+    textures: &TextureManager,
+    placement: &Placement,
+) -> Result<Entity, String> {
+    let properties = map
+        .get_definitions()
+        .get(&placement.kind)
+        .ok_or_else(|| format!("unknown entity kind \"{}\"", placement.kind))?;
+    let enemy = match properties.get("ai").map(String::as_str) {
+        Some("enemy") => true,
+        None => false,
+        Some(ai) => return Err(format!("unknown ai \"{}\"", ai)),
+    };
+    for name in properties.keys() {
+        let known = SPRITE_PROPERTIES.contains(&name.as_str())
+            || (enemy && ENEMY_PROPERTIES.contains(&name.as_str()))
+            || (!enemy && name == "pickup");
+        if !known {
+            return Err(format!("unknown property \"{}\"", name));
+        }
+    }
+
+    let texture = match properties.get("texture") {
+        Some(name) => Some(
+            textures
+                .get_id(name)
+                .ok_or_else(|| format!("missing texture \"{}\"", name))?,
+        ),
+        None => None,
+    };
+    let color = match properties.get("color") {
+        Some(color) => parse_color(color)?,
+        None => [200, 60, 60, 255],
+    };
+    let pos = tile_center(map, (placement.x, placement.y));
+    let mut entity = Entity::new(pos, property(properties, "radius", 12.0)?);
+    entity.angle = placement.angle;
+    let pickup = match properties.get("pickup") {
+        Some(pickup) => Some(Pickup::parse(pickup)?),
+        None => None,
+    };
+    entity.solid = property(properties, "solid", pickup.is_none())?;
+    entity.sprite = Some(SpriteDef {
+        texture,
+        color,
+        size: property(properties, "size", 0.8)?,
+        elevation: property(properties, "elevation", 0.0)?,
+    });
+    if properties.contains_key("health") || enemy {
+        entity.health = Some(property(properties, "health", 30)?);
+    }
+    if enemy {
+        let waypoints = placement
+            .waypoints
+            .iter()
+            .map(|tile| tile_center(map, *tile))
+            .collect();
+        entity.set_think(Enemy::new(EnemyParams::parse(properties)?, waypoints));
+    }
+    if let Some(pickup) = pickup {
+        entity.set_think(PickupThink(pickup));
+    }
+    entity.placement = Some(placement.clone());
+    Ok(entity)
+}
+
+/// Spawns every entity placed in the map, returns the errors of the
+/// placements which couldn't be spawned
+pub fn spawn_map_entities(
+    map: &Map,
+    textures: &TextureManager,
+    entities: &mut Entities,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for placement in map.get_placements() {
+        match create_entity(map, textures, placement) {
+            Ok(entity) => {
+                entities.spawn(entity);
+            }
+            Err(e) => errors.push(format!("Entity {}: {}", placement.kind, e)),
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod enemy_tests {
+    use super::*;
+    use crate::pathfinding::{Connectivity, FlowField, NavGrid, Noise};
+
+    /// Two rooms joined by a door in the middle wall
+    fn map() -> Map {
+        let text = "name = rooms
+size = 9x5
+entity.guard.ai = enemy
+entity.guard.speed = 64
+entity.guard.attack_range = 1
+entity.guard.attack_delay = 0.5
+entity.guard.attack_damage = 7
+entity.guard.memory = 0.5
+entity.lamp.color = 255,255,0
+entity.medkit.pickup = health 25
+---
+1 1 1 1 1 1 1 1 1
+1 0 0 0 1 0 0 0 1
+1 0 0 0 0 0 0 0 1
+1 0 0 0 1 0 0 0 1
+1 1 1 1 1 1 1 1 1
+---
+guard 6 2 0
+lamp 1 1
+medkit 2 3
+";
+        Map::parse(text.as_bytes(), 64.0).unwrap()
+    }
+
+    fn map_definitions_with(
+        kind: &str,
+        property: &str,
+        value: &str,
+    ) -> crate::map::EntityDefinitions {
+        let mut definitions = map().get_definitions().clone();
+        let properties = definitions.get_mut(kind).unwrap();
+        properties.insert(property.to_string(), value.to_string());
+        definitions
+    }
+
+    struct World {
+        map: Map,
+        nav: NavGrid,
+        field: FlowField,
+        entity: Entity,
+        enemy: Enemy,
+    }
+
+    impl World {
+        fn new(waypoints: Vec<Vector<f32>>) -> Self {
+            let map = map();
+            let nav = NavGrid::new(&map, Connectivity::Eight);
+            let placement = &map.get_placements()[0];
+            let entity = create_entity(&map, &TextureManager::new(), placement).unwrap();
+            let params = EnemyParams::parse(&map.get_definitions()["guard"]).unwrap();
+            Self {
+                map,
+                nav,
+                field: FlowField::new(),
+                entity,
+                enemy: Enemy::new(params, waypoints),
+            }
+        }
+
+        /// Runs the enemy for a while and returns its events
+        fn run(&mut self, player: Vector<f32>, noises: &[Noise], ticks: usize) -> Vec<EntityEvent> {
+            let mut events = Vec::new();
+            for _ in 0..ticks {
+                let player_tile = tile_of(&self.map, player).unwrap();
+                self.field.update(&self.nav, player_tile);
+                let mut context =
+                    ThinkContext::new(&self.map, &self.nav, &self.field, player, 1.0 / 60.0);
+                context.noises = noises;
+                self.enemy.think(&mut self.entity, &mut context);
+                let target = self.entity.pos + self.entity.velocity * context.delta;
+                self.entity.pos =
+                    crate::collision::slide(&self.map, self.entity.pos, target, self.entity.radius);
+                events.append(&mut context.events);
+            }
+            events
+        }
+    }
+
+    fn center(x: usize, y: usize) -> Vector<f32> {
+        Vector::new((x as f32 + 0.5) * 64.0, (y as f32 + 0.5) * 64.0)
+    }
+
+    #[test]
+    fn parameters_from_map() {
+        let map = map();
+        let params = EnemyParams::parse(&map.get_definitions()["guard"]).unwrap();
+        assert_eq!(params.speed, 64.0);
+        assert_eq!(params.attack_damage, 7);
+        assert_eq!(params.sight, EnemyParams::default().sight);
+
+        let mut entities = Entities::new(&map);
+        assert!(spawn_map_entities(&map, &TextureManager::new(), &mut entities).is_empty());
+        assert_eq!(entities.len(), 3);
+        let lamp = entities.iter().nth(1).unwrap();
+        assert_eq!(lamp.sprite.unwrap().color, [255, 255, 0, 255]);
+        assert_eq!(lamp.health, None);
+        assert!(lamp.solid && !entities.iter().nth(2).unwrap().solid);
+
+        let mut definitions = map.get_definitions().clone();
+        definitions
+            .get_mut("guard")
+            .unwrap()
+            .insert("speeed".to_string(), "1".to_string());
+        definitions
+            .get_mut("lamp")
+            .unwrap()
+            .insert("speed".to_string(), "1".to_string());
+        let placements = map.get_placements().to_vec();
+        let mut broken = map;
+        broken.set_entities(definitions, placements.clone());
+        let errors = spawn_map_entities(&broken, &TextureManager::new(), &mut entities);
+        assert_eq!(errors.len(), 2);
+        let mut definitions = map_definitions_with("medkit", "pickup", "health");
+        definitions
+            .get_mut("guard")
+            .unwrap()
+            .insert("pickup".to_string(), "key red".to_string());
+        broken.set_entities(definitions, placements.clone());
+        let errors = spawn_map_entities(&broken, &TextureManager::new(), &mut entities);
+        assert_eq!(errors.len(), 2);
+        for name in [
+            "speed",
+            "sight",
+            "attack_range",
+            "attack_damage",
+            "attack_delay",
+        ] {
+            let definitions = map_definitions_with("guard", name, "-1");
+            let error = EnemyParams::parse(&definitions["guard"]).unwrap_err();
+            assert_eq!(error, format!("invalid {} \"-1\"", name));
+        }
+        // the placements given to set_entities aren't checked
+        let mut unknown = placements;
+        unknown[0].kind = "dragon".to_string();
+        broken.set_entities(map_definitions_with("lamp", "size", "1"), unknown);
+        let errors = spawn_map_entities(&broken, &TextureManager::new(), &mut entities);
+        assert_eq!(errors, ["Entity dragon: unknown entity kind \"dragon\""]);
+    }
+
+    #[test]
+    fn test_map_entities() {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let mut entities = Entities::new(&map);
+        let errors = spawn_map_entities(&map, &TextureManager::new(), &mut entities);
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(entities.len(), map.get_placements().len());
+    }
+
+    #[test]
+    fn sees_in_view_cone_only() {
+        let mut world = World::new(Vec::new());
+        // behind the guard, which looks toward +x
+        world.run(center(5, 2), &[], 1);
+        assert_eq!(world.enemy.get_state(), EnemyState::Idle);
+        // in front of it, close enough to be attacked right away
+        world.run(center(7, 2), &[], 1);
+        assert_eq!(world.enemy.get_state(), EnemyState::Attack);
+    }
+
+    #[test]
+    fn walls_block_the_sight() {
+        let mut world = World::new(Vec::new());
+        world.entity.angle = std::f32::consts::PI;
+        world.run(center(2, 1), &[], 10);
+        assert_eq!(world.enemy.get_state(), EnemyState::Idle);
+        world.run(center(2, 2), &[], 1);
+        assert_eq!(world.enemy.get_state(), EnemyState::Chase);
+    }
+
+    #[test]
+    fn chases_and_attacks() {
+        let mut world = World::new(Vec::new());
+        world.entity.angle = std::f32::consts::PI;
+        // the player stands in the other room, around the corner
+        let events = world.run(center(2, 2), &[], 240);
+        assert_eq!(world.enemy.get_state(), EnemyState::Attack);
+        assert!(length(world.entity.pos - center(2, 2)) <= 64.0);
+        let expected = EntityEvent::AttackPlayer {
+            source: 0,
+            damage: 7,
+        };
+        assert!(events.len() >= 2);
+        assert!(events.iter().all(|event| *event == expected));
+    }
+
+    #[test]
+    fn loses_the_player_then_searches() {
+        let mut world = World::new(Vec::new());
+        world.entity.angle = std::f32::consts::PI;
+        // stays in place, so the player can't be seen again
+        world.enemy.params.speed = 0.0;
+        world.run(center(2, 2), &[], 1);
+        assert_eq!(world.enemy.get_state(), EnemyState::Chase);
+        // the player hides behind the middle wall
+        world.run(center(2, 1), &[], 20);
+        assert_eq!(world.enemy.get_state(), EnemyState::Chase);
+        world.run(center(2, 1), &[], 20);
+        assert_eq!(world.enemy.get_state(), EnemyState::Search);
+        assert_eq!(world.enemy.target, center(2, 2));
+    }
+
+    #[test]
+    fn patrols_waypoints() {
+        let mut world = World::new(vec![center(6, 1), center(6, 3)]);
+        assert_eq!(world.enemy.get_state(), EnemyState::Patrol);
+        // the player is far away in the other room, out of sight
+        let player = center(1, 1);
+        let mut visited = [false; 2];
+        for _ in 0..300 {
+            world.run(player, &[], 1);
+            visited[0] |= length(world.entity.pos - center(6, 1)) < 15.0;
+            visited[1] |= length(world.entity.pos - center(6, 3)) < 15.0;
+        }
+        assert_eq!(world.enemy.get_state(), EnemyState::Patrol);
+        assert_eq!(visited, [true, true]);
+    }
+
+    #[test]
+    fn hears_noises_through_open_tiles() {
+        let mut world = World::new(Vec::new());
+        // beside the guard, out of its view cone, then behind the middle wall
+        let player = center(6, 1);
+        // too far along the open tiles
+        let far = Noise::new(&world.nav, center(1, 3), (1, 3), 4);
+        world.run(player, &[far], 1);
+        assert_eq!(world.enemy.get_state(), EnemyState::Idle);
+
+        let near = Noise::new(&world.nav, center(1, 3), (1, 3), 10);
+        world.run(player, &[near], 1);
+        assert_eq!(world.enemy.get_state(), EnemyState::Search);
+        // walks to the noise
+        world.run(player, &[], 360);
+        assert!(length(world.entity.pos - center(1, 3)) < 64.0);
+    }
+
+    #[test]
+    fn saved_state() {
+        let mut world = World::new(Vec::new());
+        world.entity.angle = std::f32::consts::PI;
+        world.enemy.params.speed = 0.0;
+        world.run(center(2, 2), &[], 1);
+        world.run(center(2, 1), &[], 40);
+        assert_eq!(world.enemy.get_state(), EnemyState::Search);
+        let state = world.enemy.save_state().unwrap();
+
+        let mut loaded = World::new(Vec::new());
+        loaded.enemy.restore_state(&state).unwrap();
+        assert_eq!(loaded.enemy.get_state(), EnemyState::Search);
+        assert_eq!(loaded.enemy.target, center(2, 2));
+        assert_eq!(loaded.enemy.save_state(), Some(state));
+        // walks again to where the player was last seen
+        loaded.run(center(2, 1), &[], 1);
+        assert!(!loaded.enemy.path.is_empty());
+
+        for state in [
+            "",
+            "chase,1,2",
+            "fly,0,0,0,0,0,-",
+            "patrol,0,0,0,0,0,-",
+            "idle,0,0,0,0,3,-",
+        ] {
+            assert!(loaded.enemy.restore_state(state).is_err(), "{}", state);
+        }
+    }
+
+    #[test]
+    fn dies() {
+        let mut world = World::new(Vec::new());
+        world.entity.damage(100);
+        world.run(center(7, 2), &[], 1);
+        assert_eq!(world.enemy.get_state(), EnemyState::Dead);
+        assert!(!world.entity.solid);
+    }
+}
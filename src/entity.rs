@@ -0,0 +1,574 @@
+use crate::collision::{circles_overlap, slide};
+use crate::dda::Dda;
+use crate::framebuffer::Pixel;
+use crate::inventory::Pickup;
+use crate::map::{Map, Placement};
+use crate::pathfinding::{FlowField, NavGrid, Noise};
+use crate::render::{Render, TopDownView};
+use crate::renderer::Sprite;
+use crate::textures::TextureId;
+use crate::vector::Vector;
+use macroquad::prelude::{draw_circle, draw_circle_lines, Color, ORANGE};
+
+/// Identifies an entity for its whole life, ids are never reused
+pub type EntityId = u32;
+
+/// How an entity looks in the 3D view, see [Sprite]
+#[derive(Clone, Copy, Debug)]
+pub struct SpriteDef {
+    pub texture: Option<TextureId>,
+    pub color: Pixel,
+    /// In tiles
+    pub size: f32,
+    /// In tiles
+    pub elevation: f32,
+}
+
+/// Something an entity did that the game has to handle
+#[derive(Clone, PartialEq, Debug)]
+pub enum EntityEvent {
+    AttackPlayer {
+        source: EntityId,
+        damage: i32,
+    },
+    /// A projectile moves along the segment during this tick,
+    /// see [crate::weapon::move_projectile]
+    ProjectileMoved {
+        source: EntityId,
+        from: Vector<f32>,
+        to: Vector<f32>,
+        damage: i32,
+        /// In tiles
+        height: f32,
+    },
+    /// The player touches a pickup, which has to be removed if it is collected
+    Pickup {
+        source: EntityId,
+        pickup: Pickup,
+    },
+}
+
+/// What an entity can see of the world when it thinks
+pub struct ThinkContext<'a> {
+    pub map: &'a Map,
+    pub nav: &'a NavGrid,
+    /// Flow field toward the player, shared by everything chasing it
+    pub player_field: &'a FlowField,
+    pub player: Vector<f32>,
+    /// Noises made since the last tick
+    pub noises: &'a [Noise],
+    /// Duration of the tick, in seconds
+    pub delta: f32,
+    /// Filled by the entities during the update
+    pub events: Vec<EntityEvent>,
+}
+
+impl<'a> ThinkContext<'a> {
+    pub fn new(
+        map: &'a Map,
+        nav: &'a NavGrid,
+        player_field: &'a FlowField,
+        player: Vector<f32>,
+        delta: f32,
+    ) -> Self {
+        Self {
+            map,
+            nav,
+            player_field,
+            player,
+            noises: &[],
+            delta,
+            events: Vec::new(),
+        }
+    }
+}
+
+/// Behaviour of an entity, called once per tick before it moves
+pub trait Think {
+    fn think(&mut self, entity: &mut Entity, context: &mut ThinkContext);
+
+    /// State kept in the saved games, without whitespace. None for the
+    /// behaviours starting over when a game is loaded
+    fn save_state(&self) -> Option<String> {
+        None
+    }
+
+    /// Puts back a state given by [Think::save_state]
+    fn restore_state(&mut self, _state: &str) -> Result<(), String> {
+        Err("the behaviour has no state".to_string())
+    }
+}
+
+impl<F: FnMut(&mut Entity, &mut ThinkContext)> Think for F {
+    fn think(&mut self, entity: &mut Entity, context: &mut ThinkContext) {
+        self(entity, context)
+    }
+}
+
+/// Anything living in the map besides the player: enemies, decorations,
+/// projectiles... Positions and velocities are in world units
+pub struct Entity {
+    id: EntityId,
+    pub pos: Vector<f32>,
+    /// Position at the previous tick, for the interpolated rendering
+    previous_pos: Vector<f32>,
+    /// World units per second
+    pub velocity: Vector<f32>,
+    /// Facing direction, in radians
+    pub angle: f32,
+    pub radius: f32,
+    /// Solid entities push each other, the others go through everything but the walls
+    pub solid: bool,
+    pub sprite: Option<SpriteDef>,
+    /// None for entities which can't be damaged
+    pub health: Option<i32>,
+    /// Map object the entity was created from, to create it again when a
+    /// game is loaded. None for the projectiles and effects
+    pub placement: Option<Placement>,
+    think: Option<Box<dyn Think>>,
+    removed: bool,
+}
+
+impl Entity {
+    pub fn new(pos: Vector<f32>, radius: f32) -> Self {
+        Self {
+            id: 0,
+            pos,
+            previous_pos: pos,
+            velocity: Vector::new(0.0, 0.0),
+            angle: 0.0,
+            radius,
+            solid: true,
+            sprite: None,
+            health: None,
+            placement: None,
+            think: None,
+            removed: false,
+        }
+    }
+
+    /// Id given by [Entities::spawn]
+    pub fn get_id(&self) -> EntityId {
+        self.id
+    }
+
+    pub fn set_think(&mut self, think: impl Think + 'static) {
+        self.think = Some(Box::new(think));
+    }
+
+    /// State of the behaviour, see [Think::save_state]
+    pub fn get_think_state(&self) -> Option<String> {
+        self.think.as_ref()?.save_state()
+    }
+
+    pub fn set_think_state(&mut self, state: &str) -> Result<(), String> {
+        match &mut self.think {
+            Some(think) => think.restore_state(state),
+            None => Err("the entity has no behaviour".to_string()),
+        }
+    }
+
+    /// Removes health, returns true if this killed the entity
+    pub fn damage(&mut self, amount: i32) -> bool {
+        match &mut self.health {
+            Some(health) if *health > 0 => {
+                *health -= amount;
+                *health <= 0
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health.is_some_and(|health| health <= 0)
+    }
+
+    /// The entity is removed at the end of the current or next update
+    pub fn remove(&mut self) {
+        self.removed = true;
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.removed
+    }
+
+    /// Position between the previous tick (alpha = 0) and the current one
+    pub fn interpolated_pos(&self, alpha: f32) -> Vector<f32> {
+        self.previous_pos + (self.pos - self.previous_pos) * alpha
+    }
+}
+
+/// Entities of each map cell, to only test the entities close to each other.
+/// An entity is in every cell overlapped by its bounding box
+struct SpatialHash {
+    width: usize,
+    height: usize,
+    tile_size: f32,
+    cells: Vec<Vec<usize>>,
+}
+
+impl SpatialHash {
+    fn new(map: &Map) -> Self {
+        let (width, height) = (map.get_width(), map.get_height());
+        Self {
+            width,
+            height,
+            tile_size: map.get_tile_size(),
+            cells: vec![Vec::new(); width * height],
+        }
+    }
+
+    /// Range of the cells overlapped by the box around the circle, clamped to the map
+    fn cell_range(
+        &self,
+        pos: Vector<f32>,
+        radius: f32,
+    ) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let cell = |value: f32, size: usize| {
+            ((value / self.tile_size).floor().max(0.0) as usize).min(size.saturating_sub(1))
+        };
+        let (x0, x1) = (
+            cell(pos.x - radius, self.width),
+            cell(pos.x + radius, self.width),
+        );
+        let (y0, y1) = (
+            cell(pos.y - radius, self.height),
+            cell(pos.y + radius, self.height),
+        );
+        (x0..x1 + 1, y0..y1 + 1)
+    }
+
+    fn rebuild(&mut self, entities: &[Entity]) {
+        for cell in &mut self.cells {
+            cell.clear();
+        }
+        for (index, entity) in entities.iter().enumerate() {
+            self.insert(index, entity);
+        }
+    }
+
+    /// Adds the entity at the given index, greater than the indices already in the cells
+    fn insert(&mut self, index: usize, entity: &Entity) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        let (xs, ys) = self.cell_range(entity.pos, entity.radius);
+        for y in ys {
+            for x in xs.clone() {
+                self.cells[y * self.width + x].push(index);
+            }
+        }
+    }
+
+    /// Indices of the entities sharing a cell with the circle, sorted and without duplicates
+    fn candidates(&self, pos: Vector<f32>, radius: f32) -> Vec<usize> {
+        if self.width == 0 || self.height == 0 {
+            return Vec::new();
+        }
+        let (xs, ys) = self.cell_range(pos, radius);
+        let mut candidates = Vec::new();
+        for y in ys {
+            for x in xs.clone() {
+                candidates.extend_from_slice(&self.cells[y * self.width + x]);
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+
+    /// Indices of the entities in the cells crossed by the segment, sorted
+    /// and without duplicates. The cells outside of the map are clamped to it
+    fn along(&self, from: Vector<f32>, to: Vector<f32>) -> Vec<usize> {
+        if self.width == 0 || self.height == 0 {
+            return Vec::new();
+        }
+        let (origin, direction) = (from / self.tile_size, (to - from) / self.tile_size);
+        let mut candidates = Vec::new();
+        for crossing in Dda::new(origin, direction).take_while(|c| c.distance <= 1.0) {
+            let x = (crossing.tile.x.max(0) as usize).min(self.width - 1);
+            let y = (crossing.tile.y.max(0) as usize).min(self.height - 1);
+            candidates.extend_from_slice(&self.cells[y * self.width + x]);
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+}
+
+/// Every entity of the map, updated once per simulation tick
+pub struct Entities {
+    /// Sorted by id, new entities are pushed at the end
+    entities: Vec<Entity>,
+    next_id: EntityId,
+    hash: SpatialHash,
+}
+
+impl Entities {
+    pub fn new(map: &Map) -> Self {
+        Self {
+            entities: Vec::new(),
+            next_id: 1,
+            hash: SpatialHash::new(map),
+        }
+    }
+
+    /// Adds an entity to the world, returns its id
+    pub fn spawn(&mut self, mut entity: Entity) -> EntityId {
+        let id = self.next_id;
+        self.next_id += 1;
+        entity.id = id;
+        entity.previous_pos = entity.pos;
+        self.hash.insert(self.entities.len(), &entity);
+        self.entities.push(entity);
+        id
+    }
+
+    /// Id given to the next spawned entity
+    pub fn get_next_id(&self) -> EntityId {
+        self.next_id
+    }
+
+    /// Changes the id of the next spawned entity, it must be greater
+    /// than the ids of the entities already spawned
+    pub fn set_next_id(&mut self, id: EntityId) {
+        assert!(self.entities.last().is_none_or(|entity| entity.id < id));
+        self.next_id = id;
+    }
+
+    fn index(&self, id: EntityId) -> Option<usize> {
+        self.entities.binary_search_by_key(&id, |e| e.id).ok()
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+        Some(&self.entities[self.index(id)?])
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut Entity> {
+        let index = self.index(id)?;
+        Some(&mut self.entities[index])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Entity> {
+        self.entities.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Ids of the entities overlapping the circle, as of the last update or spawn
+    pub fn query(&self, pos: Vector<f32>, radius: f32) -> Vec<EntityId> {
+        self.hash
+            .candidates(pos, radius)
+            .into_iter()
+            .map(|index| &self.entities[index])
+            .filter(|entity| circles_overlap(pos, radius, entity.pos, entity.radius))
+            .map(|entity| entity.id)
+            .collect()
+    }
+
+    /// Entities which may be crossed by the segment, as of the last update
+    /// or spawn, sorted by id
+    pub fn along(&self, from: Vector<f32>, to: Vector<f32>) -> impl Iterator<Item = &Entity> {
+        self.hash
+            .along(from, to)
+            .into_iter()
+            .map(|index| &self.entities[index])
+    }
+
+    /// Runs a simulation tick: every entity thinks, then moves with
+    /// its velocity, sliding along the walls, and the solid entities
+    /// overlapping each other are pushed apart. Removed entities are dropped
+    pub fn update(&mut self, context: &mut ThinkContext) {
+        let (map, delta) = (context.map, context.delta);
+        for entity in &mut self.entities {
+            entity.previous_pos = entity.pos;
+            if entity.removed {
+                continue;
+            }
+            if let Some(mut think) = entity.think.take() {
+                think.think(entity, context);
+                // the think hook may have replaced itself
+                entity.think.get_or_insert(think);
+            }
+        }
+
+        for entity in &mut self.entities {
+            let target = entity.pos + entity.velocity * delta;
+            entity.pos = slide(map, entity.pos, target, entity.radius);
+        }
+
+        self.hash.rebuild(&self.entities);
+        self.separate(map);
+        self.entities.retain(|entity| !entity.removed);
+        self.hash.rebuild(&self.entities);
+    }
+
+    /// Pushes the overlapping solid entities apart, each one by half the overlap
+    fn separate(&mut self, map: &Map) {
+        for i in 0..self.entities.len() {
+            if !self.entities[i].solid {
+                continue;
+            }
+            let (pos, radius) = (self.entities[i].pos, self.entities[i].radius);
+            for j in self.hash.candidates(pos, radius) {
+                if j <= i || !self.entities[j].solid {
+                    continue;
+                }
+                let (a, b) = (self.entities[i].pos, self.entities[j].pos);
+                let (ra, rb) = (self.entities[i].radius, self.entities[j].radius);
+                if !circles_overlap(a, ra, b, rb) {
+                    continue;
+                }
+                let offset = b - a;
+                let distance = (offset.x * offset.x + offset.y * offset.y).sqrt();
+                let direction = if distance > 0.0 {
+                    offset / distance
+                } else {
+                    Vector::new(1.0, 0.0)
+                };
+                let push = direction * ((ra + rb - distance) / 2.0);
+                self.entities[i].pos = slide(map, a, a - push, ra);
+                self.entities[j].pos = slide(map, b, b + push, rb);
+            }
+        }
+    }
+
+    /// Sprites of the entities placed between the previous tick and the current one
+    pub fn sprites(&self, alpha: f32) -> Vec<Sprite> {
+        self.entities
+            .iter()
+            .filter(|entity| !entity.removed)
+            .filter_map(|entity| {
+                let sprite = entity.sprite?;
+                Some(Sprite {
+                    position: entity.interpolated_pos(alpha),
+                    texture: sprite.texture,
+                    color: sprite.color,
+                    size: sprite.size,
+                    elevation: sprite.elevation,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Render for Entities {
+    fn render(&self, view: &TopDownView) {
+        for entity in &self.entities {
+            let pos = view.project(entity.pos);
+            let radius = entity.radius * view.scale;
+            match entity.sprite {
+                Some(sprite) => {
+                    let [r, g, b, _] = sprite.color;
+                    let color = Color::from_rgba(r, g, b, 255);
+                    draw_circle(pos.x, pos.y, radius, view.color(color));
+                }
+                None => draw_circle_lines(pos.x, pos.y, radius, 1.0, view.color(ORANGE)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod entity_tests {
+    use super::*;
+    use crate::pathfinding::Connectivity;
+
+    fn room() -> Map {
+        let mut tiles = vec![0; 100];
+        for i in 0..10 {
+            tiles[i] = 1;
+            tiles[90 + i] = 1;
+            tiles[i * 10] = 1;
+            tiles[i * 10 + 9] = 1;
+        }
+        Map::new("room".to_string(), 10, 10, 10.0, tiles)
+    }
+
+    fn update(entities: &mut Entities, map: &Map, player: Vector<f32>, delta: f32) {
+        let nav = NavGrid::new(map, Connectivity::Four);
+        let field = FlowField::new();
+        entities.update(&mut ThinkContext::new(map, &nav, &field, player, delta));
+    }
+
+    #[test]
+    fn think_and_move() {
+        let map = room();
+        let mut entities = Entities::new(&map);
+        let mut entity = Entity::new(Vector::new(50.0, 50.0), 2.0);
+        entity.set_think(|entity: &mut Entity, context: &mut ThinkContext| {
+            // walks toward the player
+            let offset = context.player - entity.pos;
+            entity.velocity = offset * 2.0;
+        });
+        let id = entities.spawn(entity);
+        update(&mut entities, &map, Vector::new(60.0, 50.0), 0.25);
+        let entity = entities.get(id).unwrap();
+        assert_eq!(entity.pos, Vector::new(55.0, 50.0));
+        assert_eq!(entity.interpolated_pos(0.5), Vector::new(52.5, 50.0));
+    }
+
+    #[test]
+    fn walls_stop_entities() {
+        let map = room();
+        let mut entities = Entities::new(&map);
+        let mut entity = Entity::new(Vector::new(15.0, 50.0), 2.0);
+        entity.velocity = Vector::new(-100.0, 0.0);
+        let id = entities.spawn(entity);
+        update(&mut entities, &map, Vector::new(0.0, 0.0), 0.1);
+        // stops against the wall instead of going through it
+        assert_eq!(entities.get(id).unwrap().pos, Vector::new(13.0, 50.0));
+    }
+
+    #[test]
+    fn solid_entities_push_each_other() {
+        let map = room();
+        let mut entities = Entities::new(&map);
+        let a = entities.spawn(Entity::new(Vector::new(50.0, 50.0), 3.0));
+        let b = entities.spawn(Entity::new(Vector::new(52.0, 50.0), 3.0));
+        let mut ghost = Entity::new(Vector::new(51.0, 50.0), 3.0);
+        ghost.solid = false;
+        let ghost = entities.spawn(ghost);
+        // the spawned entities are in the spatial hash before the first update
+        assert_eq!(entities.query(Vector::new(51.0, 50.0), 0.5), [a, b, ghost]);
+        update(&mut entities, &map, Vector::new(0.0, 0.0), 0.1);
+
+        let (pa, pb) = (entities.get(a).unwrap().pos, entities.get(b).unwrap().pos);
+        assert!((pb.x - pa.x - 6.0).abs() < 1e-4);
+        assert_eq!(entities.get(ghost).unwrap().pos, Vector::new(51.0, 50.0));
+        assert_eq!(entities.query(Vector::new(51.0, 50.0), 0.5), [a, b, ghost]);
+        assert_eq!(entities.query(Vector::new(57.5, 50.0), 1.0), [b]);
+        assert_eq!(entities.query(Vector::new(70.0, 70.0), 5.0), []);
+    }
+
+    #[test]
+    fn removal_and_damage() {
+        let map = room();
+        let mut entities = Entities::new(&map);
+        let mut entity = Entity::new(Vector::new(50.0, 50.0), 2.0);
+        entity.health = Some(10);
+        let id = entities.spawn(entity);
+        let other = entities.spawn(Entity::new(Vector::new(20.0, 20.0), 2.0));
+
+        let entity = entities.get_mut(id).unwrap();
+        assert!(!entity.damage(4));
+        assert!(entity.damage(6));
+        assert!(entity.is_dead());
+        assert!(!entity.damage(1));
+        entity.remove();
+        update(&mut entities, &map, Vector::new(0.0, 0.0), 0.1);
+        assert!(entities.get(id).is_none());
+        assert!(entities.get(other).is_some());
+        assert_eq!(entities.len(), 1);
+    }
+}
@@ -0,0 +1,78 @@
+/// A RGBA color as stored in the framebuffer
+pub type Pixel = [u8; 4];
+
+/// Multiplies the color channels by the factor, the alpha is kept
+pub(crate) fn scale(color: Pixel, factor: f32) -> Pixel {
+    [
+        (color[0] as f32 * factor) as u8,
+        (color[1] as f32 * factor) as u8,
+        (color[2] as f32 * factor) as u8,
+        color[3],
+    ]
+}
+
+/// An image rendered on the CPU.
+/// Pixels are stored row by row, from top to bottom
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0, 0, 0, 255]; width * height],
+        }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    /// Changes the size of the framebuffer, the content is lost
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if self.width == width && self.height == height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.pixels.clear();
+        self.pixels.resize(width * height, [0, 0, 0, 255]);
+    }
+
+    #[allow(dead_code)]
+    pub fn clear(&mut self, color: Pixel) {
+        self.pixels.fill(color);
+    }
+
+    #[allow(dead_code)]
+    pub fn get_pixel(&self, x: usize, y: usize) -> Pixel {
+        self.pixels[y * self.width + x]
+    }
+
+    #[allow(dead_code)]
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Pixel) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn get_pixels_mut(&mut self) -> &mut [Pixel] {
+        &mut self.pixels
+    }
+
+    /// Raw RGBA bytes, ready to be uploaded in a texture
+    pub fn as_bytes(&self) -> &[u8] {
+        self.pixels.as_flattened()
+    }
+}
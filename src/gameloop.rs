@@ -0,0 +1,154 @@
+use crate::camera::Camera;
+use crate::player::Player;
+use crate::vector::Vector;
+
+/// Simulation ticks per second
+pub const TICK_RATE: f32 = 60.0;
+/// Ticks simulated at most per frame, so a long frame (loading, breakpoint...)
+/// doesn't start a spiral of ever longer frames
+const MAX_TICKS_PER_FRAME: usize = 8;
+
+/// Splits the variable frame times into fixed simulation ticks.
+/// The time left over after the last tick stays in the accumulator
+/// and is used to interpolate the rendered state between two ticks
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    /// Creates a timestep running `rate` ticks per second
+    pub fn new(rate: f32) -> Self {
+        Self {
+            step: 1.0 / rate,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Duration of a tick, in seconds
+    pub fn get_step(&self) -> f32 {
+        self.step
+    }
+
+    /// Adds the duration of a frame and returns the number of ticks to simulate
+    pub fn advance(&mut self, frame_time: f32) -> usize {
+        self.accumulator += frame_time.max(0.0);
+        let ticks = (self.accumulator / self.step) as usize;
+        self.accumulator -= ticks as f32 * self.step;
+        if ticks > MAX_TICKS_PER_FRAME {
+            self.accumulator = 0.0;
+            return MAX_TICKS_PER_FRAME;
+        }
+        ticks
+    }
+
+    /// Progress toward the next tick, between 0 and 1
+    pub fn get_alpha(&self) -> f32 {
+        (self.accumulator / self.step).clamp(0.0, 1.0)
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(TICK_RATE)
+    }
+}
+
+/// Position and angle of the player at a tick
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PlayerState {
+    pub pos: Vector<f32>,
+    pub angle: f32,
+}
+
+impl PlayerState {
+    pub fn of(player: &Player) -> Self {
+        Self {
+            pos: player.get_pos(),
+            angle: player.get_angle(),
+        }
+    }
+
+    /// State between `self` (alpha = 0) and `next` (alpha = 1),
+    /// the angle takes the shortest way
+    pub fn lerp(&self, next: &PlayerState, alpha: f32) -> PlayerState {
+        let turn = (next.angle - self.angle + std::f32::consts::PI)
+            .rem_euclid(std::f32::consts::TAU)
+            - std::f32::consts::PI;
+        PlayerState {
+            pos: self.pos + (next.pos - self.pos) * alpha,
+            angle: self.angle + turn * alpha,
+        }
+    }
+}
+
+/// Camera of the player placed between the previous tick and the current one
+pub fn interpolated_camera(player: &Player, previous: &PlayerState, alpha: f32) -> Camera {
+    let state = previous.lerp(&PlayerState::of(player), alpha);
+    let mut camera = player.get_camera().clone();
+    camera.set_position(state.pos);
+    camera.set_angle(state.angle);
+    camera
+}
+
+#[cfg(test)]
+mod gameloop_tests {
+    use super::*;
+    use crate::map::Map;
+    use crate::replay::{Action, InputFrame};
+
+    #[test]
+    fn accumulates_frame_times() {
+        let mut timestep = FixedTimestep::new(10.0);
+        assert_eq!(timestep.advance(0.05), 0);
+        assert!((timestep.get_alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(timestep.advance(0.07), 1);
+        assert!((timestep.get_alpha() - 0.2).abs() < 1e-4);
+        assert_eq!(timestep.advance(0.3), 3);
+        // a very long frame is clamped
+        assert_eq!(timestep.advance(10.0), MAX_TICKS_PER_FRAME);
+        assert_eq!(timestep.get_alpha(), 0.0);
+    }
+
+    /// The state after each tick doesn't depend on the frame rate
+    #[test]
+    fn simulation_is_frame_rate_independent() {
+        let map = Map::new("open".to_string(), 10, 10, 64.0, vec![0; 100]);
+        let run = |frame_time: f32, frames: usize| {
+            let mut timestep = FixedTimestep::new(60.0);
+            let mut player = Player::new(100.0, 100.0, 0.0);
+            let mut states = Vec::new();
+            for _ in 0..frames {
+                for _ in 0..timestep.advance(frame_time) {
+                    let mut input = InputFrame::new(timestep.get_step());
+                    input.set(Action::Forward, true);
+                    input.set(Action::TurnRight, true);
+                    player.update(&input, &map);
+                    states.push(PlayerState::of(&player));
+                }
+            }
+            states
+        };
+        // one second at 30 and 144 frames per second
+        let slow = run(1.0 / 30.0, 30);
+        let fast = run(1.0 / 144.0, 144);
+        assert!(slow.len().abs_diff(60) <= 1 && fast.len().abs_diff(60) <= 1);
+        let ticks = slow.len().min(fast.len());
+        assert_eq!(slow[..ticks], fast[..ticks]);
+    }
+
+    #[test]
+    fn lerp_takes_shortest_angle() {
+        let a = PlayerState {
+            pos: Vector::new(0.0, 0.0),
+            angle: 3.0,
+        };
+        let b = PlayerState {
+            pos: Vector::new(10.0, 0.0),
+            angle: -3.0,
+        };
+        let middle = a.lerp(&b, 0.5);
+        assert_eq!(middle.pos.x, 5.0);
+        assert!((middle.angle.abs() - std::f32::consts::PI).abs() < 1e-4);
+    }
+}
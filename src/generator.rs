@@ -0,0 +1,574 @@
+use crate::map::{Map, Spawn};
+use std::collections::VecDeque;
+
+const WALL: u8 = 1;
+const FLOOR: u8 = 0;
+/// BSP leaves are not split below this size, in tiles
+const MIN_LEAF_SIZE: usize = 8;
+/// Smoothing passes of the cave generator
+const CAVE_STEPS: usize = 5;
+
+/// Seeded pseudo random numbers (SplitMix64), the same seed
+/// always gives the same map on every platform
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Number in `0..max`
+    pub fn range(&mut self, max: usize) -> usize {
+        (self.next_u64() % max.max(1) as u64) as usize
+    }
+
+    /// Number in `0.0..1.0`
+    pub fn float(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.float() < probability
+    }
+}
+
+/// Kind of map to generate
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Generator {
+    /// Perfect maze made with a recursive backtracker (long corridors)
+    Backtracker,
+    /// Perfect maze made with Prim's algorithm (many short dead ends)
+    Prim,
+    /// Rooms in a binary space partition, linked by corridors
+    Bsp,
+    /// Cellular automata caves
+    Caves,
+}
+
+impl Generator {
+    pub const ALL: [Generator; 4] = [
+        Generator::Backtracker,
+        Generator::Prim,
+        Generator::Bsp,
+        Generator::Caves,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Generator::Backtracker => "backtracker",
+            Generator::Prim => "prim",
+            Generator::Bsp => "bsp",
+            Generator::Caves => "caves",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Generator> {
+        Generator::ALL.into_iter().find(|g| g.name() == name)
+    }
+
+    /// Density used when the options don't give one: perfect mazes, rooms
+    /// filling their leaf and caves starting with 45% of walls
+    pub fn default_density(self) -> f32 {
+        match self {
+            Generator::Backtracker | Generator::Prim | Generator::Bsp => 1.0,
+            Generator::Caves => 0.45,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GeneratorOptions {
+    /// Size in tiles, mazes use odd sizes so an even size leaves
+    /// a thicker wall on the right or bottom border
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+    /// Meaning depends on the generator, between 0 and 1:
+    /// mazes keep this ratio of their inner walls (below 1 opens loops),
+    /// BSP rooms fill this ratio of their leaf and caves start with
+    /// this ratio of walls. None uses [Generator::default_density]
+    pub density: Option<f32>,
+    /// Only keeps the largest open region, so every empty tile can be
+    /// reached from the spawn. Mazes and BSP dungeons are always connected
+    pub connected: bool,
+    pub tile_size: f32,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self {
+            width: 31,
+            height: 31,
+            seed: 0,
+            density: None,
+            connected: true,
+            tile_size: 64.0,
+        }
+    }
+}
+
+/// Tiles being generated, every tile starts as a wall
+struct Grid {
+    width: usize,
+    height: usize,
+    tiles: Vec<u8>,
+}
+
+impl Grid {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![WALL; width * height],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> u8 {
+        self.tiles[y * self.width + x]
+    }
+
+    fn set(&mut self, x: usize, y: usize, tile: u8) {
+        self.tiles[y * self.width + x] = tile;
+    }
+
+    fn is_border(&self, x: usize, y: usize) -> bool {
+        x == 0 || y == 0 || x == self.width - 1 || y == self.height - 1
+    }
+
+    /// Neighbours in the four directions, `distance` tiles away
+    fn neighbours(&self, x: usize, y: usize, distance: usize) -> Vec<(usize, usize)> {
+        let mut neighbours = Vec::with_capacity(4);
+        if x >= distance {
+            neighbours.push((x - distance, y));
+        }
+        if x + distance < self.width {
+            neighbours.push((x + distance, y));
+        }
+        if y >= distance {
+            neighbours.push((x, y - distance));
+        }
+        if y + distance < self.height {
+            neighbours.push((x, y + distance));
+        }
+        neighbours
+    }
+
+    /// Open regions, as region index of each tile (`usize::MAX` for walls),
+    /// and the size of each region
+    fn regions(&self) -> (Vec<usize>, Vec<usize>) {
+        let mut region = vec![usize::MAX; self.tiles.len()];
+        let mut sizes = Vec::new();
+        for start in 0..self.tiles.len() {
+            if self.tiles[start] != FLOOR || region[start] != usize::MAX {
+                continue;
+            }
+            let id = sizes.len();
+            let mut size = 0;
+            let mut queue = VecDeque::from([start]);
+            region[start] = id;
+            while let Some(index) = queue.pop_front() {
+                size += 1;
+                let (x, y) = (index % self.width, index / self.width);
+                for (nx, ny) in self.neighbours(x, y, 1) {
+                    let next = ny * self.width + nx;
+                    if self.tiles[next] == FLOOR && region[next] == usize::MAX {
+                        region[next] = id;
+                        queue.push_back(next);
+                    }
+                }
+            }
+            sizes.push(size);
+        }
+        (region, sizes)
+    }
+
+    /// Fills every open region except the largest one
+    fn keep_largest_region(&mut self) {
+        let (region, sizes) = self.regions();
+        let Some(largest) = (0..sizes.len()).max_by_key(|id| (sizes[*id], std::cmp::Reverse(*id)))
+        else {
+            return;
+        };
+        for (tile, region) in self.tiles.iter_mut().zip(region) {
+            if region != largest {
+                *tile = WALL;
+            }
+        }
+    }
+
+    /// Opens random inner walls between two open tiles, keeping `density` of them
+    fn open_loops(&mut self, rng: &mut Rng, density: f32) {
+        if density >= 1.0 {
+            return;
+        }
+        for y in 1..self.height - 1 {
+            for x in 1..self.width - 1 {
+                let horizontal = self.get(x - 1, y) == FLOOR && self.get(x + 1, y) == FLOOR;
+                let vertical = self.get(x, y - 1) == FLOOR && self.get(x, y + 1) == FLOOR;
+                if self.get(x, y) == WALL && (horizontal ^ vertical) && !rng.chance(density) {
+                    self.set(x, y, FLOOR);
+                }
+            }
+        }
+    }
+
+    /// Empty tile of the largest region closest to the center, facing
+    /// an open neighbour
+    fn spawn(&self) -> Option<Spawn> {
+        let (region, sizes) = self.regions();
+        let largest = (0..sizes.len()).max_by_key(|id| (sizes[*id], std::cmp::Reverse(*id)))?;
+        let (cx, cy) = (self.width as f32 / 2.0, self.height as f32 / 2.0);
+        let index = (0..self.tiles.len())
+            .filter(|index| region[*index] == largest)
+            .min_by(|a, b| {
+                let distance = |index: &usize| {
+                    let (x, y) = ((index % self.width) as f32, (index / self.width) as f32);
+                    (x - cx).powi(2) + (y - cy).powi(2)
+                };
+                distance(a).total_cmp(&distance(b))
+            })?;
+        let (x, y) = (index % self.width, index / self.width);
+        let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+        let direction = directions
+            .iter()
+            .position(|(dx, dy)| {
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                self.get(nx as usize, ny as usize) == FLOOR
+            })
+            .unwrap_or(0);
+        Some(Spawn {
+            x,
+            y,
+            angle: direction as f32 * std::f32::consts::FRAC_PI_2,
+        })
+    }
+}
+
+fn backtracker(grid: &mut Grid, rng: &mut Rng) {
+    let mut stack = vec![(1, 1)];
+    grid.set(1, 1, FLOOR);
+    while let Some(&(x, y)) = stack.last() {
+        let unvisited: Vec<_> = grid
+            .neighbours(x, y, 2)
+            .into_iter()
+            .filter(|(nx, ny)| !grid.is_border(*nx, *ny) && grid.get(*nx, *ny) == WALL)
+            .collect();
+        if unvisited.is_empty() {
+            stack.pop();
+            continue;
+        }
+        let (nx, ny) = unvisited[rng.range(unvisited.len())];
+        grid.set((x + nx) / 2, (y + ny) / 2, FLOOR);
+        grid.set(nx, ny, FLOOR);
+        stack.push((nx, ny));
+    }
+}
+
+fn prim(grid: &mut Grid, rng: &mut Rng) {
+    // walls between a maze cell and a cell not in the maze yet
+    let mut frontier = Vec::new();
+    let add_cell = |grid: &mut Grid, frontier: &mut Vec<_>, x: usize, y: usize| {
+        grid.set(x, y, FLOOR);
+        for (nx, ny) in grid.neighbours(x, y, 2) {
+            if !grid.is_border(nx, ny) && grid.get(nx, ny) == WALL {
+                frontier.push(((x + nx) / 2, (y + ny) / 2, nx, ny));
+            }
+        }
+    };
+    add_cell(grid, &mut frontier, 1, 1);
+    while !frontier.is_empty() {
+        let (wx, wy, x, y) = frontier.swap_remove(rng.range(frontier.len()));
+        if grid.get(x, y) == WALL {
+            grid.set(wx, wy, FLOOR);
+            add_cell(grid, &mut frontier, x, y);
+        }
+    }
+}
+
+/// A rectangle of tiles
+#[derive(Clone, Copy)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Rect {
+    fn center(&self) -> (usize, usize) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+}
+
+fn carve_corridor(grid: &mut Grid, from: (usize, usize), to: (usize, usize), rng: &mut Rng) {
+    let horizontal_first = rng.chance(0.5);
+    let corner = if horizontal_first {
+        (to.0, from.1)
+    } else {
+        (from.0, to.1)
+    };
+    for (a, b) in [(from, corner), (corner, to)] {
+        for x in a.0.min(b.0)..=a.0.max(b.0) {
+            for y in a.1.min(b.1)..=a.1.max(b.1) {
+                grid.set(x, y, FLOOR);
+            }
+        }
+    }
+}
+
+/// Splits the leaf until it is too small, carves a room in each leaf
+/// and links the two halves, returns a room of this part of the tree
+fn bsp(grid: &mut Grid, rng: &mut Rng, leaf: Rect, density: f32) -> Rect {
+    let can_split_x = leaf.width >= MIN_LEAF_SIZE * 2;
+    let can_split_y = leaf.height >= MIN_LEAF_SIZE * 2;
+    let split_x = match (can_split_x, can_split_y) {
+        (false, false) => {
+            // room with a random size inside the leaf, one tile of margin
+            let fill = density.clamp(0.3, 1.0);
+            let max_width = leaf.width - 2;
+            let max_height = leaf.height - 2;
+            let min_width = ((max_width as f32 * fill) as usize).clamp(2, max_width);
+            let min_height = ((max_height as f32 * fill) as usize).clamp(2, max_height);
+            let width = min_width + rng.range(max_width - min_width + 1);
+            let height = min_height + rng.range(max_height - min_height + 1);
+            let room = Rect {
+                x: leaf.x + 1 + rng.range(max_width - width + 1),
+                y: leaf.y + 1 + rng.range(max_height - height + 1),
+                width,
+                height,
+            };
+            for y in room.y..room.y + room.height {
+                for x in room.x..room.x + room.width {
+                    grid.set(x, y, FLOOR);
+                }
+            }
+            return room;
+        }
+        (true, true) => {
+            if leaf.width == leaf.height {
+                rng.chance(0.5)
+            } else {
+                leaf.width > leaf.height
+            }
+        }
+        (split_x, _) => split_x,
+    };
+
+    let (first, second) = if split_x {
+        let size = MIN_LEAF_SIZE + rng.range(leaf.width - MIN_LEAF_SIZE * 2 + 1);
+        (
+            Rect {
+                width: size,
+                ..leaf
+            },
+            Rect {
+                x: leaf.x + size,
+                width: leaf.width - size,
+                ..leaf
+            },
+        )
+    } else {
+        let size = MIN_LEAF_SIZE + rng.range(leaf.height - MIN_LEAF_SIZE * 2 + 1);
+        (
+            Rect {
+                height: size,
+                ..leaf
+            },
+            Rect {
+                y: leaf.y + size,
+                height: leaf.height - size,
+                ..leaf
+            },
+        )
+    };
+    let a = bsp(grid, rng, first, density);
+    let b = bsp(grid, rng, second, density);
+    carve_corridor(grid, a.center(), b.center(), rng);
+    if rng.chance(0.5) {
+        a
+    } else {
+        b
+    }
+}
+
+fn caves(grid: &mut Grid, rng: &mut Rng, density: f32) {
+    for y in 1..grid.height - 1 {
+        for x in 1..grid.width - 1 {
+            let tile = if rng.chance(density) { WALL } else { FLOOR };
+            grid.set(x, y, tile);
+        }
+    }
+    for _ in 0..CAVE_STEPS {
+        let previous = grid.tiles.clone();
+        for y in 1..grid.height - 1 {
+            for x in 1..grid.width - 1 {
+                let walls = (y - 1..=y + 1)
+                    .flat_map(|ny| (x - 1..=x + 1).map(move |nx| (nx, ny)))
+                    .filter(|(nx, ny)| (*nx, *ny) != (x, y))
+                    .filter(|(nx, ny)| previous[ny * grid.width + nx] == WALL)
+                    .count();
+                if walls >= 5 {
+                    grid.set(x, y, WALL);
+                } else if walls <= 3 {
+                    grid.set(x, y, FLOOR);
+                }
+            }
+        }
+    }
+}
+
+/// Generates a map surrounded by walls, with a spawn point on an empty tile.
+/// Maps smaller than 5x5 are made of walls only
+pub fn generate(generator: Generator, options: &GeneratorOptions) -> Map {
+    let (width, height) = (options.width.max(1), options.height.max(1));
+    let mut grid = Grid::new(width, height);
+    let mut rng = Rng::new(options.seed);
+    let density = options
+        .density
+        .unwrap_or_else(|| generator.default_density());
+    if width >= 5 && height >= 5 {
+        match generator {
+            Generator::Backtracker | Generator::Prim => {
+                if generator == Generator::Backtracker {
+                    backtracker(&mut grid, &mut rng);
+                } else {
+                    prim(&mut grid, &mut rng);
+                }
+                grid.open_loops(&mut rng, density);
+            }
+            Generator::Bsp => {
+                let leaf = Rect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                };
+                bsp(&mut grid, &mut rng, leaf, density);
+            }
+            Generator::Caves => caves(&mut grid, &mut rng, density),
+        }
+        if options.connected {
+            grid.keep_largest_region();
+        }
+    }
+
+    let spawn = grid.spawn();
+    let name = format!("{}-{}", generator.name(), options.seed);
+    let mut map = Map::new(name, width, height, options.tile_size, grid.tiles);
+    map.set_spawn(spawn);
+    map
+}
+
+#[cfg(test)]
+mod generator_tests {
+    use super::*;
+
+    fn open_tiles(map: &Map) -> Vec<(usize, usize)> {
+        (0..map.get_height())
+            .flat_map(|y| (0..map.get_width()).map(move |x| (x, y)))
+            .filter(|(x, y)| !map.is_wall(*x, *y))
+            .collect()
+    }
+
+    fn is_connected(map: &Map) -> bool {
+        let grid = Grid {
+            width: map.get_width(),
+            height: map.get_height(),
+            tiles: (0..map.get_width() * map.get_height())
+                .map(|i| map[(i % map.get_width(), i / map.get_width())])
+                .collect(),
+        };
+        grid.regions().1.len() == 1
+    }
+
+    #[test]
+    fn generators_are_deterministic() {
+        for generator in Generator::ALL {
+            let options = GeneratorOptions {
+                seed: 42,
+                width: 41,
+                height: 33,
+                density: Some(0.45),
+                ..Default::default()
+            };
+            let mut a = Vec::new();
+            let mut b = Vec::new();
+            generate(generator, &options).write(&mut a).unwrap();
+            generate(generator, &options).write(&mut b).unwrap();
+            assert_eq!(a, b, "{}", generator.name());
+
+            let other = GeneratorOptions {
+                seed: 43,
+                ..options
+            };
+            let mut c = Vec::new();
+            generate(generator, &other).write(&mut c).unwrap();
+            assert_ne!(a, c, "{}", generator.name());
+        }
+    }
+
+    #[test]
+    fn maps_are_closed_connected_and_spawnable() {
+        for generator in Generator::ALL {
+            for seed in 0..5 {
+                let options = GeneratorOptions {
+                    seed,
+                    width: 40,
+                    height: 30,
+                    density: Some(0.45),
+                    ..Default::default()
+                };
+                let map = generate(generator, &options);
+                for x in 0..map.get_width() {
+                    assert!(map.is_wall(x, 0) && map.is_wall(x, map.get_height() - 1));
+                }
+                for y in 0..map.get_height() {
+                    assert!(map.is_wall(0, y) && map.is_wall(map.get_width() - 1, y));
+                }
+                assert!(open_tiles(&map).len() > 20, "{}", generator.name());
+                assert!(is_connected(&map), "{} {}", generator.name(), seed);
+                let spawn = map.get_spawn().unwrap();
+                assert!(!map.is_wall(spawn.x, spawn.y));
+            }
+        }
+    }
+
+    #[test]
+    fn perfect_maze_has_no_loops() {
+        for generator in [Generator::Backtracker, Generator::Prim] {
+            let options = GeneratorOptions {
+                seed: 7,
+                ..Default::default()
+            };
+            let map = generate(generator, &options);
+            // a tree: one less connection than open tiles
+            let tiles = open_tiles(&map);
+            let edges: usize = tiles
+                .iter()
+                .map(|(x, y)| {
+                    (!map.is_wall(x + 1, *y)) as usize + (!map.is_wall(*x, y + 1)) as usize
+                })
+                .sum();
+            assert_eq!(edges, tiles.len() - 1, "{}", generator.name());
+        }
+    }
+
+    #[test]
+    fn written_map_can_be_parsed() {
+        let map = generate(Generator::Caves, &GeneratorOptions::default());
+        assert!(map.get_spawn().is_some());
+        let mut data = Vec::new();
+        map.write(&mut data).unwrap();
+        let parsed = Map::parse(data.as_slice(), 64.0).unwrap();
+        assert_eq!(parsed.get_spawn(), map.get_spawn());
+        assert_eq!(parsed.get_name(), map.get_name());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::camera::Camera;
+use crate::capture::save_png;
+use crate::framebuffer::{Framebuffer, Pixel};
+use crate::map::Map;
+use crate::palette::PaletteMode;
+use crate::renderer::{Renderer, FLAT_COLORS};
+use crate::textures::TextureManager;
+use std::path::{Path, PathBuf};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 100;
+/// Maximum difference allowed on each channel of each pixel
+const TOLERANCE: u8 = 3;
+
+/// A camera pose, the position is in tiles
+struct Pose {
+    name: &'static str,
+    tile: (usize, usize),
+    angle: f32,
+    pitch: f32,
+    height: f32,
+    palette: bool,
+}
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn empty_tiles(map: &Map) -> Vec<(usize, usize)> {
+    (0..map.get_height())
+        .flat_map(|y| (0..map.get_width()).map(move |x| (x, y)))
+        .filter(|(x, y)| !map.is_wall(*x, *y))
+        .collect()
+}
+
+/// Poses used for every map: the first empty tile and the empty tile
+/// closest to the center of the map
+fn poses(map: &Map) -> Vec<Pose> {
+    let tiles = empty_tiles(map);
+    let corner = tiles[0];
+    let (cx, cy) = (map.get_width() as f32 / 2.0, map.get_height() as f32 / 2.0);
+    let center = *tiles
+        .iter()
+        .min_by(|a, b| {
+            let distance =
+                |(x, y): &(usize, usize)| (*x as f32 - cx).powi(2) + (*y as f32 - cy).powi(2);
+            distance(a).total_cmp(&distance(b))
+        })
+        .unwrap();
+    let pose = |name, tile, angle| Pose {
+        name,
+        tile,
+        angle,
+        pitch: 0.0,
+        height: 0.5,
+        palette: false,
+    };
+    vec![
+        pose("corner", corner, std::f32::consts::FRAC_PI_4),
+        pose("center", center, std::f32::consts::PI - 0.3),
+        pose("center-back", center, -0.3),
+        Pose {
+            pitch: 0.2,
+            height: 0.3,
+            ..pose("look-up", center, 1.0)
+        },
+        Pose {
+            palette: true,
+            ..pose("palette", center, std::f32::consts::PI - 0.3)
+        },
+    ]
+}
+
+fn render(map: &Map, textures: &TextureManager, pose: &Pose) -> Framebuffer {
+    let tile_size = map.get_tile_size();
+    let mut camera = Camera::new(
+        (pose.tile.0 as f32 + 0.5) * tile_size,
+        (pose.tile.1 as f32 + 0.5) * tile_size,
+        pose.angle,
+    );
+    camera.set_pitch(pose.pitch);
+    camera.set_height(pose.height);
+
+    let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
+    let mut renderer = Renderer::new(1);
+    if pose.palette {
+        let mode = PaletteMode::new(textures, &FLAT_COLORS);
+        let palette = mode.get_palette();
+        renderer.render_indexed(map, &camera, textures, &mode, palette, &mut framebuffer);
+    } else {
+        renderer.render(map, &camera, textures, &mut framebuffer);
+    }
+    framebuffer
+}
+
+fn load_reference(path: &Path) -> Option<Framebuffer> {
+    let image = image::open(path).ok()?.to_rgba8();
+    let mut framebuffer = Framebuffer::new(image.width() as usize, image.height() as usize);
+    for (pixel, reference) in framebuffer.get_pixels_mut().iter_mut().zip(image.pixels()) {
+        *pixel = reference.0;
+    }
+    Some(framebuffer)
+}
+
+fn differs(a: Pixel, b: Pixel) -> bool {
+    a.iter().zip(&b).any(|(a, b)| a.abs_diff(*b) > TOLERANCE)
+}
+
+/// Reference dimmed to gray, with the differing pixels in red
+fn diff_image(actual: &Framebuffer, reference: &Framebuffer) -> Framebuffer {
+    let mut diff = Framebuffer::new(actual.get_width(), actual.get_height());
+    for ((pixel, a), b) in diff
+        .get_pixels_mut()
+        .iter_mut()
+        .zip(actual.get_pixels())
+        .zip(reference.get_pixels())
+    {
+        *pixel = if differs(*a, *b) {
+            [255, 0, 0, 255]
+        } else {
+            let gray = ((b[0] as u16 + b[1] as u16 + b[2] as u16) / 9) as u8;
+            [gray, gray, gray, 255]
+        };
+    }
+    diff
+}
+
+/// Compares the image with its reference, returns an error message on failure
+fn check(name: &str, actual: &Framebuffer) -> Result<(), String> {
+    let reference_path = root().join("tests/golden").join(format!("{}.png", name));
+    let update = std::env::var("UPDATE_GOLDEN").is_ok_and(|value| value == "1");
+    if update {
+        std::fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        save_png(actual, &reference_path).unwrap();
+        eprintln!("Golden image written: {}", reference_path.display());
+        return Ok(());
+    }
+    let Some(reference) = load_reference(&reference_path) else {
+        return Err(format!(
+            "missing golden image {}, create it with UPDATE_GOLDEN=1",
+            reference_path.display()
+        ));
+    };
+
+    let same_size = (reference.get_width(), reference.get_height())
+        == (actual.get_width(), actual.get_height());
+    let different = if same_size {
+        actual
+            .get_pixels()
+            .iter()
+            .zip(reference.get_pixels())
+            .filter(|(a, b)| differs(**a, **b))
+            .count()
+    } else {
+        actual.get_pixels().len()
+    };
+    if different == 0 {
+        return Ok(());
+    }
+
+    let output = root().join("target/golden");
+    std::fs::create_dir_all(&output).unwrap();
+    save_png(actual, &output.join(format!("{}-actual.png", name))).unwrap();
+    if same_size {
+        let diff = diff_image(actual, &reference);
+        save_png(&diff, &output.join(format!("{}-diff.png", name))).unwrap();
+    }
+    Err(format!(
+        "{}: {} pixels differ from {}, see {}",
+        name,
+        different,
+        reference_path.display(),
+        output.display()
+    ))
+}
+
+#[test]
+fn golden_images() {
+    let mut paths: Vec<_> = std::fs::read_dir(root().join("maps"))
+        .unwrap()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "map"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failures = Vec::new();
+    for path in paths {
+        let map = Map::open(&path, 64.0).unwrap();
+        let textures = TextureManager::for_map(&map);
+        let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+        for pose in poses(&map) {
+            let name = format!("{}-{}", stem, pose.name);
+            if let Err(e) = check(&name, &render(&map, &textures, &pose)) {
+                failures.push(e);
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn diff_marks_changed_pixels() {
+    let reference = Framebuffer::new(3, 1);
+    let mut actual = Framebuffer::new(3, 1);
+    actual.set_pixel(1, 0, [TOLERANCE, 0, 0, 255]);
+    actual.set_pixel(2, 0, [TOLERANCE + 1, 0, 0, 255]);
+    let diff = diff_image(&actual, &reference);
+    assert_eq!(diff.get_pixel(1, 0), [0, 0, 0, 255]);
+    assert_eq!(diff.get_pixel(2, 0), [255, 0, 0, 255]);
+}
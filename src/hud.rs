@@ -0,0 +1,204 @@
+use crate::campaign::LevelStats;
+use crate::player::{Player, MAX_ARMOR, MAX_HEALTH};
+use macroquad::prelude::{
+    draw_rectangle, draw_rectangle_lines, draw_text, measure_text, Color, Rect, BLUE, GREEN,
+    LIGHTGRAY, RED, WHITE, YELLOW,
+};
+
+/// Height of the bar, relative to the height of the viewport
+const BAR_HEIGHT: f32 = 0.08;
+/// How long the messages stay on screen, in seconds
+const MESSAGE_DURATION: f32 = 2.0;
+
+/// Color of the key squares, from the name of the key
+fn key_color(key: &str) -> Color {
+    match key {
+        "red" => RED,
+        "blue" => BLUE,
+        "yellow" => YELLOW,
+        "green" => GREEN,
+        _ => WHITE,
+    }
+}
+
+/// Status bar at the bottom of the view: health, armor, ammo, keys and
+/// items, with a short message above it
+#[derive(Default)]
+pub struct Hud {
+    message: Option<(String, f32)>,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows a message for a few seconds, replacing the previous one
+    pub fn show_message(&mut self, message: impl Into<String>) {
+        self.show_message_for(message, MESSAGE_DURATION);
+    }
+
+    pub fn show_message_for(&mut self, message: impl Into<String>, seconds: f32) {
+        self.message = Some((message.into(), seconds));
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        if let Some((_, remaining)) = &mut self.message {
+            *remaining -= delta;
+            if *remaining <= 0.0 {
+                self.message = None;
+            }
+        }
+    }
+
+    /// Part of the viewport above the bar, where the weapon is drawn
+    pub fn view_area(viewport: Rect) -> Rect {
+        Rect::new(
+            viewport.x,
+            viewport.y,
+            viewport.w,
+            viewport.h * (1.0 - BAR_HEIGHT),
+        )
+    }
+
+    pub fn draw(&self, player: &Player, viewport: Rect) {
+        let height = viewport.h * BAR_HEIGHT;
+        let y = viewport.y + viewport.h - height;
+        draw_rectangle(
+            viewport.x,
+            y,
+            viewport.w,
+            height,
+            Color::new(0.1, 0.1, 0.1, 0.85),
+        );
+        let font_size = height * 0.6;
+        let baseline = y + height * 0.7;
+        let mut x = viewport.x + 10.0;
+        let text = |text: &str, color: Color, x: &mut f32| {
+            draw_text(text, *x, baseline, font_size, color);
+            *x += measure_text(text, None, font_size as u16, 1.0).width + font_size;
+        };
+
+        let health_color = if player.get_health() * 4 <= MAX_HEALTH {
+            RED
+        } else {
+            WHITE
+        };
+        text(
+            &format!("HEALTH {}", player.get_health()),
+            health_color,
+            &mut x,
+        );
+        if player.get_armor() > 0 {
+            let armor = format!("ARMOR {}/{}", player.get_armor(), MAX_ARMOR);
+            text(&armor, LIGHTGRAY, &mut x);
+        }
+        let inventory = player.get_inventory();
+        if let Some(weapon) = inventory.get_weapon() {
+            let def = weapon.get_def();
+            let ammo = format!(
+                "{} {}/{}",
+                def.name.to_uppercase(),
+                weapon.get_ammo(),
+                def.max_ammo
+            );
+            text(&ammo, YELLOW, &mut x);
+        }
+        let size = height * 0.5;
+        for key in inventory.get_keys() {
+            let top = y + (height - size) / 2.0;
+            draw_rectangle(x, top, size, size, key_color(key));
+            draw_rectangle_lines(x, top, size, size, 2.0, WHITE);
+            x += size * 1.5;
+        }
+        x += font_size - size * 0.5;
+        for (name, count) in inventory.get_items() {
+            text(&format!("{} x{}", name, count), LIGHTGRAY, &mut x);
+        }
+
+        let message = match &self.message {
+            _ if player.is_dead() => Some("You are dead"),
+            Some((message, _)) => Some(message.as_str()),
+            None => None,
+        };
+        if let Some(message) = message {
+            let size = measure_text(message, None, font_size as u16, 1.0);
+            draw_text(
+                message,
+                viewport.x + (viewport.w - size.width) / 2.0,
+                y - height * 0.5,
+                font_size,
+                WHITE,
+            );
+        }
+    }
+}
+
+/// Darkens the viewport and draws the lines centered on it, a third
+/// of the way down
+fn draw_screen(lines: &[(String, Color)], viewport: Rect) {
+    draw_rectangle(
+        viewport.x,
+        viewport.y,
+        viewport.w,
+        viewport.h,
+        Color::new(0.0, 0.0, 0.0, 0.75),
+    );
+    let font_size = viewport.h * 0.06;
+    let mut y = viewport.y + viewport.h * 0.3;
+    for (line, color) in lines {
+        let size = measure_text(line, None, font_size as u16, 1.0);
+        draw_text(
+            line,
+            viewport.x + (viewport.w - size.width) / 2.0,
+            y,
+            font_size,
+            *color,
+        );
+        y += font_size * 1.6;
+    }
+}
+
+/// What follows a level once Enter is pressed on its statistics
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AfterLevel {
+    NextLevel,
+    /// The last level of a campaign, its completion screen follows
+    CampaignComplete,
+    Quit,
+}
+
+/// Screen shown over the view at the end of a level: time, kills and
+/// secrets found, and the key going to what follows
+pub fn draw_level_stats(stats: &LevelStats, after: AfterLevel, viewport: Rect) {
+    let seconds = stats.time as u32;
+    let lines = [
+        ("LEVEL COMPLETE".to_string(), YELLOW),
+        (format!("TIME {}:{:02}", seconds / 60, seconds % 60), WHITE),
+        (format!("KILLS {}/{}", stats.kills, stats.enemies), WHITE),
+        (
+            format!("SECRETS {}/{}", stats.secrets, stats.total_secrets),
+            WHITE,
+        ),
+        (
+            match after {
+                AfterLevel::NextLevel => "Press Enter for the next level",
+                AfterLevel::CampaignComplete => "Press Enter to finish the campaign",
+                AfterLevel::Quit => "Press Enter to quit",
+            }
+            .to_string(),
+            LIGHTGRAY,
+        ),
+    ];
+    draw_screen(&lines, viewport);
+}
+
+/// Screen shown once the last level of a campaign is complete
+pub fn draw_campaign_complete(name: &str, viewport: Rect) {
+    let lines = [
+        (name.to_uppercase(), YELLOW),
+        ("COMPLETE".to_string(), YELLOW),
+        ("Press any key to quit".to_string(), LIGHTGRAY),
+    ];
+    draw_screen(&lines, viewport);
+}
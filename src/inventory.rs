@@ -0,0 +1,274 @@
+use crate::collision::circles_overlap;
+use crate::entity::{Entity, EntityEvent, Think, ThinkContext};
+use crate::player::PLAYER_RADIUS;
+use crate::weapon::{find_weapon, Weapon, WeaponDef};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Something the player collects by touching it, given by the `pickup`
+/// property of an entity kind: `health <n>`, `armor <n>`, `ammo <weapon> <n>`,
+/// `weapon <name>`, `key <name>` or `item <name> [count]`
+#[derive(Clone, PartialEq, Debug)]
+pub enum Pickup {
+    Health(i32),
+    Armor(i32),
+    Ammo { weapon: String, amount: u32 },
+    Weapon(String),
+    Key(String),
+    Item { name: String, count: u32 },
+}
+
+impl Pickup {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let values: Vec<&str> = value.split_whitespace().collect();
+        let number = |value: &str| {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("invalid amount \"{}\"", value))
+        };
+        let stat = |value: &str| {
+            i32::try_from(number(value)?).map_err(|_| format!("amount too large \"{}\"", value))
+        };
+        let weapon = |name: &str| match find_weapon(name) {
+            Some(_) => Ok(name.to_string()),
+            None => Err(format!("unknown weapon \"{}\"", name)),
+        };
+        match values[..] {
+            ["health", amount] => Ok(Pickup::Health(stat(amount)?)),
+            ["armor", amount] => Ok(Pickup::Armor(stat(amount)?)),
+            ["ammo", name, amount] => Ok(Pickup::Ammo {
+                weapon: weapon(name)?,
+                amount: number(amount)?,
+            }),
+            ["weapon", name] => Ok(Pickup::Weapon(weapon(name)?)),
+            ["key", name] => Ok(Pickup::Key(name.to_string())),
+            ["item", name] => Ok(Pickup::Item {
+                name: name.to_string(),
+                count: 1,
+            }),
+            ["item", name, count] => Ok(Pickup::Item {
+                name: name.to_string(),
+                count: number(count)?,
+            }),
+            _ => Err(format!("invalid pickup \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for Pickup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pickup::Health(amount) => write!(f, "{} health", amount),
+            Pickup::Armor(amount) => write!(f, "{} armor", amount),
+            Pickup::Ammo { weapon, amount } => write!(f, "{} {} ammo", amount, weapon),
+            Pickup::Weapon(name) => write!(f, "the {}", name),
+            Pickup::Key(key) => write!(f, "the {} key", key),
+            Pickup::Item { name, count: 1 } => write!(f, "a {}", name),
+            Pickup::Item { name, count } => write!(f, "{} {}", count, name),
+        }
+    }
+}
+
+/// Behaviour of the pickup entities: reports the player touching them with
+/// [EntityEvent::Pickup], the game removes them once collected
+pub struct PickupThink(pub Pickup);
+
+impl Think for PickupThink {
+    fn think(&mut self, entity: &mut Entity, context: &mut ThinkContext) {
+        if circles_overlap(entity.pos, entity.radius, context.player, PLAYER_RADIUS) {
+            context.events.push(EntityEvent::Pickup {
+                source: entity.get_id(),
+                pickup: self.0.clone(),
+            });
+        }
+    }
+}
+
+/// Weapons, keys and items carried by the player
+#[derive(Clone, Debug, Default)]
+pub struct Inventory {
+    weapons: Vec<Weapon>,
+    current_weapon: usize,
+    keys: BTreeSet<String>,
+    items: BTreeMap<String, u32>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_weapons(&self) -> &[Weapon] {
+        &self.weapons
+    }
+
+    /// The weapon in hand, None if there is no weapon at all
+    pub fn get_weapon(&self) -> Option<&Weapon> {
+        self.weapons.get(self.current_weapon)
+    }
+
+    pub fn get_weapon_mut(&mut self) -> Option<&mut Weapon> {
+        self.weapons.get_mut(self.current_weapon)
+    }
+
+    pub fn next_weapon(&mut self) {
+        if !self.weapons.is_empty() {
+            self.current_weapon = (self.current_weapon + 1) % self.weapons.len();
+        }
+    }
+
+    /// Takes a carried weapon in hand, returns false if it isn't carried
+    pub fn select_weapon(&mut self, name: &str) -> bool {
+        match self.weapons.iter().position(|w| w.get_def().name == name) {
+            Some(index) => {
+                self.current_weapon = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds a weapon with some ammo, or only the ammo if the weapon is
+    /// already carried. Returns false if nothing was added
+    pub fn add_weapon(&mut self, def: WeaponDef, ammo: u32) -> bool {
+        match self
+            .weapons
+            .iter_mut()
+            .find(|w| w.get_def().name == def.name)
+        {
+            Some(weapon) => weapon.add_ammo(ammo) > 0,
+            None => {
+                self.weapons.push(Weapon::new(def, ammo));
+                // a new weapon is taken in hand
+                self.current_weapon = self.weapons.len() - 1;
+                true
+            }
+        }
+    }
+
+    /// Adds ammo to a carried weapon, returns false if the weapon
+    /// isn't carried or is full
+    pub fn add_ammo(&mut self, weapon: &str, amount: u32) -> bool {
+        self.weapons
+            .iter_mut()
+            .find(|w| w.get_def().name == weapon)
+            .is_some_and(|weapon| weapon.add_ammo(amount) > 0)
+    }
+
+    pub fn update_weapons(&mut self, delta: f32) {
+        for weapon in &mut self.weapons {
+            weapon.update(delta);
+        }
+    }
+
+    pub fn has_key(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Returns false if the key was already carried
+    pub fn add_key(&mut self, key: &str) -> bool {
+        self.keys.insert(key.to_string())
+    }
+
+    /// Drops every key, they only open the doors of their level
+    pub fn clear_keys(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Sorted by name
+    pub fn get_keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.iter().map(String::as_str)
+    }
+
+    pub fn add_item(&mut self, name: &str, count: u32) {
+        *self.items.entry(name.to_string()).or_default() += count;
+    }
+
+    /// Removes some items, returns false if there aren't enough of them
+    pub fn remove_item(&mut self, name: &str, count: u32) -> bool {
+        match self.items.get_mut(name) {
+            Some(carried) if *carried >= count => {
+                *carried -= count;
+                if *carried == 0 {
+                    self.items.remove(name);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get_item_count(&self, name: &str) -> u32 {
+        self.items.get(name).copied().unwrap_or(0)
+    }
+
+    /// Sorted by name
+    pub fn get_items(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.items
+            .iter()
+            .map(|(name, count)| (name.as_str(), *count))
+    }
+}
+
+#[cfg(test)]
+mod inventory_tests {
+    use super::*;
+    use crate::weapon::{LAUNCHER, PISTOL};
+
+    #[test]
+    fn parse_pickups() {
+        assert_eq!(Pickup::parse("health 25"), Ok(Pickup::Health(25)));
+        assert_eq!(
+            Pickup::parse("ammo  pistol 10"),
+            Ok(Pickup::Ammo {
+                weapon: "pistol".to_string(),
+                amount: 10
+            })
+        );
+        assert_eq!(
+            Pickup::parse("item gem"),
+            Ok(Pickup::Item {
+                name: "gem".to_string(),
+                count: 1
+            })
+        );
+        for invalid in [
+            "health",
+            "health -5",
+            "health 3000000000",
+            "armor 2147483648",
+            "weapon bfg",
+            "ammo pistol",
+            "gold 5",
+        ] {
+            assert!(Pickup::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn weapons_and_items() {
+        let mut inventory = Inventory::new();
+        assert!(inventory.get_weapon().is_none());
+        assert!(!inventory.add_ammo("pistol", 5));
+        assert!(inventory.add_weapon(PISTOL, 10));
+        assert!(inventory.add_weapon(LAUNCHER, 2));
+        assert_eq!(inventory.get_weapon().unwrap().get_def().name, "launcher");
+        inventory.next_weapon();
+        assert_eq!(inventory.get_weapon().unwrap().get_ammo(), 10);
+        // picking a weapon again gives its ammo
+        assert!(inventory.add_weapon(PISTOL, 10));
+        assert_eq!(inventory.get_weapon().unwrap().get_ammo(), 20);
+        assert!(inventory.add_ammo("launcher", 100));
+        assert!(!inventory.add_ammo("launcher", 1));
+
+        assert!(inventory.add_key("red"));
+        assert!(!inventory.add_key("red"));
+        assert!(inventory.has_key("red") && !inventory.has_key("blue"));
+
+        inventory.add_item("gem", 2);
+        assert!(!inventory.remove_item("gem", 3));
+        assert!(inventory.remove_item("gem", 2));
+        assert_eq!(inventory.get_item_count("gem"), 0);
+        assert_eq!(inventory.get_items().count(), 0);
+    }
+}
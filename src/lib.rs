@@ -0,0 +1,36 @@
+//! Raycasting engine used by the `raycaster` game, also used by the benchmarks
+
+pub mod audio;
+pub mod automap;
+pub mod camera;
+pub mod campaign;
+pub mod capture;
+pub mod collision;
+pub mod dda;
+pub mod debug_view;
+pub mod enemy;
+pub mod entity;
+pub mod framebuffer;
+pub mod gameloop;
+pub mod generator;
+/// Golden-image tests of the renderer, see the README
+#[cfg(test)]
+mod golden;
+pub mod hud;
+pub mod inventory;
+pub mod map;
+pub mod palette;
+pub mod pathfinding;
+pub mod player;
+pub mod postfx;
+pub mod render;
+pub mod renderer;
+pub mod replay;
+pub mod resolution;
+pub mod save;
+pub mod script;
+pub mod script_api;
+pub mod textures;
+pub mod trigger;
+pub mod vector;
+pub mod weapon;
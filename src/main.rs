@@ -1,23 +1,196 @@
-mod map;
-mod player;
-mod render;
-mod vector;
-
 use macroquad::prelude::*;
-use map::Map;
-use player::Player;
+use raycaster::audio::{AudioOutput, NoAudio};
+use raycaster::automap::Automap;
+use raycaster::camera::Camera;
+use raycaster::campaign::{Campaign, CarriedPlayer, LevelStats};
+use raycaster::capture::{next_screenshot_path, save_png, RecordFormat, Recorder};
+use raycaster::debug_view::{draw_debug_view, DebugViewMode};
+use raycaster::enemy::spawn_map_entities;
+use raycaster::entity::{Entities, EntityEvent, ThinkContext};
+use raycaster::framebuffer::Framebuffer;
+use raycaster::gameloop::{interpolated_camera, FixedTimestep, PlayerState};
+use raycaster::generator::{generate, Generator, GeneratorOptions};
+use raycaster::hud::{draw_campaign_complete, draw_level_stats, AfterLevel, Hud};
+use raycaster::map::Map;
+use raycaster::palette::{PaletteFlash, PaletteMode};
+use raycaster::pathfinding::{Connectivity, FlowField, NavGrid, Noise};
+use raycaster::player::{Player, MOVE_SPEED};
+use raycaster::postfx::{DamageFlash, PostProcess, Shake};
+use raycaster::render::{draw_view, AtlasTexture, PostShader, ScreenTexture};
+use raycaster::renderer::{Renderer, FLAT_COLORS};
+use raycaster::replay::{Action, InputFrame, Replay};
+use raycaster::resolution::{DynamicResolution, RenderResolution};
+use raycaster::save::{save_file_stem, SaveGame};
+use raycaster::script::{Script, ScriptError, Value};
+use raycaster::script_api::{load_script, GameHost};
+use raycaster::textures::TextureManager;
+use raycaster::trigger::{set_tile, EventQueue, MapEvent, Triggers};
+use raycaster::vector::Vector;
+use raycaster::weapon::{fire, move_projectile, WeaponView};
 use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Value following the given command line flag
+fn arg_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
 
-use crate::player::RayCastResult;
+/// Map generated with `--generate <kind> [--seed N] [--size WxH] [--output path]`
+fn generate_map(kind: &str) -> Map {
+    let generator = Generator::from_name(kind).unwrap_or_else(|| {
+        let names: Vec<_> = Generator::ALL.iter().map(|g| g.name()).collect();
+        panic!(
+            "Unknown generator {}, expected one of {}",
+            kind,
+            names.join(", ")
+        )
+    });
+    let mut options = GeneratorOptions {
+        seed: arg_value("--seed")
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(|| macroquad::miniquad::date::now() as u64),
+        ..Default::default()
+    };
+    if let Some((width, height)) = arg_value("--size")
+        .as_deref()
+        .and_then(|size| size.split_once('x'))
+    {
+        options.width = width.parse().unwrap_or(options.width);
+        options.height = height.parse().unwrap_or(options.height);
+    }
+    let map = generate(generator, &options);
+    if let Some(output) = arg_value("--output") {
+        let written = File::create(&output).and_then(|file| map.write(&mut BufWriter::new(file)));
+        match written {
+            Ok(()) => println!("Map written to {}", output),
+            Err(e) => eprintln!("Error writing map {}: {}", output, e),
+        }
+    }
+    map
+}
 
-fn open_map() -> Map {
-    let map = File::open("./maps/testMap.map").unwrap();
-    match Map::parse(map, 64.0) {
-        Ok(map) => map,
-        Err(e) => panic!("Error parsing map: {:?}", e),
+/// Campaign given with `--campaign <path>` or the single map given with
+/// `--map <path>`, defaults to the episode of the test map
+fn open_campaign() -> Campaign {
+    if let Some(path) = arg_value("--map") {
+        return Campaign::new(path.clone(), vec![PathBuf::from(path)]);
+    }
+    let path = arg_value("--campaign").unwrap_or("./maps/episode.campaign".to_string());
+    match Campaign::open(&path) {
+        Ok(campaign) => campaign,
+        Err(e) => panic!("Error reading campaign {}: {}", path, e),
     }
 }
 
+/// Player on the spawn point of the map, or at a fixed position
+/// for maps without one
+fn spawn_player(map: &Map) -> Player {
+    match map.get_spawn() {
+        Some(spawn) => {
+            let center =
+                map.tile_corner(spawn.x, spawn.y) + Vector::new(0.5, 0.5) * map.get_tile_size();
+            Player::new(center.x, center.y, spawn.angle)
+        }
+        None => Player::new(100.0, 100.0, 0.0),
+    }
+}
+
+/// Number of render threads given with `--threads N`,
+/// defaults to 0 (one per core)
+fn thread_count() -> usize {
+    arg_value("--threads")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Resolutions cycled with the P key
+const RESOLUTIONS: [RenderResolution; 4] = [
+    RenderResolution::Window,
+    RenderResolution::Scale(0.5),
+    RenderResolution::Fixed(640, 400),
+    RenderResolution::Fixed(320, 200),
+];
+/// Column steps of the line renderer cycled with the L key
+const COLUMN_STEPS: [usize; 3] = [1, 2, 4];
+/// Keys toggling the post effects, in the order of the pipeline
+const POST_EFFECT_KEYS: [KeyCode; 6] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+];
+
+fn automap_path(map: &Map) -> String {
+    format!("./saves/{}.automap", save_file_stem(map))
+}
+
+/// Loads the explored tiles saved for this map, if any
+fn load_automap(map: &Map) -> Automap {
+    File::open(automap_path(map))
+        .and_then(|file| Automap::read(BufReader::new(file), map))
+        .unwrap_or_else(|_| Automap::new(map))
+}
+
+fn save_automap(map: &Map, automap: &Automap) {
+    let result = std::fs::create_dir_all("./saves")
+        .and_then(|_| File::create(automap_path(map)))
+        .and_then(|file| automap.write(&mut BufWriter::new(file)));
+    if let Err(e) = result {
+        eprintln!("Unable to save the automap: {}", e);
+    }
+}
+
+/// Only one frame every `RECORD_EVERY` frames is recorded
+const RECORD_EVERY: usize = 2;
+const RECORD_FPS: u32 = 30;
+
+fn take_screenshot(map: &Map, framebuffer: &Framebuffer) {
+    let result = next_screenshot_path(Path::new("./screenshots"), &save_file_stem(map))
+        .and_then(|path| save_png(framebuffer, &path).map(|_| path));
+    match result {
+        Ok(path) => println!("Screenshot saved to {}", path.display()),
+        Err(e) => eprintln!("Unable to save the screenshot: {}", e),
+    }
+}
+
+fn finish_recording(recorder: Recorder) {
+    let path = recorder.get_path().to_path_buf();
+    let frames = recorder.get_recorded();
+    match recorder.finish() {
+        Ok(()) => println!("Recorded {} frames to {}", frames, path.display()),
+        Err(e) => eprintln!("Unable to finish the recording: {}", e),
+    }
+}
+
+/// Starts a recording if there is none, otherwise stops the current one
+fn toggle_recording(recorder: &mut Option<Recorder>, map: &Map, format: RecordFormat) {
+    if let Some(recorder) = recorder.take() {
+        finish_recording(recorder);
+        return;
+    }
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let name = format!("./recordings/{}-{}", save_file_stem(map), time);
+    let path = match format {
+        RecordFormat::Png => name,
+        RecordFormat::Y4m => name + ".y4m",
+    };
+    *recorder = Some(Recorder::new(
+        format,
+        Path::new(&path),
+        RECORD_EVERY,
+        RECORD_FPS,
+    ));
+}
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "RayCaster".to_owned(),
@@ -27,81 +200,709 @@ fn window_conf() -> Conf {
     }
 }
 
-fn move_player(player: &mut Player, map: &Map) {
-    if is_key_down(KeyCode::Q) {
-        player.rotate(-0.1);
-    }
-    if is_key_down(KeyCode::D) {
-        player.rotate(0.1);
-    }
-    if is_key_down(KeyCode::Z) {
-        player.move_forward(100.0 * get_frame_time(), &map);
-    }
-    if is_key_down(KeyCode::S) {
-        player.move_backward(100.0 * get_frame_time(), &map);
+/// Keys bound to each action
+const ACTION_KEYS: [(Action, KeyCode); 11] = [
+    (Action::Forward, KeyCode::Z),
+    (Action::Backward, KeyCode::S),
+    (Action::TurnLeft, KeyCode::Q),
+    (Action::TurnRight, KeyCode::D),
+    (Action::ZoomIn, KeyCode::R),
+    (Action::ZoomOut, KeyCode::F),
+    (Action::LookUp, KeyCode::Up),
+    (Action::LookDown, KeyCode::Down),
+    (Action::Crouch, KeyCode::LeftControl),
+    (Action::Fire, KeyCode::Space),
+    (Action::Use, KeyCode::E),
+];
+
+fn read_input(delta: f32) -> InputFrame {
+    let mut input = InputFrame::new(delta);
+    for (action, key) in ACTION_KEYS {
+        input.set(action, is_key_down(key));
     }
+    input
 }
 
-fn draw_rays(map: &Map, player: &Player) {
-    let mut angle = -std::f32::consts::PI / 4.0;
-    let increment = std::f32::consts::PI / 2.0 / screen_width();
+fn save_replay(map: &Map, replay: &Replay) -> Option<PathBuf> {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let path = PathBuf::from(format!("./replays/{}-{}.replay", save_file_stem(map), time));
+    let result = std::fs::create_dir_all("./replays")
+        .and_then(|_| File::create(&path))
+        .and_then(|file| replay.write(&mut BufWriter::new(file)));
+    match result {
+        Ok(()) => {
+            println!("Replay saved to {}", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            eprintln!("Unable to save the replay: {}", e);
+            None
+        }
+    }
+}
 
-    for i in 0..screen_width() as i32 {
-        let ray = player.raycast(&map, angle);
-        angle += increment;
-        match ray {
-            RayCastResult::NoHit => {}
-            RayCastResult::Hit(distance, _, side) => {
-                let height = screen_height() / distance;
-                let color = if side {
-                    Color::from_rgba(255, 0, 0, 255)
-                } else {
-                    Color::from_rgba(190, 0, 0, 255)
-                };
-                draw_line(
-                    i as f32,
-                    screen_height() / 2.0 - height / 2.0,
-                    i as f32,
-                    screen_height() / 2.0 + height / 2.0,
-                    1.0,
-                    color,
-                );
-            }
+fn load_replay(map: &Map, path: &Path) -> Option<Replay> {
+    let replay = File::open(path).and_then(|file| Replay::read(BufReader::new(file)));
+    match replay {
+        Ok(replay) if replay.get_map() == map.get_name() => Some(replay),
+        Ok(replay) => {
+            eprintln!("The replay was recorded on the map {}", replay.get_map());
+            None
+        }
+        Err(e) => {
+            eprintln!("Unable to load the replay {}: {}", path.display(), e);
+            None
         }
     }
 }
 
-fn debug_infos() {
+fn quick_save_path(map: &Map) -> String {
+    format!("./saves/{}.save", save_file_stem(map))
+}
+
+fn write_quick_save(map: &Map, save: &SaveGame) -> std::io::Result<()> {
+    std::fs::create_dir_all("./saves")
+        .and_then(|_| File::create(quick_save_path(map)))
+        .and_then(|file| save.write(&mut BufWriter::new(file)))
+}
+
+fn read_quick_save(map: &Map) -> std::io::Result<SaveGame> {
+    File::open(quick_save_path(map)).and_then(|file| SaveGame::read(BufReader::new(file), map))
+}
+
+fn debug_infos(renderer: &Renderer, framebuffer: &Framebuffer, post: &PostProcess) {
     let fps = get_fps();
     let render_time = get_frame_time();
     let render_time_ms = render_time * 1000.0;
     let fps_text = format!("FPS: {:.2}", fps);
     let render_time_text = format!("Render time: {:.2}ms", render_time_ms);
+    let threads_text = format!(
+        "Render threads: {}, resolution: {}x{}",
+        renderer.get_threads(),
+        framebuffer.get_width(),
+        framebuffer.get_height()
+    );
     draw_text(&fps_text, 10.0, 20.0, 20.0, GREEN);
     draw_text(&render_time_text, 10.0, 40.0, 20.0, GREEN);
+    draw_text(&threads_text, 10.0, 60.0, 20.0, GREEN);
+
+    let effects: Vec<String> = post
+        .get_names()
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| post.is_enabled(name))
+        .map(|(i, name)| format!("{}:{}", i + 1, name))
+        .collect();
+    let effects_text = format!("Post effects: {}", effects.join(" "));
+    draw_text(&effects_text, 10.0, 80.0, 20.0, GREEN);
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let mut map = open_map();
-    let mut player = Player::new(100.0, 100.0, 0.0);
+fn replay_infos(text: &str) {
+    draw_text(text, 10.0, 120.0, 20.0, ORANGE);
+}
+
+fn recording_infos(recorder: &Recorder) {
+    let text = format!("REC {} frames", recorder.get_recorded());
+    draw_text(&text, 10.0, 100.0, 20.0, RED);
+}
+
+/// Red flash and screen shake of the player being hurt
+fn damage_effects(flash: &mut PaletteFlash, post: &mut PostProcess) {
+    flash.start([255, 0, 0, 255], 0.6);
+    if let Some(damage) = post.get_mut::<DamageFlash>() {
+        damage.start([255, 0, 0, 255], 0.5);
+    }
+    if let Some(shake) = post.get_mut::<Shake>() {
+        shake.start(0.02, 0.3);
+    }
+}
+
+/// Opens the locked door touched by the player if they have its key
+fn open_door(
+    map: &mut Map,
+    nav: &mut NavGrid,
+    player: &Player,
+    door: (usize, usize),
+    hud: &mut Hud,
+) {
+    let Some(key) = map.get_lock(map[door]).map(str::to_string) else {
+        return;
+    };
+    if player.get_inventory().has_key(&key) {
+        set_tile(map, nav, door, 0);
+        hud.show_message(format!("Opened with the {} key", key));
+    } else {
+        hud.show_message(format!("You need the {} key", key));
+    }
+}
+
+/// Script errors don't stop the game, they are printed and shown on the HUD
+fn report_script_error(error: ScriptError, hud: &mut Hud) {
+    eprintln!("Script error: {}", error);
+    hud.show_message(format!("Script error: {}", error));
+}
+
+/// Saves what must be kept when leaving a level
+fn leave_level(map: &Map, automap: &Automap, recorder: &mut Option<Recorder>) {
+    save_automap(map, automap);
+    if let Some(recorder) = recorder.take() {
+        finish_recording(recorder);
+    }
+}
+
+/// Plays a level until it is finished, returns what the player carries
+/// to the next one, or None if the game was quit
+async fn play_level(
+    mut map: Map,
+    carried: Option<&CarriedPlayer>,
+    after: AfterLevel,
+) -> Option<CarriedPlayer> {
+    let textures = TextureManager::for_map(&map);
+    for error in textures.get_errors() {
+        eprintln!("Texture error: {}", error);
+    }
+    let atlas = AtlasTexture::new(&textures);
+    let mut player = spawn_player(&map);
+    if let Some(carried) = carried {
+        carried.apply(&mut player);
+    }
+    let security_camera = Camera::new(660.0, 660.0, -3.0 * std::f32::consts::FRAC_PI_4);
+    let mut show_security_camera = false;
+    let mut renderer = Renderer::new(thread_count());
+    let mut framebuffer = Framebuffer::new(0, 0);
+    let mut screen_texture = ScreenTexture::default();
+    let mut use_framebuffer = true;
+    let mut resolution = 0;
+    let mut dynamic_resolution = DynamicResolution::new(1.0 / 60.0);
+    let mut column_step = 0;
+    let mut debug_view = DebugViewMode::Off;
+    let mut automap = load_automap(&map);
+    let mut show_automap = false;
+    let mut rotate_minimap = true;
+    let palette_mode = PaletteMode::new(&textures, &FLAT_COLORS);
+    let mut use_palette = false;
+    let mut flash = PaletteFlash::new();
+    let mut post = PostProcess::standard();
+    let post_shader = PostShader::new()
+        .map_err(|e| eprintln!("Post-processing shader unavailable: {}", e))
+        .ok();
+    let mut use_post_shader = false;
+    let mut recorder: Option<Recorder> = None;
+    let mut replay_recording: Option<Replay> = None;
+    let mut last_replay = arg_value("--replay").map(PathBuf::from);
+    // replay being played and the index of its next frame
+    let mut playback: Option<(Replay, usize)> = None;
+    // the replay given on the command line is played once the level is ready
+    let mut start_playback = last_replay.is_some();
+    let mut entities = Entities::new(&map);
+    for error in spawn_map_entities(&map, &textures, &mut entities) {
+        eprintln!("{}", error);
+    }
+    let mut nav = NavGrid::new(&map, Connectivity::Eight);
+    let (mut triggers, errors) = Triggers::from_map(&map);
+    for error in errors {
+        eprintln!("{}", error);
+    }
+    let mut map_events = EventQueue::new();
+    // statistics of the level once it's complete
+    let mut level_stats = None;
+    // time played on the level, in seconds
+    let mut level_time = 0.0;
+    let mut player_field = FlowField::new();
+    let mut weapon_view = WeaponView::new();
+    let mut hud = Hud::new();
+    // there is no audio backend yet
+    let mut audio = NoAudio;
+    let mut script = match load_script(&map) {
+        Some(Ok(script)) => Some(script),
+        Some(Err(error)) => {
+            report_script_error(error, &mut hud);
+            None
+        }
+        None => None,
+    };
+    // runs the top level statements or calls a callback of the level
+    // script, if the map has one, with access to the game
+    macro_rules! run_script {
+        (init) => {
+            run_script!(|script: &mut Script, host| script.init(host))
+        };
+        ($name:expr, $args:expr) => {
+            run_script!(|script: &mut Script, host| script.call(host, $name, $args).map(|_| ()))
+        };
+        ($run:expr) => {
+            if let Some(script) = &mut script {
+                let mut host = GameHost::new(
+                    &mut map,
+                    &mut nav,
+                    &mut player,
+                    &mut entities,
+                    &textures,
+                    &mut map_events,
+                );
+                if let Err(error) = $run(script, &mut host) {
+                    report_script_error(error, &mut hud);
+                }
+            }
+        };
+    }
+    run_script!(init);
+    // noises made during a tick, heard by the entities on the next one
+    let mut noises: Vec<Noise> = Vec::new();
+    let mut timestep = FixedTimestep::default();
+    let mut previous_state = PlayerState::of(&player);
+    // puts the level back in the state of a saved game, for the quick
+    // loads and the replays. The player is restored by the caller
+    macro_rules! restore_level {
+        ($save:ident) => {
+            $save
+                .restore_level(&mut map, &mut nav, &textures)
+                .map(|restored| {
+                    entities = restored;
+                    $save.restore_logic(&mut triggers, script.as_mut());
+                    automap = $save.automap.clone();
+                    level_time = $save.time;
+                    map_events = EventQueue::new();
+                    noises.clear();
+                    level_stats = None;
+                })
+        };
+    }
     loop {
         if is_key_pressed(KeyCode::Escape) {
-            return;
+            leave_level(&map, &automap, &mut recorder);
+            return None;
+        }
+        if level_stats.is_some() && is_key_pressed(KeyCode::Enter) {
+            leave_level(&map, &automap, &mut recorder);
+            return Some(CarriedPlayer::of(&player));
         }
 
-        move_player(&mut player, &map);
+        if is_key_pressed(KeyCode::Tab) {
+            show_security_camera = !show_security_camera;
+        }
+        if is_key_pressed(KeyCode::B) {
+            use_framebuffer = !use_framebuffer;
+        }
+        if is_key_pressed(KeyCode::P) {
+            resolution = (resolution + 1) % RESOLUTIONS.len();
+        }
+        if is_key_pressed(KeyCode::O) {
+            dynamic_resolution.set_enabled(!dynamic_resolution.is_enabled());
+        }
+        if is_key_pressed(KeyCode::L) {
+            column_step = (column_step + 1) % COLUMN_STEPS.len();
+        }
+        if is_key_pressed(KeyCode::F1) {
+            debug_view = debug_view.next();
+        }
+        if is_key_pressed(KeyCode::M) {
+            show_automap = !show_automap;
+        }
+        if is_key_pressed(KeyCode::N) {
+            rotate_minimap = !rotate_minimap;
+        }
+        if is_key_pressed(KeyCode::I) {
+            use_palette = !use_palette;
+        }
+        for (key, name) in POST_EFFECT_KEYS.iter().zip(post.get_names()) {
+            if is_key_pressed(*key) {
+                post.toggle(name);
+            }
+        }
+        if is_key_pressed(KeyCode::K) && post_shader.is_some() {
+            use_post_shader = !use_post_shader;
+        }
+        // flash demos: damage and pickup
+        if is_key_pressed(KeyCode::F2) {
+            damage_effects(&mut flash, &mut post);
+        }
+        if is_key_pressed(KeyCode::F3) {
+            flash.start([255, 200, 0, 255], 0.4);
+        }
+        if is_key_pressed(KeyCode::X) {
+            player.get_inventory_mut().next_weapon();
+        }
+        if is_key_pressed(KeyCode::F10) {
+            toggle_recording(&mut recorder, &map, RecordFormat::Y4m);
+        }
+        if is_key_pressed(KeyCode::F11) {
+            toggle_recording(&mut recorder, &map, RecordFormat::Png);
+        }
+        let screenshot = is_key_pressed(KeyCode::F12);
+        if is_key_pressed(KeyCode::F6) {
+            match replay_recording.take() {
+                Some(replay) => last_replay = save_replay(&map, &replay).or(last_replay),
+                None => {
+                    let level = SaveGame::capture(
+                        &map,
+                        &player,
+                        &entities,
+                        &triggers,
+                        script.as_ref(),
+                        &automap,
+                        level_time,
+                    );
+                    replay_recording = Some(Replay::new(&player, &level));
+                }
+            }
+        }
+        let playback_key = is_key_pressed(KeyCode::F7) && replay_recording.is_none();
+        if std::mem::take(&mut start_playback) || playback_key {
+            playback = last_replay
+                .as_ref()
+                .and_then(|path| load_replay(&map, path))
+                .map(|replay| (replay, 0));
+            if let Some((replay, _)) = &playback {
+                // the whole level goes back to the start of the recording
+                let started = replay
+                    .start_level(&map)
+                    .map_err(|e| e.to_string())
+                    .and_then(|save| restore_level!(save).map(|_| save));
+                match started {
+                    Ok(save) => {
+                        let mut start = replay.start_player();
+                        save.restore_player(&mut start);
+                        player = start;
+                        previous_state = PlayerState::of(&player);
+                    }
+                    Err(e) => {
+                        eprintln!("Unable to play the replay: {}", e);
+                        playback = None;
+                    }
+                }
+            }
+        }
+        if is_key_pressed(KeyCode::F5) {
+            if player.is_dead() || level_stats.is_some() {
+                hud.show_message("You can't save now");
+            } else {
+                let save = SaveGame::capture(
+                    &map,
+                    &player,
+                    &entities,
+                    &triggers,
+                    script.as_ref(),
+                    &automap,
+                    level_time,
+                );
+                match write_quick_save(&map, &save) {
+                    Ok(()) => hud.show_message("Game saved"),
+                    Err(e) => {
+                        eprintln!("Unable to save the game: {}", e);
+                        hud.show_message("Unable to save the game");
+                    }
+                }
+            }
+        }
+        if is_key_pressed(KeyCode::F9) {
+            // the replays don't go across a load
+            if let Some(replay) = replay_recording.take() {
+                last_replay = save_replay(&map, &replay).or(last_replay);
+            }
+            playback = None;
+            let loaded = read_quick_save(&map)
+                .map_err(|e| e.to_string())
+                .and_then(|save| restore_level!(save).map(|_| save));
+            match loaded {
+                Ok(save) => {
+                    save.restore_player(&mut player);
+                    previous_state = PlayerState::of(&player);
+                    hud.show_message("Game loaded");
+                }
+                Err(e) => {
+                    eprintln!("Unable to load the game: {}", e);
+                    hud.show_message("Unable to load the game");
+                }
+            }
+        }
+        flash.update(get_frame_time());
+        hud.update(get_frame_time());
+        post.update(get_frame_time());
+
+        // the simulation runs at a fixed rate, the keys held during the
+        // frame are used for every tick of the frame
+        for _ in 0..timestep.advance(get_frame_time()) {
+            let input = match &mut playback {
+                Some((replay, next)) => {
+                    let input = replay.get_frames().get(*next).copied();
+                    *next += 1;
+                    input
+                }
+                None => Some(read_input(timestep.get_step())),
+            };
+            previous_state = PlayerState::of(&player);
+            if let Some(tile) = map.to_map_coordinates(&player.get_pos()) {
+                player_field.update(&nav, (tile.x, tile.y));
+            }
+            let mut context = ThinkContext::new(
+                &map,
+                &nav,
+                &player_field,
+                player.get_pos(),
+                timestep.get_step(),
+            );
+            context.noises = &noises;
+            entities.update(&mut context);
+            for event in context.events {
+                match event {
+                    EntityEvent::AttackPlayer { damage, .. } => {
+                        player.damage(damage);
+                        damage_effects(&mut flash, &mut post);
+                    }
+                    EntityEvent::ProjectileMoved {
+                        source,
+                        from,
+                        to,
+                        damage,
+                        height,
+                    } => move_projectile(&map, &mut entities, source, from, to, damage, height),
+                    EntityEvent::Pickup { source, pickup } => {
+                        if player.collect(&pickup) {
+                            if let Some(entity) = entities.get_mut(source) {
+                                entity.remove();
+                            }
+                            flash.start([255, 200, 0, 255], 0.4);
+                            hud.show_message(format!("Picked up {}", pickup));
+                        }
+                    }
+                }
+            }
+            noises.clear();
+            player
+                .get_inventory_mut()
+                .update_weapons(timestep.get_step());
+            match input {
+                // the player can't move anymore once dead or at the end of the level
+                Some(_) if player.is_dead() || level_stats.is_some() => {}
+                Some(input) => {
+                    level_time += input.delta;
+                    let previous_pos = player.get_pos();
+                    player.update(&input, &map);
+                    let offset = player.get_pos() - previous_pos;
+                    let moved = (offset.x * offset.x + offset.y * offset.y).sqrt();
+                    weapon_view.update(input.delta, moved / (MOVE_SPEED * input.delta));
+                    if let Some(door) = player.touched_door(&map) {
+                        open_door(&mut map, &mut nav, &player, door, &mut hud);
+                    }
+                    let tile = map
+                        .to_map_coordinates(&player.get_pos())
+                        .map(|tile| (tile.x, tile.y));
+                    let facing = player.facing_tile(&map);
+                    let delta = [Value::Number(input.delta as f64)];
+                    run_script!("on_tick", &delta);
+                    triggers.update(tile, facing, input.is_down(Action::Use), &mut map_events);
+                    while let Some(event) = map_events.pop() {
+                        match event {
+                            MapEvent::SetTile { x, y, tile } => {
+                                set_tile(&mut map, &mut nav, (x, y), tile)
+                            }
+                            MapEvent::Teleport { x, y, angle } => {
+                                let size = map.get_tile_size();
+                                let center = map.tile_corner(x, y) + Vector::new(size, size) * 0.5;
+                                player.teleport(center, angle);
+                                // no interpolation across the map
+                                previous_state = PlayerState::of(&player);
+                            }
+                            MapEvent::Sound(name) => audio.play(&name),
+                            MapEvent::Message(message) => hud.show_message(message),
+                            MapEvent::EndLevel => {
+                                level_stats =
+                                    Some(LevelStats::count(&map, &entities, &triggers, level_time))
+                            }
+                            MapEvent::Triggered(name) => {
+                                let args = [Value::Str(name)];
+                                run_script!("on_trigger", &args);
+                            }
+                            MapEvent::Used { x, y } => {
+                                let args = [Value::Number(x as f64), Value::Number(y as f64)];
+                                run_script!("on_use", &args);
+                            }
+                        }
+                    }
+                    let (pos, angle) = (player.get_pos(), player.get_angle());
+                    let height = player.get_camera().get_height();
+                    if let Some(weapon) = player.get_inventory_mut().get_weapon_mut() {
+                        if input.is_down(Action::Fire)
+                            && fire(weapon, &map, &mut entities, pos, angle, height)
+                        {
+                            weapon_view.shoot();
+                            if let Some(tile) = map.to_map_coordinates(&pos) {
+                                let loudness = weapon.get_def().loudness;
+                                noises.push(Noise::new(&nav, pos, (tile.x, tile.y), loudness));
+                            }
+                        }
+                    }
+                    if let Some(replay) = &mut replay_recording {
+                        replay.push(input);
+                    }
+                }
+                None => playback = None,
+            }
+        }
+        let view_camera = interpolated_camera(&player, &previous_state, timestep.get_alpha());
+        renderer.set_sprites(&entities.sprites(timestep.get_alpha()));
         let width = screen_width();
         let height = screen_height();
-        map.auto_tile_size(width, height);
         clear_background(BLACK);
-        // map.render();
-        // player.render();
 
-        draw_rays(&map, &player);
+        let (viewport, debug_viewport) = match debug_view {
+            DebugViewMode::Split => (
+                Rect::new(0.0, 0.0, width / 2.0, height),
+                Rect::new(width / 2.0, 0.0, width / 2.0, height),
+            ),
+            _ => (
+                Rect::new(0.0, 0.0, width, height),
+                Rect::new(0.0, 0.0, width, height),
+            ),
+        };
+        let columns = if use_framebuffer {
+            let scale = dynamic_resolution.update(get_frame_time());
+            let (render_width, render_height) =
+                RESOLUTIONS[resolution].size(viewport.w, viewport.h, scale);
+            framebuffer.resize(render_width, render_height);
+            if use_palette {
+                renderer.render_indexed(
+                    &map,
+                    &view_camera,
+                    &textures,
+                    &palette_mode,
+                    &flash.apply(palette_mode.get_palette()),
+                    &mut framebuffer,
+                );
+            } else {
+                renderer.render(&map, &view_camera, &textures, &mut framebuffer);
+            }
+            match post_shader.as_ref().filter(|_| use_post_shader) {
+                Some(shader) => {
+                    shader.set_params(&post.shader_params());
+                    screen_texture.draw(&framebuffer, viewport, Some(shader));
+                }
+                None => {
+                    post.apply(&mut framebuffer);
+                    screen_texture.draw(&framebuffer, viewport, None);
+                }
+            }
+            render_width
+        } else {
+            draw_view(
+                &map,
+                &view_camera,
+                &textures,
+                &atlas,
+                viewport,
+                COLUMN_STEPS[column_step],
+            );
+            viewport.w as usize / COLUMN_STEPS[column_step]
+        };
+        automap.record_view(&map, &view_camera, columns);
+
+        // captures always use the CPU renderer, without the shader effects
+        if screenshot || recorder.is_some() {
+            if !use_framebuffer {
+                framebuffer.resize(viewport.w as usize, viewport.h as usize);
+                renderer.render(&map, &view_camera, &textures, &mut framebuffer);
+                post.apply(&mut framebuffer);
+            }
+            if screenshot {
+                take_screenshot(&map, &framebuffer);
+            }
+            if let Some(Err(e)) = recorder.as_mut().map(|r| r.capture(&framebuffer)) {
+                eprintln!("Recording stopped: {}", e);
+                recorder = None;
+            }
+        }
+
+        if let Some(weapon) = player.get_inventory().get_weapon() {
+            weapon_view.draw(weapon, Hud::view_area(viewport));
+        }
+        hud.draw(&player, viewport);
+
+        match debug_view {
+            DebugViewMode::Off => {}
+            DebugViewMode::Overlay => {
+                draw_debug_view(&map, &entities, &player, columns, debug_viewport, 0.5);
+            }
+            DebugViewMode::Split => {
+                draw_debug_view(&map, &entities, &player, columns, debug_viewport, 1.0);
+            }
+        }
+        if show_security_camera {
+            let inset = Rect::new(width * 0.7, height * 0.7, width * 0.28, height * 0.28);
+            draw_rectangle(inset.x, inset.y, inset.w, inset.h, DARKGRAY);
+            draw_view(&map, &security_camera, &textures, &atlas, inset, 1);
+            draw_rectangle_lines(inset.x, inset.y, inset.w, inset.h, 2.0, WHITE);
+        }
+
+        if show_automap {
+            automap.draw_automap(&map, &view_camera, Rect::new(0.0, 0.0, width, height));
+        } else {
+            let radius = width.min(height) * 0.12;
+            let center = Vector::new(width - radius - 10.0, radius + 10.0);
+            automap.draw_minimap(&map, &view_camera, center, radius, rotate_minimap);
+        }
+
+        if let Some(stats) = &level_stats {
+            draw_level_stats(stats, after, Rect::new(0.0, 0.0, width, height));
+        }
 
-        debug_infos();
+        debug_infos(&renderer, &framebuffer, &post);
+        if let Some(recorder) = &recorder {
+            recording_infos(recorder);
+        }
+        if let Some(replay) = &replay_recording {
+            replay_infos(&format!(
+                "Recording replay: {} frames",
+                replay.get_frames().len()
+            ));
+        } else if let Some((replay, next)) = &playback {
+            replay_infos(&format!("Replay: {}/{}", next, replay.get_frames().len()));
+        }
 
         next_frame().await
     }
 }
+
+/// Shows the end of the campaign until a key is pressed
+async fn campaign_complete(name: &str) {
+    // the Enter press ending the last level is only cleared by the next frame
+    loop {
+        clear_background(BLACK);
+        draw_campaign_complete(name, Rect::new(0.0, 0.0, screen_width(), screen_height()));
+        next_frame().await;
+        if get_last_key_pressed().is_some() {
+            return;
+        }
+    }
+}
+
+/// Plays the map generated with `--generate`, or the maps of the campaign
+/// one after the other, the player keeping their inventory
+#[macroquad::main(window_conf)]
+async fn main() {
+    if let Some(kind) = arg_value("--generate") {
+        play_level(generate_map(&kind), None, AfterLevel::Quit).await;
+        return;
+    }
+    let campaign = open_campaign();
+    let maps = campaign.get_maps();
+    let mut carried = None;
+    for (index, path) in maps.iter().enumerate() {
+        let map = match Map::open(path, 64.0) {
+            Ok(map) => map,
+            Err(e) => panic!("Error parsing map {}: {:?}", path.display(), e),
+        };
+        let after = if index + 1 < maps.len() {
+            AfterLevel::NextLevel
+        } else {
+            AfterLevel::CampaignComplete
+        };
+        match play_level(map, carried.as_ref(), after).await {
+            Some(player) => carried = Some(player),
+            None => return,
+        }
+    }
+    campaign_complete(campaign.get_name()).await;
+}
@@ -1,13 +1,15 @@
 use regex::Regex;
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Lines};
+use std::io::{self, BufRead, BufReader, Lines, Read, Write};
 use std::ops::{Index, IndexMut};
+use std::path::{Path, PathBuf};
 
-use crate::render::Render;
+use crate::dda::{Crossing, Dda};
+use crate::render::{Render, TopDownView};
 use crate::vector::Vector;
-use macroquad::prelude::{draw_rectangle, BLACK, RED, WHITE};
+use macroquad::prelude::{draw_rectangle, draw_rectangle_lines, BLACK, DARKGRAY, GRAY, WHITE};
 
 pub struct Map {
     name: String,
@@ -15,6 +17,68 @@ pub struct Map {
     height: usize,
     tiles: Vec<u8>,
     tile_size: f32,
+    textures: MapTextures,
+    spawn: Option<Spawn>,
+    /// Key needed to open each locked door tile (`door.<tile> = <key>` fields)
+    locks: HashMap<u8, String>,
+    definitions: EntityDefinitions,
+    placements: Vec<Placement>,
+    triggers: TriggerDefinitions,
+    /// Level script file (`script` field)
+    script: Option<PathBuf>,
+}
+
+/// Entity kinds declared in the header (`entity.<kind>.<property> = <value>` fields):
+/// the properties of each kind, read by the code spawning the entities
+pub type EntityDefinitions = HashMap<String, HashMap<String, String>>;
+
+/// Trigger regions declared in the header (`trigger.<name>.<property> = <value>`
+/// fields): the properties of each trigger, read by [crate::trigger::Triggers]
+pub type TriggerDefinitions = HashMap<String, HashMap<String, String>>;
+
+/// An entity placed in the objects section of the map, after the tiles:
+/// `<kind> <x> <y> [angle in degrees] [<x>,<y> waypoints...]`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Placement {
+    pub kind: String,
+    pub x: usize,
+    pub y: usize,
+    /// In radians
+    pub angle: f32,
+    /// Tiles to go through, in order
+    pub waypoints: Vec<(usize, usize)>,
+}
+
+/// Where the player starts (`spawn = <x> <y> [angle in degrees]` field)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Spawn {
+    pub x: usize,
+    pub y: usize,
+    /// In radians
+    pub angle: f32,
+}
+
+/// First wall crossed by a segment, see [Map::cast_segment]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SegmentHit {
+    pub tile: Vector<usize>,
+    /// World position where the segment enters the wall
+    pub point: Vector<f32>,
+    /// True if the wall was entered through a horizontal face (moving along y)
+    pub side: bool,
+}
+
+/// Textures declared in the map header, by name
+#[derive(Default, Clone, Debug)]
+pub struct MapTextures {
+    /// Directory containing the texture files (`textures` field)
+    pub directory: Option<PathBuf>,
+    /// Texture of each wall tile (`wall.<tile>` fields)
+    pub walls: HashMap<u8, String>,
+    pub floor: Option<String>,
+    pub ceiling: Option<String>,
+    /// Panorama drawn instead of the ceiling
+    pub sky: Option<String>,
 }
 
 pub struct ParseErrorDetails {
@@ -32,7 +96,7 @@ impl Debug for ParseError {
         match self {
             ParseError::FileError(e) => write!(f, "File error: {}", e),
             ParseError::InvalidFormat(e) => {
-                write!(f, "Invalid format: {}\n", e.message)?;
+                writeln!(f, "Invalid format: {}", e.message)?;
                 write!(f, " at line {}", e.line)
             }
         }
@@ -57,25 +121,25 @@ fn parse_field(field: String, line_number: &u32) -> Result<(String, String), Par
         }));
     }
     let value = value.unwrap();
-    if key.len() == 0 || value.len() == 0 {
+    if key.is_empty() || value.is_empty() {
         return Err(ParseError::InvalidFormat(ParseErrorDetails {
             line: *line_number,
             message: "Invalid field format".to_string(),
         }));
     }
-    return Ok((key.trim().to_owned(), value.trim().to_owned()));
+    Ok((key.trim().to_owned(), value.trim().to_owned()))
 }
 
 /// loads all the fields from the given iterator of lines
 /// until the "---" separator is reached
 /// Returns a hashmap of the fields
 fn load_fields(
-    lines: &mut Lines<BufReader<File>>,
+    lines: &mut Lines<impl BufRead>,
     line_number: &mut u32,
 ) -> Result<HashMap<String, String>, ParseError> {
     let mut fields = HashMap::new();
     let mut map_reached = false;
-    while let Some(line) = lines.next() {
+    for line in lines.by_ref() {
         *line_number += 1;
         let line = line.map_err(ParseError::FileError)?;
         if line == "---" {
@@ -91,11 +155,11 @@ fn load_fields(
             message: "Map separator not found".to_string(),
         }));
     }
-    return Ok(fields);
+    Ok(fields)
 }
 
 fn parse_size(size: &str, line: &u32) -> Result<(usize, usize), ParseError> {
-    let size_regex = Regex::new(r"(\d+)x(\d+)").unwrap();
+    let size_regex = Regex::new(r"^(\d+)x(\d+)$").unwrap();
 
     let captures =
         size_regex
@@ -106,28 +170,236 @@ fn parse_size(size: &str, line: &u32) -> Result<(usize, usize), ParseError> {
             }))?;
     let width = captures.get(1).unwrap().as_str().parse::<usize>().unwrap();
     let height = captures.get(2).unwrap().as_str().parse::<usize>().unwrap();
-    return Ok((width, height));
+    Ok((width, height))
+}
+
+/// Reads the texture related fields
+fn parse_textures(fields: &HashMap<String, String>, line: &u32) -> Result<MapTextures, ParseError> {
+    let mut textures = MapTextures {
+        directory: fields.get("textures").map(PathBuf::from),
+        floor: fields.get("floor").cloned(),
+        ceiling: fields.get("ceiling").cloned(),
+        sky: fields.get("sky").cloned(),
+        ..Default::default()
+    };
+    for (key, value) in fields {
+        if let Some(tile) = key.strip_prefix("wall.") {
+            let tile = tile.parse::<u8>().map_err(|_| {
+                ParseError::InvalidFormat(ParseErrorDetails {
+                    line: *line,
+                    message: format!("Invalid wall tile \"{}\"", tile),
+                })
+            })?;
+            textures.walls.insert(tile, value.clone());
+        }
+    }
+    Ok(textures)
+}
+
+/// Reads the locked door fields
+fn parse_locks(
+    fields: &HashMap<String, String>,
+    line: &u32,
+) -> Result<HashMap<u8, String>, ParseError> {
+    let mut locks = HashMap::new();
+    for (key, value) in fields {
+        if let Some(tile) = key.strip_prefix("door.") {
+            let tile = tile
+                .parse::<u8>()
+                .ok()
+                .filter(|tile| *tile != 0)
+                .ok_or_else(|| {
+                    ParseError::InvalidFormat(ParseErrorDetails {
+                        line: *line,
+                        message: format!("Invalid door tile \"{}\"", tile),
+                    })
+                })?;
+            locks.insert(tile, value.clone());
+        }
+    }
+    Ok(locks)
+}
+
+/// Reads the spawn field, if any
+fn parse_spawn(fields: &HashMap<String, String>, line: &u32) -> Result<Option<Spawn>, ParseError> {
+    let Some(spawn) = fields.get("spawn") else {
+        return Ok(None);
+    };
+    let error = || {
+        ParseError::InvalidFormat(ParseErrorDetails {
+            line: *line,
+            message: "Invalid spawn format, expected <x> <y> [angle]".to_string(),
+        })
+    };
+    let values: Vec<&str> = spawn.split_whitespace().collect();
+    if values.len() < 2 || values.len() > 3 {
+        return Err(error());
+    }
+    let x = values[0].parse::<usize>().map_err(|_| error())?;
+    let y = values[1].parse::<usize>().map_err(|_| error())?;
+    let angle = match values.get(2) {
+        Some(angle) => angle.parse::<f32>().map_err(|_| error())?,
+        None => 0.0,
+    };
+    Ok(Some(Spawn {
+        x,
+        y,
+        angle: angle.to_radians(),
+    }))
+}
+
+/// Reads the `<prefix>.<name>.<property>` fields: the properties of each name
+fn parse_definitions(
+    fields: &HashMap<String, String>,
+    prefix: &str,
+    line: &u32,
+) -> Result<HashMap<String, HashMap<String, String>>, ParseError> {
+    let mut definitions = HashMap::<String, HashMap<String, String>>::new();
+    for (key, value) in fields {
+        let Some(definition) = key
+            .strip_prefix(prefix)
+            .and_then(|key| key.strip_prefix('.'))
+        else {
+            continue;
+        };
+        let (name, property) = definition
+            .split_once('.')
+            .filter(|(name, property)| !name.is_empty() && !property.is_empty())
+            .ok_or_else(|| {
+                ParseError::InvalidFormat(ParseErrorDetails {
+                    line: *line,
+                    message: format!(
+                        "Invalid {} field \"{}\", expected {}.<name>.<property>",
+                        prefix, key, prefix
+                    ),
+                })
+            })?;
+        definitions
+            .entry(name.to_string())
+            .or_default()
+            .insert(property.to_string(), value.clone());
+    }
+    Ok(definitions)
+}
+
+impl Placement {
+    /// Reads a placement written like in the objects section, the kind
+    /// must be defined by the map and the tiles must be in it
+    pub fn parse(line: &str, map: &Map) -> Result<Self, String> {
+        let mut values = line.split_whitespace();
+        let (Some(kind), Some(x), Some(y)) = (values.next(), values.next(), values.next()) else {
+            return Err(
+                "Invalid object format, expected <kind> <x> <y> [angle] [waypoints]".to_string(),
+            );
+        };
+        if !map.definitions.contains_key(kind) {
+            return Err(format!("Unknown entity kind \"{}\"", kind));
+        }
+        let tile = |x: &str, y: &str| -> Result<(usize, usize), String> {
+            let x = x
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid x \"{}\"", x))?;
+            let y = y
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid y \"{}\"", y))?;
+            if x >= map.width || y >= map.height {
+                return Err(format!("{} {} is outside of the map", x, y));
+            }
+            Ok((x, y))
+        };
+        let (x, y) = tile(x, y)?;
+        let mut placement = Placement {
+            kind: kind.to_string(),
+            x,
+            y,
+            angle: 0.0,
+            waypoints: Vec::new(),
+        };
+        for value in values {
+            match value.split_once(',') {
+                Some((x, y)) => placement.waypoints.push(tile(x, y)?),
+                None if placement.waypoints.is_empty() => {
+                    let angle = value
+                        .parse::<f32>()
+                        .map_err(|_| format!("Invalid angle \"{}\"", value))?;
+                    placement.angle = angle.to_radians();
+                }
+                None => return Err(format!("Invalid waypoint \"{}\"", value)),
+            }
+        }
+        Ok(placement)
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.kind,
+            self.x,
+            self.y,
+            self.angle.to_degrees()
+        )?;
+        for (x, y) in &self.waypoints {
+            write!(f, " {},{}", x, y)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the objects section, until the end of the file
+fn parse_placements(
+    lines: &mut Lines<impl BufRead>,
+    line_number: &mut u32,
+    map: &Map,
+) -> Result<Vec<Placement>, ParseError> {
+    let mut placements = Vec::new();
+    for line in lines.by_ref() {
+        *line_number += 1;
+        let line = line.map_err(ParseError::FileError)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |message: String| {
+            ParseError::InvalidFormat(ParseErrorDetails {
+                line: *line_number,
+                message,
+            })
+        };
+        let placement = Placement::parse(&line, map).map_err(error)?;
+        for (x, y) in std::iter::once((placement.x, placement.y)).chain(placement.waypoints.clone())
+        {
+            if map.is_wall(x, y) {
+                return Err(error(format!("{} {} is not an empty tile", x, y)));
+            }
+        }
+        placements.push(placement);
+    }
+    Ok(placements)
 }
 
 /// Parses the tiles from the given iterator of lines
-/// until the end of the file is reached
+/// until the end of the file or the objects separator is reached
 /// Returns a vector of tiles if there are enough tiles
 /// or an error if there are too many or too few tiles or
 /// if the tiles are invalid
 fn parse_tiles(
-    lines: &mut Lines<BufReader<File>>,
+    lines: &mut Lines<impl BufRead>,
     line_number: &mut u32,
     width: &usize,
     height: &usize,
 ) -> Result<Vec<u8>, ParseError> {
     let mut tiles = Vec::new();
-    let expected_len = (width * height) as usize;
+    let expected_len = width * height;
     tiles.reserve(expected_len);
-    while let Some(line) = lines.next() {
+    for line in lines.by_ref() {
         *line_number += 1;
         let line = line.map_err(ParseError::FileError)?;
-        let mut line = line.split_whitespace();
-        while let Some(tile) = line.next() {
+        if line.trim() == "---" {
+            break;
+        }
+        for tile in line.split_whitespace() {
             let tile = tile.parse::<u8>().map_err(|_| {
                 ParseError::InvalidFormat(ParseErrorDetails {
                     line: *line_number,
@@ -150,25 +422,95 @@ fn parse_tiles(
                 "Not enough tiles, expected {} but got {}",
                 expected_len,
                 tiles.len()
-            )
-            .to_owned(),
+            ),
         }));
     }
-    return Ok(tiles);
+    Ok(tiles)
 }
 
 impl Map {
     pub fn new(name: String, width: usize, height: usize, tile_size: f32, tiles: Vec<u8>) -> Map {
-        assert!(tiles.len() == (width * height) as usize);
+        assert!(tiles.len() == width * height);
         Map {
-            name: name,
-            width: width,
-            height: height,
-            tiles: tiles,
-            tile_size: tile_size,
+            name,
+            width,
+            height,
+            tiles,
+            tile_size,
+            textures: MapTextures::default(),
+            spawn: None,
+            locks: HashMap::new(),
+            definitions: EntityDefinitions::new(),
+            placements: Vec::new(),
+            triggers: TriggerDefinitions::new(),
+            script: None,
         }
     }
 
+    pub fn get_definitions(&self) -> &EntityDefinitions {
+        &self.definitions
+    }
+
+    pub fn get_placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    /// Replaces the entity kinds and the placed entities,
+    /// the placements are not checked
+    pub fn set_entities(&mut self, definitions: EntityDefinitions, placements: Vec<Placement>) {
+        self.definitions = definitions;
+        self.placements = placements;
+    }
+
+    /// Key needed to open the doors made of the given tile, None if they aren't locked
+    pub fn get_lock(&self, tile: u8) -> Option<&str> {
+        self.locks.get(&tile).map(String::as_str)
+    }
+
+    pub fn set_lock(&mut self, tile: u8, key: Option<String>) {
+        match key {
+            Some(key) => self.locks.insert(tile, key),
+            None => self.locks.remove(&tile),
+        };
+    }
+
+    pub fn get_triggers(&self) -> &TriggerDefinitions {
+        &self.triggers
+    }
+
+    pub fn set_triggers(&mut self, triggers: TriggerDefinitions) {
+        self.triggers = triggers;
+    }
+
+    pub fn get_script(&self) -> Option<&Path> {
+        self.script.as_deref()
+    }
+
+    pub fn set_script(&mut self, script: Option<PathBuf>) {
+        self.script = script;
+    }
+
+    pub fn get_spawn(&self) -> Option<Spawn> {
+        self.spawn
+    }
+
+    pub fn set_spawn(&mut self, spawn: Option<Spawn>) {
+        self.spawn = spawn;
+    }
+
+    pub fn get_textures(&self) -> &MapTextures {
+        &self.textures
+    }
+
+    #[allow(dead_code)]
+    pub fn set_textures(&mut self, textures: MapTextures) {
+        self.textures = textures;
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
     #[allow(dead_code)]
     pub fn get_width(&self) -> usize {
         self.width
@@ -183,12 +525,13 @@ impl Map {
         self.tile_size
     }
 
-    /// Automatically sets the tile size so that the map
-    /// fits into the given window size
-    pub fn auto_tile_size(&mut self, window_width: f32, window_height: f32) {
+    /// Computes the tile size (in pixels) so that the map
+    /// fits into the given window size. The world tile size is not
+    /// changed, views use the ratio between the two as scale
+    pub fn auto_tile_size(&self, window_width: f32, window_height: f32) -> f32 {
         let width = window_width as usize / self.width;
         let height = window_height as usize / self.height;
-        self.tile_size = width.min(height) as f32;
+        width.min(height) as f32
     }
 
     fn get_tile(&self, x: usize, y: usize) -> &u8 {
@@ -202,7 +545,7 @@ impl Map {
     /// Parses the given map file and returns a map
     /// To see how the map file is structured, see the
     /// README.md file
-    pub fn parse(map: File, tile_size: f32) -> Result<Map, ParseError> {
+    pub fn parse(map: impl Read, tile_size: f32) -> Result<Map, ParseError> {
         let reader = BufReader::new(map);
         let mut lines = reader.lines();
         let mut line = 0;
@@ -211,20 +554,126 @@ impl Map {
         let name = fields
             .get("name")
             .ok_or(ParseError::InvalidFormat(ParseErrorDetails {
-                line: line,
+                line,
                 message: "Missing name field".to_string(),
             }))?;
 
         let size = fields
             .get("size")
             .ok_or(ParseError::InvalidFormat(ParseErrorDetails {
-                line: line,
+                line,
                 message: "Missing size field".to_string(),
             }))?;
 
         let (width, height) = parse_size(size, &line)?;
+        let textures = parse_textures(&fields, &line)?;
+        let spawn = parse_spawn(&fields, &line)?;
+        let locks = parse_locks(&fields, &line)?;
+        let definitions = parse_definitions(&fields, "entity", &line)?;
+        let triggers = parse_definitions(&fields, "trigger", &line)?;
         let tiles = parse_tiles(&mut lines, &mut line, &width, &height)?;
-        Ok(Map::new(name.to_string(), width, height, tile_size, tiles))
+        let mut map = Map::new(name.to_string(), width, height, tile_size, tiles);
+        map.textures = textures;
+        map.locks = locks;
+        map.definitions = definitions;
+        map.triggers = triggers;
+        map.script = fields.get("script").map(PathBuf::from);
+        map.placements = parse_placements(&mut lines, &mut line, &map)?;
+        if let Some(spawn) = spawn {
+            if spawn.x >= width || spawn.y >= height || map.is_wall(spawn.x, spawn.y) {
+                return Err(ParseError::InvalidFormat(ParseErrorDetails {
+                    line,
+                    message: "The spawn must be on an empty tile".to_string(),
+                }));
+            }
+        }
+        map.spawn = spawn;
+        Ok(map)
+    }
+
+    /// Writes the map in the format read by [Map::parse],
+    /// the texture directory and the script are written as they are stored
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "name = {}", self.name)?;
+        writeln!(writer, "size = {}x{}", self.width, self.height)?;
+        let textures = &self.textures;
+        if let Some(directory) = &textures.directory {
+            writeln!(writer, "textures = {}", directory.display())?;
+        }
+        let mut walls: Vec<_> = textures.walls.iter().collect();
+        walls.sort();
+        for (tile, texture) in walls {
+            writeln!(writer, "wall.{} = {}", tile, texture)?;
+        }
+        for (key, value) in [
+            ("floor", &textures.floor),
+            ("ceiling", &textures.ceiling),
+            ("sky", &textures.sky),
+        ] {
+            if let Some(value) = value {
+                writeln!(writer, "{} = {}", key, value)?;
+            }
+        }
+        if let Some(spawn) = self.spawn {
+            writeln!(
+                writer,
+                "spawn = {} {} {}",
+                spawn.x,
+                spawn.y,
+                spawn.angle.to_degrees()
+            )?;
+        }
+        if let Some(script) = &self.script {
+            writeln!(writer, "script = {}", script.display())?;
+        }
+        let mut locks: Vec<_> = self.locks.iter().collect();
+        locks.sort();
+        for (tile, key) in locks {
+            writeln!(writer, "door.{} = {}", tile, key)?;
+        }
+        for (prefix, definitions) in [("entity", &self.definitions), ("trigger", &self.triggers)] {
+            let mut names: Vec<_> = definitions.iter().collect();
+            names.sort_by_key(|(name, _)| *name);
+            for (name, properties) in names {
+                let mut properties: Vec<_> = properties.iter().collect();
+                properties.sort();
+                for (property, value) in properties {
+                    writeln!(writer, "{}.{}.{} = {}", prefix, name, property, value)?;
+                }
+            }
+        }
+        writeln!(writer, "---")?;
+        for row in self.tiles.chunks(self.width) {
+            let row: Vec<String> = row.iter().map(|tile| tile.to_string()).collect();
+            writeln!(writer, "{}", row.join(" "))?;
+        }
+        if !self.placements.is_empty() {
+            writeln!(writer, "---")?;
+        }
+        for placement in &self.placements {
+            writeln!(writer, "{}", placement)?;
+        }
+        Ok(())
+    }
+
+    /// Opens and parses the map file at the given path,
+    /// the texture directory and the script are relative to the map file
+    pub fn open(path: impl AsRef<Path>, tile_size: f32) -> Result<Map, ParseError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(ParseError::FileError)?;
+        let mut map = Map::parse(file, tile_size)?;
+        if let (Some(directory), Some(parent)) = (&map.textures.directory, path.parent()) {
+            map.textures.directory = Some(parent.join(directory));
+        }
+        if let (Some(script), Some(parent)) = (&map.script, path.parent()) {
+            map.script = Some(parent.join(script));
+        }
+        Ok(map)
+    }
+
+    /// Returns true if the given tile blocks the player and the rays
+    pub fn is_wall(&self, x: usize, y: usize) -> bool {
+        *self.get_tile(x, y) != 0
     }
 
     pub fn in_map(&self, pos: &Vector<f32>) -> bool {
@@ -234,6 +683,11 @@ impl Map {
             && pos.y < self.height as f32 * self.tile_size
     }
 
+    /// World position of the top left corner of the given tile
+    pub fn tile_corner(&self, x: usize, y: usize) -> Vector<f32> {
+        Vector::new(x as f32 * self.tile_size, y as f32 * self.tile_size)
+    }
+
     pub fn to_map_coordinates(&self, pos: &Vector<f32>) -> Option<Vector<usize>> {
         if !self.in_map(pos) {
             return None;
@@ -242,6 +696,60 @@ impl Map {
         let y = (pos.y / self.tile_size) as usize;
         Some(Vector::new(x, y))
     }
+
+    /// The tile if it is inside the map
+    pub fn tile_at(&self, tile: Vector<i32>) -> Option<Vector<usize>> {
+        let inside = tile.x >= 0
+            && tile.y >= 0
+            && (tile.x as usize) < self.width
+            && (tile.y as usize) < self.height;
+        inside.then(|| Vector::new(tile.x as usize, tile.y as usize))
+    }
+
+    /// Tiles crossed by the segment between two world positions with the
+    /// segment parameter (0 at `from`, 1 at `to`) where they are entered.
+    /// Stops at the tile of `to` or when the segment leaves the map
+    fn crossings(
+        &self,
+        from: Vector<f32>,
+        to: Vector<f32>,
+    ) -> impl Iterator<Item = (Vector<usize>, Crossing)> + '_ {
+        let origin = from / self.tile_size;
+        let direction = (to - from) / self.tile_size;
+        let in_map = self.in_map(&from);
+        Dda::new(origin, direction)
+            .take_while(move |crossing| in_map && crossing.distance <= 1.0)
+            .map_while(|crossing| Some((self.tile_at(crossing.tile)?, crossing)))
+    }
+
+    /// Tiles crossed by the segment between two world positions, in order,
+    /// walls included. Stops at the tile of `to` or when the segment leaves the map
+    pub fn cells_along(
+        &self,
+        from: Vector<f32>,
+        to: Vector<f32>,
+    ) -> impl Iterator<Item = Vector<usize>> + '_ {
+        self.crossings(from, to).map(|(tile, _)| tile)
+    }
+
+    /// First wall crossed by the segment between two world positions,
+    /// None if the way is clear. The parts of the segment outside of the
+    /// map are not checked
+    pub fn cast_segment(&self, from: Vector<f32>, to: Vector<f32>) -> Option<SegmentHit> {
+        self.crossings(from, to)
+            .find(|(tile, _)| self.is_wall(tile.x, tile.y))
+            .map(|(tile, crossing)| SegmentHit {
+                tile,
+                point: from + (to - from) * crossing.distance,
+                side: crossing.side,
+            })
+    }
+
+    /// True if no wall is between the two world positions,
+    /// always false when one of them is outside of the map
+    pub fn has_line_of_sight(&self, a: Vector<f32>, b: Vector<f32>) -> bool {
+        self.in_map(&a) && self.in_map(&b) && self.cast_segment(a, b).is_none()
+    }
 }
 
 impl Index<(usize, usize)> for Map {
@@ -260,16 +768,16 @@ impl IndexMut<(usize, usize)> for Map {
 
 impl Debug for Map {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
+        writeln!(
             f,
-            "name: {}, width: {}, height: {}\n",
+            "name: {}, width: {}, height: {}",
             self.name, self.width, self.height
         )?;
         for y in 0..self.height {
             for x in 0..self.width {
                 write!(f, "{} ", self.get_tile(x, y))?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         Ok(())
     }
@@ -277,22 +785,19 @@ impl Debug for Map {
 
 impl Render for Map {
     /// Renders the map in a top-down view
-    fn render(&self) {
+    fn render(&self, view: &TopDownView) {
+        let size = self.tile_size * view.scale;
         for tile_y in 0..self.height {
             for tile_x in 0..self.width {
                 let tile = self.get_tile(tile_x, tile_y);
                 let color = match tile {
                     0 => WHITE,
                     1 => BLACK,
-                    _ => RED,
+                    _ => DARKGRAY,
                 };
-                draw_rectangle(
-                    tile_x as f32 * self.tile_size,
-                    tile_y as f32 * self.tile_size,
-                    self.tile_size,
-                    self.tile_size,
-                    color,
-                );
+                let corner = view.project(self.tile_corner(tile_x, tile_y));
+                draw_rectangle(corner.x, corner.y, size, size, view.color(color));
+                draw_rectangle_lines(corner.x, corner.y, size, size, 1.0, view.color(GRAY));
             }
         }
     }
@@ -381,6 +886,33 @@ mod parser_tests {
     }
 }
 
+#[cfg(test)]
+mod textures_test {
+    use super::*;
+
+    #[test]
+    fn texture_fields() {
+        let mut fields = HashMap::new();
+        fields.insert("textures".to_string(), "textures".to_string());
+        fields.insert("wall.1".to_string(), "brick".to_string());
+        fields.insert("wall.12".to_string(), "wood".to_string());
+        fields.insert("floor".to_string(), "stone".to_string());
+        let textures = parse_textures(&fields, &1).unwrap();
+        assert_eq!(textures.directory, Some(PathBuf::from("textures")));
+        assert_eq!(textures.walls.get(&1).unwrap(), "brick");
+        assert_eq!(textures.walls.get(&12).unwrap(), "wood");
+        assert_eq!(textures.floor.unwrap(), "stone");
+        assert!(textures.ceiling.is_none());
+    }
+
+    #[test]
+    fn invalid_wall_tile() {
+        let mut fields = HashMap::new();
+        fields.insert("wall.300".to_string(), "brick".to_string());
+        assert!(parse_textures(&fields, &1).is_err());
+    }
+}
+
 #[cfg(test)]
 mod simple_load_test {
     use super::*;
@@ -402,3 +934,152 @@ mod simple_load_test {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod write_test {
+    use super::*;
+
+    #[test]
+    fn write_parse() {
+        let text = "name = room\nsize = 3x3\nwall.1 = brick\nfloor = stone\nspawn = 1 1 90\n---\n1 1 1\n1 0 1\n1 1 1\n";
+        let map = Map::parse(text.as_bytes(), 64.0).unwrap();
+        let spawn = map.get_spawn().unwrap();
+        assert_eq!((spawn.x, spawn.y), (1, 1));
+        assert!((spawn.angle - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+        let mut written = Vec::new();
+        map.write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+    }
+
+    #[test]
+    fn entities() {
+        let text = "name = room\nsize = 4x3\nentity.guard.health = 20\nentity.guard.speed = 50\nentity.lamp.size = 0.5\n---\n1 1 1 1\n1 0 0 1\n1 1 1 1\n---\nguard 1 1 180 2,1 1,1\nlamp 2 1 0\n";
+        let map = Map::parse(text.as_bytes(), 64.0).unwrap();
+        assert_eq!(map.get_definitions()["guard"]["health"], "20");
+        let placements = map.get_placements();
+        assert_eq!(placements.len(), 2);
+        assert_eq!((placements[0].x, placements[0].y), (1, 1));
+        assert!((placements[0].angle - std::f32::consts::PI).abs() < 1e-6);
+        assert_eq!(placements[0].waypoints, [(2, 1), (1, 1)]);
+
+        let mut written = Vec::new();
+        map.write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+
+        for invalid in [
+            "ghost 1 1",
+            "guard 0 0",
+            "guard 1",
+            "guard 1 1 x",
+            "guard 1 1 2,1 9",
+        ] {
+            let text = text.replace("lamp 2 1 0", invalid);
+            assert!(Map::parse(text.as_bytes(), 64.0).is_err(), "{}", invalid);
+        }
+        let text = text.replace("entity.lamp.size", "entity.lamp");
+        assert!(Map::parse(text.as_bytes(), 64.0).is_err());
+    }
+
+    #[test]
+    fn locked_doors() {
+        let text = "name = room\nsize = 3x3\ndoor.2 = red\n---\n1 1 1\n1 0 2\n1 1 1\n";
+        let map = Map::parse(text.as_bytes(), 64.0).unwrap();
+        assert_eq!(map.get_lock(2), Some("red"));
+        assert!(map.get_triggers().is_empty());
+        assert_eq!(map.get_lock(1), None);
+
+        let mut written = Vec::new();
+        map.write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+
+        let text = text.replace("door.2", "door.0");
+        assert!(Map::parse(text.as_bytes(), 64.0).is_err());
+    }
+
+    #[test]
+    fn triggers() {
+        let text = "name = room\nsize = 3x3\nscript = room.script\ntrigger.hello.action = message Hello\ntrigger.hello.area = 1 1\n---\n1 1 1\n1 0 1\n1 1 1\n";
+        let map = Map::parse(text.as_bytes(), 64.0).unwrap();
+        assert_eq!(map.get_triggers()["hello"]["area"], "1 1");
+        assert_eq!(map.get_script(), Some(Path::new("room.script")));
+
+        let mut written = Vec::new();
+        map.write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+
+        let text = text.replace("trigger.hello.area", "trigger.hello");
+        assert!(Map::parse(text.as_bytes(), 64.0).is_err());
+    }
+
+    #[test]
+    fn spawn_in_wall() {
+        let text = "name = room\nsize = 3x3\nspawn = 0 1\n---\n1 1 1\n1 0 1\n1 1 1\n";
+        assert!(Map::parse(text.as_bytes(), 64.0).is_err());
+        let text = text.replace("spawn = 0 1", "spawn = 1");
+        assert!(Map::parse(text.as_bytes(), 64.0).is_err());
+    }
+}
+
+#[cfg(test)]
+mod segment_test {
+    use super::*;
+
+    /// 5x4 tiles room with a pillar at (2, 1)
+    fn room() -> Map {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1,
+            1, 0, 1, 0, 1,
+            1, 0, 0, 0, 1,
+            1, 1, 1, 1, 1,
+        ];
+        Map::new("room".to_string(), 5, 4, 10.0, tiles)
+    }
+
+    #[test]
+    fn cells_along() {
+        let map = room();
+        let cells: Vec<_> = map
+            .cells_along(Vector::new(15.0, 25.0), Vector::new(35.0, 15.0))
+            .map(|tile| (tile.x, tile.y))
+            .collect();
+        assert_eq!(cells, [(1, 2), (2, 2), (2, 1), (3, 1)]);
+        // leaving the map
+        let cells = map.cells_along(Vector::new(15.0, 15.0), Vector::new(-100.0, 15.0));
+        assert_eq!(cells.count(), 2);
+    }
+
+    #[test]
+    fn cast_segment() {
+        let map = room();
+        let hit = map
+            .cast_segment(Vector::new(15.0, 15.0), Vector::new(35.0, 15.0))
+            .unwrap();
+        assert_eq!(hit.tile, Vector::new(2, 1));
+        assert_eq!(hit.point, Vector::new(20.0, 15.0));
+        assert!(!hit.side);
+
+        let hit = map
+            .cast_segment(Vector::new(25.0, 25.0), Vector::new(25.0, 0.0))
+            .unwrap();
+        assert_eq!(hit.tile, Vector::new(2, 1));
+        assert_eq!(hit.point, Vector::new(25.0, 20.0));
+        assert!(hit.side);
+
+        // stops before the wall
+        assert_eq!(
+            map.cast_segment(Vector::new(15.0, 15.0), Vector::new(19.0, 15.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn line_of_sight() {
+        let map = room();
+        assert!(map.has_line_of_sight(Vector::new(12.0, 18.0), Vector::new(35.0, 28.0)));
+        assert!(!map.has_line_of_sight(Vector::new(15.0, 15.0), Vector::new(35.0, 15.0)));
+        assert!(map.has_line_of_sight(Vector::new(15.0, 15.0), Vector::new(15.0, 15.0)));
+        assert!(!map.has_line_of_sight(Vector::new(15.0, 15.0), Vector::new(-5.0, 15.0)));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::framebuffer::{scale, Pixel};
+use crate::textures::{TextureId, TextureManager};
+
+/// Number of precomputed light levels, 0 is the brightest one
+pub const LIGHT_LEVELS: usize = 32;
+/// Brightness of the extra samples used to build the palette, so it
+/// also contains the darker shades needed by the colormap
+const SAMPLE_SHADES: [f32; 4] = [1.0, 0.7, 0.45, 0.2];
+
+/// A 256 colors palette
+#[derive(Clone)]
+pub struct Palette {
+    colors: [Pixel; 256],
+}
+
+/// Channel with the largest range in the given colors, and that range
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = colors.iter().map(|c| c[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|c| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(channel, range)| (*range, std::cmp::Reverse(*channel)))
+        .unwrap()
+}
+
+/// Splits the colors in 256 boxes using the median cut algorithm
+/// and returns the average color of each box
+fn median_cut(mut colors: Vec<[u8; 3]>) -> Vec<[u8; 3]> {
+    colors.sort_unstable();
+    colors.dedup();
+    let (channel, range) = widest_channel(&colors);
+    let mut boxes = vec![(colors, channel, range)];
+
+    while boxes.len() < 256 {
+        // split the box with the largest channel range
+        let Some(index) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, (colors, _, _))| colors.len() > 1)
+            .max_by_key(|(index, (_, _, range))| (*range, std::cmp::Reverse(*index)))
+            .map(|(index, _)| index)
+        else {
+            break;
+        };
+        let (mut colors, channel, _) = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|c| (c[channel], c[0], c[1], c[2]));
+        let upper = colors.split_off(colors.len() / 2);
+        for colors in [colors, upper] {
+            let (channel, range) = widest_channel(&colors);
+            boxes.push((colors, channel, range));
+        }
+    }
+
+    boxes
+        .iter()
+        .map(|(colors, _, _)| {
+            let mut sum = [0usize; 3];
+            for color in colors {
+                for channel in 0..3 {
+                    sum[channel] += color[channel] as usize;
+                }
+            }
+            sum.map(|s| (s / colors.len()) as u8)
+        })
+        .collect()
+}
+
+impl Palette {
+    #[allow(dead_code)]
+    pub fn new(colors: [Pixel; 256]) -> Self {
+        Self { colors }
+    }
+
+    /// Builds a palette fitting the given colors
+    pub fn from_colors(colors: impl IntoIterator<Item = Pixel>) -> Self {
+        let samples = colors
+            .into_iter()
+            .flat_map(|color| SAMPLE_SHADES.map(|shade| scale(color, shade)))
+            .map(|color| [color[0], color[1], color[2]])
+            .chain([[0, 0, 0], [255, 255, 255]])
+            .collect();
+
+        let mut colors = [[0, 0, 0, 255]; 256];
+        for (color, average) in colors.iter_mut().zip(median_cut(samples)) {
+            *color = [average[0], average[1], average[2], 255];
+        }
+        Self { colors }
+    }
+
+    pub fn get(&self, index: u8) -> Pixel {
+        self.colors[index as usize]
+    }
+
+    #[allow(dead_code)]
+    pub fn get_colors(&self) -> &[Pixel; 256] {
+        &self.colors
+    }
+
+    /// Index of the closest color of the palette
+    pub fn nearest(&self, color: Pixel) -> u8 {
+        let distance = |other: &Pixel| -> i32 {
+            (0..3)
+                .map(|channel| (color[channel] as i32 - other[channel] as i32).pow(2))
+                .sum()
+        };
+        let mut best = (0, i32::MAX);
+        for (index, other) in self.colors.iter().enumerate() {
+            let distance = distance(other);
+            if distance < best.1 {
+                best = (index, distance);
+            }
+        }
+        best.0 as u8
+    }
+
+    /// Returns a copy of the palette where every color is moved
+    /// toward `color` by `amount` (0 to 1), used for screen flashes
+    pub fn flash(&self, color: Pixel, amount: f32) -> Palette {
+        let amount = amount.clamp(0.0, 1.0);
+        let mut flashed = self.clone();
+        for entry in flashed.colors.iter_mut() {
+            for channel in 0..3 {
+                let from = entry[channel] as f32;
+                entry[channel] = (from + (color[channel] as f32 - from) * amount) as u8;
+            }
+        }
+        flashed
+    }
+}
+
+/// Light tables (like the Doom COLORMAP lump): for each light level,
+/// the palette index to use in place of each palette index
+pub struct Colormap {
+    tables: Vec<[u8; 256]>,
+}
+
+impl Colormap {
+    pub fn new(palette: &Palette) -> Self {
+        let tables = (0..LIGHT_LEVELS)
+            .map(|level| {
+                let light = 1.0 - level as f32 / LIGHT_LEVELS as f32;
+                let mut table = [0; 256];
+                for (index, entry) in table.iter_mut().enumerate() {
+                    *entry = palette.nearest(scale(palette.get(index as u8), light));
+                }
+                table
+            })
+            .collect();
+        Self { tables }
+    }
+
+    /// Light level matching a brightness between 0 and 1
+    pub fn level(light: f32) -> usize {
+        let level = ((1.0 - light) * LIGHT_LEVELS as f32).round() as usize;
+        level.min(LIGHT_LEVELS - 1)
+    }
+
+    /// Palette index of the given color lit with the given brightness
+    pub fn shade(&self, index: u8, light: f32) -> u8 {
+        self.tables[Self::level(light)][index as usize]
+    }
+}
+
+/// Everything needed to render with a palette: the palette, its
+/// colormap and every texture quantized to palette indices
+pub struct PaletteMode {
+    palette: Palette,
+    colormap: Colormap,
+    /// Quantized textures, by texture id
+    textures: Vec<Vec<u8>>,
+    widths: Vec<usize>,
+    heights: Vec<usize>,
+}
+
+impl PaletteMode {
+    /// Builds a palette fitting the textures and the given extra colors
+    /// (flat colors used when there is no texture) and quantizes the textures
+    pub fn new(textures: &TextureManager, extra_colors: &[Pixel]) -> Self {
+        let colors = textures
+            .get_textures()
+            .iter()
+            .flat_map(|texture| texture.get_pixels().iter().copied())
+            .chain(extra_colors.iter().copied());
+        let palette = Palette::from_colors(colors);
+        let colormap = Colormap::new(&palette);
+
+        let mut cache = std::collections::HashMap::new();
+        let textures = textures.get_textures();
+        let quantized = textures
+            .iter()
+            .map(|texture| {
+                texture
+                    .get_pixels()
+                    .iter()
+                    .map(|color| {
+                        *cache
+                            .entry(*color)
+                            .or_insert_with(|| palette.nearest(*color))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            palette,
+            colormap,
+            textures: quantized,
+            widths: textures.iter().map(|texture| texture.get_width()).collect(),
+            heights: textures
+                .iter()
+                .map(|texture| texture.get_height())
+                .collect(),
+        }
+    }
+
+    pub fn get_palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn get_colormap(&self) -> &Colormap {
+        &self.colormap
+    }
+
+    /// Palette index of a texel, wrapping around the texture edges
+    pub fn get_texel(&self, texture: TextureId, x: usize, y: usize) -> u8 {
+        let (width, height) = (self.widths[texture], self.heights[texture]);
+        self.textures[texture][(y % height) * width + x % width]
+    }
+}
+
+/// A palette flash fading over time (damage, pickup...)
+pub struct PaletteFlash {
+    color: Pixel,
+    amount: f32,
+}
+
+/// How much of the flash disappears every second
+const FLASH_FADE: f32 = 2.0;
+
+impl PaletteFlash {
+    pub fn new() -> Self {
+        Self {
+            color: [0, 0, 0, 255],
+            amount: 0.0,
+        }
+    }
+
+    /// Starts a flash of the given color, `amount` is its initial strength
+    pub fn start(&mut self, color: Pixel, amount: f32) {
+        self.color = color;
+        self.amount = amount;
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        self.amount = (self.amount - delta * FLASH_FADE).max(0.0);
+    }
+
+    /// The palette to display, with the flash applied
+    pub fn apply(&self, palette: &Palette) -> Palette {
+        if self.amount <= 0.0 {
+            palette.clone()
+        } else {
+            palette.flash(self.color, self.amount)
+        }
+    }
+}
+
+impl Default for PaletteFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod palette_tests {
+    use super::*;
+
+    #[test]
+    fn palette_keeps_few_colors_exact() {
+        let colors = [[255, 0, 0, 255], [0, 128, 0, 255], [10, 20, 30, 255]];
+        let palette = Palette::from_colors(colors);
+        for color in colors {
+            assert_eq!(palette.get(palette.nearest(color)), color);
+        }
+    }
+
+    #[test]
+    fn colormap_darkens() {
+        let palette = Palette::from_colors([[200, 200, 200, 255]]);
+        let colormap = Colormap::new(&palette);
+        let index = palette.nearest([200, 200, 200, 255]);
+        assert_eq!(colormap.shade(index, 1.0), index);
+        let dark = palette.get(colormap.shade(index, 0.2));
+        assert!(dark[0] < 100);
+        assert_eq!(palette.get(colormap.shade(index, 0.0)), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn flash_blends_toward_color() {
+        let palette = Palette::new([[0, 0, 0, 255]; 256]);
+        let mut flash = PaletteFlash::new();
+        flash.start([255, 0, 0, 255], 0.5);
+        assert_eq!(flash.apply(&palette).get(0), [127, 0, 0, 255]);
+        flash.update(1.0);
+        assert_eq!(flash.apply(&palette).get(0), [0, 0, 0, 255]);
+    }
+}
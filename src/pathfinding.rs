@@ -0,0 +1,559 @@
+use crate::map::Map;
+use crate::vector::Vector;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+/// Cost of a straight step, a diagonal step costs `DIAGONAL_COST` (about √2 times more)
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+/// A tile position, as used by the `Map` index
+pub type Tile = (usize, usize);
+
+/// Neighbours of a tile used by the searches
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Connectivity {
+    /// Up, down, left and right
+    Four,
+    /// Also the diagonals, only when both tiles next to the corner are walkable
+    Eight,
+}
+
+const STRAIGHT: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const DIAGONAL: [(isize, isize); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+/// Walkable tiles of a map, the grid used by the searches.
+/// It is built once from the map, tiles opened or closed later
+/// (doors...) are updated with [NavGrid::set_walkable]
+pub struct NavGrid {
+    width: usize,
+    height: usize,
+    walkable: Vec<bool>,
+    connectivity: Connectivity,
+    /// Incremented on every change, so flow fields know when to update
+    version: u64,
+}
+
+impl NavGrid {
+    /// Grid where every tile which is not a wall is walkable
+    pub fn new(map: &Map, connectivity: Connectivity) -> Self {
+        Self::with_passable(map, connectivity, |tile| tile == 0)
+    }
+
+    /// Grid where the walkable tiles are the ones accepted by `passable`,
+    /// which is given the tile value (lets doors or solid decorations
+    /// be handled differently from the walls)
+    pub fn with_passable(
+        map: &Map,
+        connectivity: Connectivity,
+        passable: impl Fn(u8) -> bool,
+    ) -> Self {
+        let (width, height) = (map.get_width(), map.get_height());
+        let walkable = (0..width * height)
+            .map(|i| passable(map[(i % width, i / width)]))
+            .collect();
+        Self {
+            width,
+            height,
+            walkable,
+            connectivity,
+            version: 0,
+        }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_connectivity(&self) -> Connectivity {
+        self.connectivity
+    }
+
+    pub fn set_connectivity(&mut self, connectivity: Connectivity) {
+        self.connectivity = connectivity;
+        self.version += 1;
+    }
+
+    /// Tiles outside of the grid are not walkable
+    pub fn is_walkable(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.walkable[y * self.width + x]
+    }
+
+    /// Tiles outside of the grid are ignored
+    pub fn set_walkable(&mut self, x: usize, y: usize, walkable: bool) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let index = y * self.width + x;
+        if self.walkable[index] != walkable {
+            self.walkable[index] = walkable;
+            self.version += 1;
+        }
+    }
+
+    fn offset(&self, (x, y): Tile, (dx, dy): (isize, isize)) -> Option<Tile> {
+        let (nx, ny) = (x.checked_add_signed(dx)?, y.checked_add_signed(dy)?);
+        self.is_walkable(nx, ny).then_some((nx, ny))
+    }
+
+    /// Walkable neighbours of the tile with the cost of the step,
+    /// diagonals never cut a corner
+    fn neighbours(&self, tile: Tile) -> Vec<(Tile, u32)> {
+        let mut neighbours: Vec<_> = STRAIGHT
+            .iter()
+            .filter_map(|step| self.offset(tile, *step))
+            .map(|next| (next, STRAIGHT_COST))
+            .collect();
+        if self.connectivity == Connectivity::Eight {
+            for (dx, dy) in DIAGONAL {
+                let corner_free =
+                    self.offset(tile, (dx, 0)).is_some() && self.offset(tile, (0, dy)).is_some();
+                if let Some(next) = self.offset(tile, (dx, dy)).filter(|_| corner_free) {
+                    neighbours.push((next, DIAGONAL_COST));
+                }
+            }
+        }
+        neighbours
+    }
+
+    /// Estimated cost between two tiles, never more than the real cost
+    fn heuristic(&self, (ax, ay): Tile, (bx, by): Tile) -> u32 {
+        let (dx, dy) = (ax.abs_diff(bx) as u32, ay.abs_diff(by) as u32);
+        match self.connectivity {
+            Connectivity::Four => (dx + dy) * STRAIGHT_COST,
+            Connectivity::Eight => {
+                dx.max(dy) * STRAIGHT_COST + dx.min(dy) * (DIAGONAL_COST - STRAIGHT_COST)
+            }
+        }
+    }
+
+    /// Shortest path with A*, from `start` to `goal` both included.
+    /// None if one of them is not walkable or the goal can't be reached
+    pub fn find_path(&self, start: Tile, goal: Tile) -> Option<Vec<Tile>> {
+        if !self.is_walkable(start.0, start.1) || !self.is_walkable(goal.0, goal.1) {
+            return None;
+        }
+        let index = |(x, y): Tile| y * self.width + x;
+        let mut cost = vec![u32::MAX; self.walkable.len()];
+        let mut came_from = vec![usize::MAX; self.walkable.len()];
+        // ties are broken on the lowest remaining estimate, toward the goal
+        let mut open = BinaryHeap::new();
+        cost[index(start)] = 0;
+        open.push(Reverse((
+            self.heuristic(start, goal),
+            self.heuristic(start, goal),
+            start,
+        )));
+        while let Some(Reverse((_, _, tile))) = open.pop() {
+            if tile == goal {
+                let mut path = vec![goal];
+                let mut current = index(goal);
+                while came_from[current] != usize::MAX {
+                    current = came_from[current];
+                    path.push((current % self.width, current / self.width));
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let tile_cost = cost[index(tile)];
+            for (next, step) in self.neighbours(tile) {
+                let next_cost = tile_cost + step;
+                if next_cost < cost[index(next)] {
+                    cost[index(next)] = next_cost;
+                    came_from[index(next)] = index(tile);
+                    let estimate = self.heuristic(next, goal);
+                    open.push(Reverse((next_cost + estimate, estimate, next)));
+                }
+            }
+        }
+        None
+    }
+
+    /// True if a straight line between the centers of the two tiles
+    /// only crosses walkable tiles. A line going exactly through a corner
+    /// needs both tiles next to the corner to be walkable
+    pub fn is_line_walkable(&self, from: Tile, to: Tile) -> bool {
+        let (mut x, mut y) = (from.0 as isize, from.1 as isize);
+        let (dx, dy) = (
+            to.0 as isize - from.0 as isize,
+            to.1 as isize - from.1 as isize,
+        );
+        let (step_x, step_y) = (dx.signum(), dy.signum());
+        let (nx, ny) = (dx.abs(), dy.abs());
+        let walkable = |x: isize, y: isize| self.is_walkable(x as usize, y as usize);
+        if !walkable(x, y) {
+            return false;
+        }
+        // walk the tiles crossed by the line, comparing the distance to the next
+        // vertical and horizontal grid line (scaled by 2 * nx * ny to stay in integers)
+        let (mut ix, mut iy) = (0, 0);
+        while ix < nx || iy < ny {
+            let next_x = (1 + 2 * ix) * ny;
+            let next_y = (1 + 2 * iy) * nx;
+            if next_x == next_y {
+                if !walkable(x + step_x, y) || !walkable(x, y + step_y) {
+                    return false;
+                }
+                x += step_x;
+                y += step_y;
+                ix += 1;
+                iy += 1;
+            } else if next_x < next_y {
+                x += step_x;
+                ix += 1;
+            } else {
+                y += step_y;
+                iy += 1;
+            }
+            if !walkable(x, y) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Removes the intermediate tiles of a path which can be skipped
+    /// by walking in a straight line, the first and last tiles are kept
+    pub fn smooth_path(&self, path: &[Tile]) -> Vec<Tile> {
+        let Some(&first) = path.first() else {
+            return Vec::new();
+        };
+        let mut smoothed = vec![first];
+        let mut anchor = 0;
+        for i in 1..path.len() {
+            let last = i + 1 == path.len();
+            if last || !self.is_line_walkable(path[anchor], path[i + 1]) {
+                smoothed.push(path[i]);
+                anchor = i;
+            }
+        }
+        smoothed
+    }
+}
+
+/// Distance from every tile to a target and the direction to follow to reach it,
+/// computed once for all the agents chasing the same target
+pub struct FlowField {
+    width: usize,
+    target: Option<Tile>,
+    /// Version of the grid the field was computed with
+    version: u64,
+    connectivity: Connectivity,
+    distances: Vec<u32>,
+}
+
+impl FlowField {
+    /// Empty field, nothing is reachable until [FlowField::update] is called
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            target: None,
+            version: 0,
+            connectivity: Connectivity::Four,
+            distances: Vec::new(),
+        }
+    }
+
+    pub fn get_target(&self) -> Option<Tile> {
+        self.target
+    }
+
+    /// Computes the field toward `target`, only if the target or the grid
+    /// changed since the last call. Returns true if it was computed
+    pub fn update(&mut self, grid: &NavGrid, target: Tile) -> bool {
+        let size = grid.width * grid.height;
+        if self.target == Some(target)
+            && self.version == grid.version
+            && self.connectivity == grid.connectivity
+            && self.distances.len() == size
+        {
+            return false;
+        }
+        self.width = grid.width;
+        self.target = Some(target);
+        self.version = grid.version;
+        self.connectivity = grid.connectivity;
+        self.distances.clear();
+        self.distances.resize(size, u32::MAX);
+        if !grid.is_walkable(target.0, target.1) {
+            return true;
+        }
+
+        // Dijkstra, the buckets of a queue per cost would be faster
+        // but the grids are small
+        let mut open = BinaryHeap::new();
+        self.distances[target.1 * self.width + target.0] = 0;
+        open.push(Reverse((0, target)));
+        while let Some(Reverse((distance, tile))) = open.pop() {
+            if distance > self.distances[tile.1 * self.width + tile.0] {
+                continue;
+            }
+            for (next, step) in grid.neighbours(tile) {
+                let index = next.1 * self.width + next.0;
+                if distance + step < self.distances[index] {
+                    self.distances[index] = distance + step;
+                    open.push(Reverse((distance + step, next)));
+                }
+            }
+        }
+        true
+    }
+
+    /// Cost to reach the target from the tile (10 per straight step,
+    /// 14 per diagonal step), None if it can't be reached
+    pub fn get_distance(&self, x: usize, y: usize) -> Option<u32> {
+        if self.width == 0 || x >= self.width {
+            return None;
+        }
+        let distance = *self.distances.get(y * self.width + x)?;
+        (distance != u32::MAX).then_some(distance)
+    }
+
+    /// Neighbour to move to from the tile to get closer to the target,
+    /// None on the target or when it can't be reached
+    pub fn next_step(&self, grid: &NavGrid, tile: Tile) -> Option<Tile> {
+        let distance = self.get_distance(tile.0, tile.1)?;
+        grid.neighbours(tile)
+            .into_iter()
+            .filter_map(|(next, _)| Some((next, self.get_distance(next.0, next.1)?)))
+            .filter(|(_, next_distance)| *next_distance < distance)
+            .min_by_key(|(_, next_distance)| *next_distance)
+            .map(|(next, _)| next)
+    }
+
+    /// Normalized direction from the tile to the next step
+    pub fn get_direction(&self, grid: &NavGrid, tile: Tile) -> Option<Vector<f32>> {
+        let next = self.next_step(grid, tile)?;
+        let mut direction =
+            Vector::new(next.0 as f32 - tile.0 as f32, next.1 as f32 - tile.1 as f32);
+        direction.normalize();
+        Some(direction)
+    }
+}
+
+impl Default for FlowField {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Breadth first search of the tiles reachable from `start`,
+/// mostly useful to check a map before placing things on it
+pub fn reachable_tiles(grid: &NavGrid, start: Tile) -> Vec<Tile> {
+    if !grid.is_walkable(start.0, start.1) {
+        return Vec::new();
+    }
+    let mut visited = vec![false; grid.width * grid.height];
+    let mut tiles = Vec::new();
+    let mut queue = VecDeque::from([start]);
+    visited[start.1 * grid.width + start.0] = true;
+    while let Some(tile) = queue.pop_front() {
+        tiles.push(tile);
+        for (next, _) in grid.neighbours(tile) {
+            let index = next.1 * grid.width + next.0;
+            if !visited[index] {
+                visited[index] = true;
+                queue.push_back(next);
+            }
+        }
+    }
+    tiles
+}
+
+/// A sound spreading through the walkable tiles, it goes around the walls
+/// and closed doors instead of through them
+pub struct Noise {
+    /// World position of the source
+    pub origin: Vector<f32>,
+    /// Number of steps to reach each tile, for the tiles it reaches
+    distances: HashMap<Tile, u32>,
+}
+
+impl Noise {
+    /// Spreads a noise made on the tile, up to `loudness` steps away
+    pub fn new(grid: &NavGrid, origin: Vector<f32>, tile: Tile, loudness: u32) -> Self {
+        let mut distances = HashMap::new();
+        if grid.is_walkable(tile.0, tile.1) {
+            distances.insert(tile, 0);
+            let mut queue = VecDeque::from([tile]);
+            while let Some(tile) = queue.pop_front() {
+                let distance = distances[&tile];
+                if distance == loudness {
+                    continue;
+                }
+                // diagonals would let the sound through the corners of the walls
+                for step in STRAIGHT {
+                    if let Some(next) = grid.offset(tile, step) {
+                        distances.entry(next).or_insert_with(|| {
+                            queue.push_back(next);
+                            distance + 1
+                        });
+                    }
+                }
+            }
+        }
+        Self { origin, distances }
+    }
+
+    /// Number of steps between the source and the tile, None if the noise doesn't reach it
+    pub fn get_distance(&self, x: usize, y: usize) -> Option<u32> {
+        self.distances.get(&(x, y)).copied()
+    }
+}
+
+#[cfg(test)]
+mod pathfinding_tests {
+    use super::*;
+
+    /// `#` are walls, `D` doors (tile 2)
+    fn map(rows: &[&str]) -> Map {
+        let tiles = rows
+            .iter()
+            .flat_map(|row| {
+                row.chars().map(|c| match c {
+                    '#' => 1,
+                    'D' => 2,
+                    _ => 0,
+                })
+            })
+            .collect();
+        Map::new("test".to_string(), rows[0].len(), rows.len(), 64.0, tiles)
+    }
+
+    fn maze() -> Map {
+        map(&[
+            "#########",
+            "#...#...#",
+            "#.#.#.#.#",
+            "#.#...#.#",
+            "#.#####.#",
+            "#.......#",
+            "#########",
+        ])
+    }
+
+    fn is_valid(grid: &NavGrid, path: &[Tile]) -> bool {
+        path.iter().all(|(x, y)| grid.is_walkable(*x, *y))
+            && path.windows(2).all(|step| {
+                let (a, b) = (step[0], step[1]);
+                grid.neighbours(a).iter().any(|(next, _)| *next == b)
+            })
+    }
+
+    #[test]
+    fn shortest_path() {
+        let grid = NavGrid::new(&maze(), Connectivity::Four);
+        let path = grid.find_path((1, 1), (7, 1)).unwrap();
+        assert!(is_valid(&grid, &path));
+        assert_eq!(path.first(), Some(&(1, 1)));
+        assert_eq!(path.last(), Some(&(7, 1)));
+        // through the middle passage, not around the bottom
+        assert_eq!(path.len(), 11);
+        assert_eq!(grid.find_path((1, 1), (1, 1)), Some(vec![(1, 1)]));
+        assert_eq!(grid.find_path((1, 1), (0, 0)), None);
+    }
+
+    #[test]
+    fn diagonals_dont_cut_corners() {
+        let map = map(&["#####", "#..##", "##..#", "#####"]);
+        let four = NavGrid::new(&map, Connectivity::Four);
+        let eight = NavGrid::new(&map, Connectivity::Eight);
+        let path = eight.find_path((1, 1), (3, 2)).unwrap();
+        assert!(is_valid(&eight, &path));
+        // (1, 1) -> (2, 2) would go between two walls
+        assert_eq!(path, vec![(1, 1), (2, 1), (2, 2), (3, 2)]);
+        assert_eq!(four.find_path((1, 1), (3, 2)), Some(path));
+
+        let open = map_open();
+        let eight = NavGrid::new(&open, Connectivity::Eight);
+        assert_eq!(eight.find_path((1, 1), (4, 4)).unwrap().len(), 4);
+    }
+
+    fn map_open() -> Map {
+        map(&["######", "#....#", "#....#", "#....#", "#....#", "######"])
+    }
+
+    #[test]
+    fn doors() {
+        let map = map(&["#######", "#..D..#", "#######"]);
+        let closed = NavGrid::new(&map, Connectivity::Four);
+        assert_eq!(closed.find_path((1, 1), (5, 1)), None);
+        let mut open = NavGrid::with_passable(&map, Connectivity::Four, |tile| tile != 1);
+        assert_eq!(open.find_path((1, 1), (5, 1)).unwrap().len(), 5);
+        open.set_walkable(3, 1, false);
+        assert_eq!(open.find_path((1, 1), (5, 1)), None);
+    }
+
+    #[test]
+    fn flow_field() {
+        let mut grid = NavGrid::new(&maze(), Connectivity::Four);
+        let mut field = FlowField::new();
+        assert!(field.update(&grid, (7, 1)));
+        assert!(!field.update(&grid, (7, 1)));
+        assert_eq!(field.get_distance(7, 1), Some(0));
+        assert_eq!(field.get_distance(1, 1), Some(10 * STRAIGHT_COST));
+        assert_eq!(field.get_distance(0, 0), None);
+
+        // following the field gives a shortest path
+        let mut tile = (1, 1);
+        let mut steps = 0;
+        while let Some(next) = field.next_step(&grid, tile) {
+            tile = next;
+            steps += 1;
+        }
+        assert_eq!((tile, steps), ((7, 1), 10));
+
+        // closing the middle passage forces the way around
+        grid.set_walkable(5, 3, false);
+        assert!(field.update(&grid, (7, 1)));
+        assert_eq!(field.get_distance(1, 1), Some(14 * STRAIGHT_COST));
+        // nothing to change outside of the grid
+        grid.set_walkable(100, 1, true);
+        assert!(!field.update(&grid, (7, 1)));
+        let direction = field.get_direction(&grid, (1, 1)).unwrap();
+        assert_eq!(direction, Vector::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn smoothing() {
+        let grid = NavGrid::new(&map_open(), Connectivity::Four);
+        let path = grid.find_path((1, 1), (4, 3)).unwrap();
+        assert_eq!(grid.smooth_path(&path), vec![(1, 1), (4, 3)]);
+
+        let grid = NavGrid::new(&maze(), Connectivity::Four);
+        let path = grid.find_path((1, 1), (7, 5)).unwrap();
+        let smoothed = grid.smooth_path(&path);
+        assert_eq!(smoothed.first(), Some(&(1, 1)));
+        assert_eq!(smoothed.last(), Some(&(7, 5)));
+        assert!(smoothed.len() < path.len());
+        assert!(smoothed
+            .windows(2)
+            .all(|step| grid.is_line_walkable(step[0], step[1])));
+    }
+
+    #[test]
+    fn line_through_corner() {
+        let grid = NavGrid::new(&map(&["####", "#.##", "##.#", "####"]), Connectivity::Eight);
+        assert!(!grid.is_line_walkable((1, 1), (2, 2)));
+        let grid = NavGrid::new(&map_open(), Connectivity::Eight);
+        assert!(grid.is_line_walkable((1, 1), (4, 4)));
+        assert_eq!(reachable_tiles(&grid, (1, 1)).len(), 16);
+    }
+
+    #[test]
+    fn noise_goes_around_walls() {
+        let grid = NavGrid::new(&maze(), Connectivity::Eight);
+        let noise = Noise::new(&grid, Vector::new(0.0, 0.0), (1, 1), 10);
+        assert_eq!(noise.get_distance(1, 1), Some(0));
+        assert_eq!(noise.get_distance(7, 1), Some(10));
+        assert_eq!(noise.get_distance(2, 2), None);
+        // 4 steps down then 6 to the right
+        assert_eq!(noise.get_distance(7, 5), Some(10));
+        // 12 steps by both ways
+        assert_eq!(noise.get_distance(7, 3), None);
+    }
+}
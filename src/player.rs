@@ -1,18 +1,36 @@
+use crate::camera::{Camera, DEFAULT_HEIGHT};
+use crate::collision::slide;
+use crate::inventory::{Inventory, Pickup};
 use crate::map::Map;
-use crate::render::Render;
+use crate::render::{Render, TopDownView};
+use crate::replay::{Action, InputFrame};
 use crate::vector::Vector;
+use crate::weapon::{find_weapon, Weapon, PISTOL};
 use macroquad::prelude::{draw_circle, draw_line, RED, YELLOW};
 
-const PLAYER_RADIUS: f32 = 10.0;
+pub const PLAYER_RADIUS: f32 = 10.0;
+/// Movement speed, in world units per second
+pub const MOVE_SPEED: f32 = 100.0;
+/// Rotation speed, in radians per second
+const TURN_SPEED: f32 = 6.0;
+/// Speed of the zoom and of the pitch changes, per second
+const VIEW_SPEED: f32 = 1.0;
+/// Eye height when crouching, relative to the default one
+const CROUCH_HEIGHT: f32 = 0.6;
+pub const MAX_HEALTH: i32 = 100;
+pub const MAX_ARMOR: i32 = 100;
+/// Distance in front of the player where doors and switches are touched, in world units
+const REACH: f32 = 8.0;
+/// Ammo given with a weapon picked up
+const WEAPON_PICKUP_AMMO: u32 = 10;
 
 pub struct Player {
-    pub pos: Vector<f32>,
+    pos: Vector<f32>,
     angle: f32,
-}
-
-pub enum RayCastResult {
-    Hit(f32, Vector<usize>, bool),
-    NoHit,
+    camera: Camera,
+    health: i32,
+    armor: i32,
+    inventory: Inventory,
 }
 
 impl Player {
@@ -20,155 +38,260 @@ impl Player {
         Self {
             pos: Vector::new(x, y),
             angle,
+            camera: Camera::new(x, y, angle),
+            health: MAX_HEALTH,
+            armor: 0,
+            inventory: Inventory::new(),
         }
+        .with_weapon(Weapon::new(PISTOL, 30))
     }
 
-    pub fn rotate(&mut self, angle: f32) {
-        self.angle += angle;
+    fn with_weapon(mut self, weapon: Weapon) -> Self {
+        self.inventory
+            .add_weapon(*weapon.get_def(), weapon.get_ammo());
+        self
+    }
 
-        if self.angle > std::f32::consts::PI {
-            self.angle -= std::f32::consts::TAU;
-        }
+    pub fn get_health(&self) -> i32 {
+        self.health
+    }
 
-        if self.angle < -std::f32::consts::PI {
-            self.angle += std::f32::consts::TAU;
-        }
+    pub fn get_armor(&self) -> i32 {
+        self.armor
     }
 
-    pub fn move_forward(&mut self, distance: f32, map: &Map) {
-        let x = self.pos.x + self.angle.cos() * distance;
-        let y = self.pos.y + self.angle.sin() * distance;
+    pub fn set_health(&mut self, health: i32) {
+        self.health = health.clamp(0, MAX_HEALTH);
+    }
 
-        self.move_collision_check(x, y, map, false);
+    pub fn set_armor(&mut self, armor: i32) {
+        self.armor = armor.clamp(0, MAX_ARMOR);
     }
 
-    pub fn move_backward(&mut self, distance: f32, map: &Map) {
-        let x = self.pos.x - self.angle.cos() * distance;
-        let y = self.pos.y - self.angle.sin() * distance;
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0
+    }
 
-        self.move_collision_check(x, y, map, true);
-    }
-
-    fn move_collision_check(&mut self, x: f32, y: f32, map: &Map, rev: bool) {
-        let map_pos = map.to_map_coordinates(&self.pos);
-        let x_rad = x
-            + (if rev ^ (self.angle.abs() < std::f32::consts::PI / 2.0) {
-                PLAYER_RADIUS
-            } else {
-                -PLAYER_RADIUS
-            });
-        let y_rad = y
-            + (if rev ^ (self.angle > 0.0) {
-                PLAYER_RADIUS
-            } else {
-                -PLAYER_RADIUS
-            });
-
-        let new_map_pos = map.to_map_coordinates(&Vector::new(x_rad, y_rad));
-        if map_pos.is_none() || new_map_pos.is_none() {
-            return;
-        }
+    /// Removes health, the armor absorbs half of the damage while it lasts.
+    /// A negative amount does nothing
+    pub fn damage(&mut self, amount: i32) {
+        let amount = amount.max(0);
+        let absorbed = (amount / 2).min(self.armor);
+        self.armor -= absorbed;
+        self.health = (self.health - (amount - absorbed)).max(0);
+    }
 
-        let map_pos = map_pos.unwrap();
-        let new_map_pos = new_map_pos.unwrap();
+    pub fn get_inventory(&self) -> &Inventory {
+        &self.inventory
+    }
 
-        if map[(new_map_pos.x, map_pos.y)] != 1 {
-            self.pos.x = x;
-        }
-        if map[(map_pos.x, new_map_pos.y)] != 1 {
-            self.pos.y = y;
-        }
+    pub fn get_inventory_mut(&mut self) -> &mut Inventory {
+        &mut self.inventory
     }
 
-    /// cast a single ray from (x, y) and return the distance to the nearest wall
-    /// Return [RayCastResult::Hit] if the ray hit a wall
-    /// Return [RayCastResult::NoHit] if the ray didn't hit a wall
-    pub fn raycast(&self, map: &Map, offset: f32) -> RayCastResult {
-        if !map.in_map(&self.pos) {
-            return RayCastResult::NoHit;
+    /// Takes a pickup, returns false if the player doesn't need it
+    /// (full health, full ammo...) so it stays in place
+    pub fn collect(&mut self, pickup: &Pickup) -> bool {
+        let refill = |value: &mut i32, amount: i32, max: i32| {
+            if *value >= max {
+                return false;
+            }
+            *value = (*value + amount).min(max);
+            true
+        };
+        match pickup {
+            Pickup::Health(amount) => refill(&mut self.health, *amount, MAX_HEALTH),
+            Pickup::Armor(amount) => refill(&mut self.armor, *amount, MAX_ARMOR),
+            Pickup::Ammo { weapon, amount } => self.inventory.add_ammo(weapon, *amount),
+            Pickup::Weapon(name) => find_weapon(name)
+                .is_some_and(|def| self.inventory.add_weapon(def, WEAPON_PICKUP_AMMO)),
+            Pickup::Key(key) => self.inventory.add_key(key),
+            Pickup::Item { name, count } => {
+                self.inventory.add_item(name, *count);
+                true
+            }
         }
+    }
 
-        let pos = self.pos / map.get_tile_size();
-        let angle = self.angle + offset;
-        let direction = Vector::new(angle.cos(), angle.sin());
+    /// Tile right in front of the player, the one doors and switches are used on
+    pub fn facing_tile(&self, map: &Map) -> Option<(usize, usize)> {
+        let direction = Vector::new(self.angle.cos(), self.angle.sin());
+        let point = self.pos + direction * (PLAYER_RADIUS + REACH);
+        let tile = map.to_map_coordinates(&point)?;
+        Some((tile.x, tile.y))
+    }
 
-        let step_size = Vector::new(
-            (1.0 + (direction.y / direction.x).powi(2)).sqrt(),
-            (1.0 + (direction.x / direction.y).powi(2)).sqrt(),
-        );
+    /// Locked door tile right in front of the player, if any
+    pub fn touched_door(&self, map: &Map) -> Option<(usize, usize)> {
+        self.facing_tile(map)
+            .filter(|tile| map.get_lock(map[*tile]).is_some())
+    }
 
-        let mut map_pos: Vector<i32> = pos.to_i32();
-        let ray_len_x = if direction.x < 0.0 {
-            (pos.x - map_pos.x as f32) * step_size.x
-        } else {
-            ((map_pos.x as f32 + 1.0) - pos.x) * step_size.x
-        };
+    /// Moves the player without checking the walls, keeps the angle if None
+    pub fn teleport(&mut self, pos: Vector<f32>, angle: Option<f32>) {
+        self.pos = pos;
+        self.camera.set_position(pos);
+        if let Some(angle) = angle {
+            self.angle = 0.0;
+            self.rotate(angle);
+        }
+    }
 
-        let ray_len_y = if direction.y < 0.0 {
-            (pos.y - map_pos.y as f32) * step_size.y
-        } else {
-            ((map_pos.y as f32 + 1.0) - pos.y) * step_size.y
-        };
+    pub fn get_pos(&self) -> Vector<f32> {
+        self.pos
+    }
 
-        let mut ray_len = Vector::new(ray_len_x, ray_len_y);
+    pub fn get_angle(&self) -> f32 {
+        self.angle
+    }
 
-        let step = Vector::new(
-            if direction.x < 0.0 { -1 } else { 1 },
-            if direction.y < 0.0 { -1 } else { 1 },
-        );
+    pub fn get_camera(&self) -> &Camera {
+        &self.camera
+    }
 
-        let mut hit = false;
-        let mut side = false;
-        let mut out = false;
-        let mut distance = 0.0;
-
-        while !hit && !out {
-            if ray_len.x < ray_len.y {
-                distance = ray_len.x;
-                map_pos.x += step.x;
-                ray_len.x += step_size.x;
-                side = false;
-            } else {
-                distance = ray_len.y;
-                map_pos.y += step.y;
-                ray_len.y += step_size.y;
-                side = true;
-            }
+    /// Gives access to the view settings (fov, pitch, height), the camera
+    /// position and angle are overwritten when the player moves
+    pub fn get_camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
 
-            if map_pos.x < 0
-                || map_pos.x >= map.get_width() as i32
-                || map_pos.y < 0
-                || map_pos.y >= map.get_height() as i32
-            {
-                out = true;
-            } else if map[(map_pos.x as usize, map_pos.y as usize)] == 1 {
-                hit = true;
-            }
+    /// Moves the player and changes the view according to the actions held
+    /// during a frame. Only the frame data is used so replaying the same
+    /// frames gives the same result
+    pub fn update(&mut self, input: &InputFrame, map: &Map) {
+        let delta = input.delta;
+        if input.is_down(Action::TurnLeft) {
+            self.rotate(-TURN_SPEED * delta);
+        }
+        if input.is_down(Action::TurnRight) {
+            self.rotate(TURN_SPEED * delta);
+        }
+        if input.is_down(Action::Forward) {
+            self.move_forward(MOVE_SPEED * delta, map);
+        }
+        if input.is_down(Action::Backward) {
+            self.move_backward(MOVE_SPEED * delta, map);
         }
 
-        if hit {
-            let perp_distance = distance * offset.cos();
-            RayCastResult::Hit(
-                perp_distance,
-                Vector::new(map_pos.x as usize, map_pos.y as usize),
-                side,
-            )
+        let camera = &mut self.camera;
+        if input.is_down(Action::ZoomIn) {
+            camera.set_fov(camera.get_fov() - VIEW_SPEED * delta);
+        }
+        if input.is_down(Action::ZoomOut) {
+            camera.set_fov(camera.get_fov() + VIEW_SPEED * delta);
+        }
+        if input.is_down(Action::LookUp) {
+            camera.set_pitch(camera.get_pitch() + VIEW_SPEED * delta);
+        }
+        if input.is_down(Action::LookDown) {
+            camera.set_pitch(camera.get_pitch() - VIEW_SPEED * delta);
+        }
+        if input.is_down(Action::Crouch) {
+            camera.set_height(DEFAULT_HEIGHT * CROUCH_HEIGHT);
         } else {
-            RayCastResult::NoHit
+            camera.set_height(DEFAULT_HEIGHT);
+        }
+    }
+
+    pub fn rotate(&mut self, angle: f32) {
+        self.angle += angle;
+
+        if self.angle > std::f32::consts::PI {
+            self.angle -= std::f32::consts::TAU;
         }
+
+        if self.angle < -std::f32::consts::PI {
+            self.angle += std::f32::consts::TAU;
+        }
+
+        self.camera.set_angle(self.angle);
+    }
+
+    pub fn move_forward(&mut self, distance: f32, map: &Map) {
+        let x = self.pos.x + self.angle.cos() * distance;
+        let y = self.pos.y + self.angle.sin() * distance;
+
+        self.move_collision_check(x, y, map);
+    }
+
+    pub fn move_backward(&mut self, distance: f32, map: &Map) {
+        let x = self.pos.x - self.angle.cos() * distance;
+        let y = self.pos.y - self.angle.sin() * distance;
+
+        self.move_collision_check(x, y, map);
+    }
+
+    fn move_collision_check(&mut self, x: f32, y: f32, map: &Map) {
+        self.pos = slide(map, self.pos, Vector::new(x, y), PLAYER_RADIUS);
+        self.camera.set_position(self.pos);
     }
 }
 
 impl Render for Player {
-    fn render(&self) {
-        draw_circle(self.pos.x, self.pos.y, 10.0, RED);
+    fn render(&self, view: &TopDownView) {
+        let pos = view.project(self.pos);
+        let radius = PLAYER_RADIUS * view.scale;
+        draw_circle(pos.x, pos.y, radius, view.color(RED));
         draw_line(
-            self.pos.x,
-            self.pos.y,
-            (self.angle.cos() * 30.0) + self.pos.x,
-            (self.angle.sin() * 30.0) + self.pos.y,
+            pos.x,
+            pos.y,
+            (self.angle.cos() * radius * 3.0) + pos.x,
+            (self.angle.sin() * radius * 3.0) + pos.y,
             1.0,
-            YELLOW,
+            view.color(YELLOW),
         );
     }
 }
+
+#[cfg(test)]
+mod player_tests {
+    use super::*;
+
+    #[test]
+    fn damage_and_pickups() {
+        let mut player = Player::new(0.0, 0.0, 0.0);
+        assert!(!player.collect(&Pickup::Health(10)));
+        assert!(player.collect(&Pickup::Armor(20)));
+        player.damage(30);
+        assert_eq!((player.get_health(), player.get_armor()), (85, 5));
+        player.damage(30);
+        assert_eq!((player.get_health(), player.get_armor()), (60, 0));
+        assert!(player.collect(&Pickup::Health(50)));
+        assert_eq!(player.get_health(), MAX_HEALTH);
+        player.damage(500);
+        assert!(player.is_dead());
+
+        let inventory = player.get_inventory();
+        assert_eq!(inventory.get_weapon().unwrap().get_def().name, "pistol");
+        assert!(player.collect(&Pickup::Weapon("launcher".to_string())));
+        let weapon = player.get_inventory().get_weapon().unwrap();
+        assert_eq!(weapon.get_ammo(), WEAPON_PICKUP_AMMO);
+    }
+
+    #[test]
+    fn negative_damage() {
+        let mut player = Player::new(0.0, 0.0, 0.0);
+        player.collect(&Pickup::Armor(20));
+        player.damage(-100);
+        assert_eq!((player.get_health(), player.get_armor()), (MAX_HEALTH, 20));
+        player.damage(i32::MIN);
+        assert_eq!((player.get_health(), player.get_armor()), (MAX_HEALTH, 20));
+    }
+
+    #[test]
+    fn touches_locked_doors() {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1,
+            1, 0, 2, 1,
+            1, 1, 1, 1,
+        ];
+        let mut map = Map::new("door".to_string(), 4, 3, 64.0, tiles);
+        let player = Player::new(115.0, 96.0, 0.0);
+        assert_eq!(player.touched_door(&map), None);
+        map.set_lock(2, Some("red".to_string()));
+        assert_eq!(player.touched_door(&map), Some((2, 1)));
+        let player = Player::new(80.0, 96.0, 0.0);
+        assert_eq!(player.touched_door(&map), None);
+    }
+}
@@ -0,0 +1,587 @@
+use crate::framebuffer::{Framebuffer, Pixel};
+use std::any::Any;
+
+/// 4x4 Bayer matrix used by the ordered dithering
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// An effect applied to the framebuffer once the view is rendered
+pub trait PostEffect: Any {
+    /// Name used to toggle the effect in a [PostProcess]
+    fn name(&self) -> &'static str;
+
+    /// Advances timed effects (shake, flash...), called once per frame
+    fn update(&mut self, _delta: f32) {}
+
+    /// Inactive effects are skipped by the pipeline
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    /// Writes the processed `source` in `target`, both have the same size
+    fn apply(&self, source: &Framebuffer, target: &mut Framebuffer);
+
+    /// Writes the settings of the effect for the shader path,
+    /// effects without a shader equivalent leave them untouched
+    fn shader_params(&self, _params: &mut ShaderParams) {}
+}
+
+/// Uniforms of the post-processing shader, the default values
+/// leave the image unchanged
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShaderParams {
+    /// Levels per channel, 0 disables the dithering
+    pub dither_levels: f32,
+    pub scanlines: f32,
+    pub curvature: f32,
+    pub vignette: f32,
+    pub vignette_radius: f32,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    pub tint: [f32; 3],
+    /// Flash color and amount
+    pub flash: [f32; 4],
+    /// Image offset, in fraction of the image size
+    pub offset: [f32; 2],
+}
+
+impl Default for ShaderParams {
+    fn default() -> Self {
+        Self {
+            dither_levels: 0.0,
+            scanlines: 0.0,
+            curvature: 0.0,
+            vignette: 0.0,
+            vignette_radius: 1.0,
+            brightness: 1.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            tint: [1.0; 3],
+            flash: [0.0; 4],
+            offset: [0.0; 2],
+        }
+    }
+}
+
+/// Calls `f` for every pixel of `source` and writes the result in `target`
+fn map_pixels(
+    source: &Framebuffer,
+    target: &mut Framebuffer,
+    f: impl Fn(usize, usize, Pixel) -> Pixel,
+) {
+    let width = source.get_width().max(1);
+    for (i, (to, from)) in target
+        .get_pixels_mut()
+        .iter_mut()
+        .zip(source.get_pixels())
+        .enumerate()
+    {
+        *to = f(i % width, i / width, *from);
+    }
+}
+
+/// Pixel of `source` at the given coordinates, clamped to the edges
+fn sample_clamped(source: &Framebuffer, x: isize, y: isize) -> Pixel {
+    let x = x.clamp(0, source.get_width() as isize - 1) as usize;
+    let y = y.clamp(0, source.get_height() as isize - 1) as usize;
+    source.get_pixel(x, y)
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+/// Ordered dithering down to `levels` values per channel
+pub struct Dither {
+    pub levels: u8,
+}
+
+impl PostEffect for Dither {
+    fn name(&self) -> &'static str {
+        "dither"
+    }
+
+    fn apply(&self, source: &Framebuffer, target: &mut Framebuffer) {
+        let step = 255.0 / (self.levels.max(2) - 1) as f32;
+        map_pixels(source, target, |x, y, pixel| {
+            let threshold = (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+            let mut out = pixel;
+            for channel in out.iter_mut().take(3) {
+                let level = (*channel as f32 / step + threshold).round();
+                *channel = to_u8(level * step);
+            }
+            out
+        });
+    }
+
+    fn shader_params(&self, params: &mut ShaderParams) {
+        params.dither_levels = self.levels.max(2) as f32;
+    }
+}
+
+/// CRT screen: scanlines and barrel distortion
+pub struct Crt {
+    /// Darkening of every other row, between 0 and 1
+    pub scanlines: f32,
+    /// Strength of the barrel distortion, 0 keeps the image flat
+    pub curvature: f32,
+}
+
+impl PostEffect for Crt {
+    fn name(&self) -> &'static str {
+        "crt"
+    }
+
+    fn apply(&self, source: &Framebuffer, target: &mut Framebuffer) {
+        let (width, height) = (source.get_width() as f32, source.get_height() as f32);
+        let scanline = 1.0 - self.scanlines.clamp(0.0, 1.0);
+        map_pixels(source, target, |x, y, _| {
+            // position between -1 and 1
+            let u = (x as f32 + 0.5) / width * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / height * 2.0 - 1.0;
+            let u = u * (1.0 + v * v * self.curvature);
+            let v = v * (1.0 + u * u * self.curvature);
+            if u.abs() > 1.0 || v.abs() > 1.0 {
+                return [0, 0, 0, 255];
+            }
+            let sx = ((u + 1.0) / 2.0 * width) as isize;
+            let sy = ((v + 1.0) / 2.0 * height) as isize;
+            let pixel = sample_clamped(source, sx, sy);
+            if y % 2 == 1 {
+                let mut dark = pixel;
+                for channel in dark.iter_mut().take(3) {
+                    *channel = to_u8(*channel as f32 * scanline);
+                }
+                dark
+            } else {
+                pixel
+            }
+        });
+    }
+
+    fn shader_params(&self, params: &mut ShaderParams) {
+        params.scanlines = self.scanlines;
+        params.curvature = self.curvature;
+    }
+}
+
+/// Darkens the borders of the image
+pub struct Vignette {
+    /// Darkening of the corners, between 0 and 1
+    pub strength: f32,
+    /// Distance from the center where the darkening starts,
+    /// 1 is the distance of the corners
+    pub radius: f32,
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &'static str {
+        "vignette"
+    }
+
+    fn apply(&self, source: &Framebuffer, target: &mut Framebuffer) {
+        let (width, height) = (source.get_width() as f32, source.get_height() as f32);
+        let radius = self.radius.clamp(0.0, 0.999);
+        map_pixels(source, target, |x, y, pixel| {
+            let u = (x as f32 + 0.5) / width * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / height * 2.0 - 1.0;
+            let distance = ((u * u + v * v) / 2.0).sqrt();
+            let t = ((distance - radius) / (1.0 - radius)).clamp(0.0, 1.0);
+            let factor = 1.0 - self.strength * t * t * (3.0 - 2.0 * t);
+            let mut out = pixel;
+            for channel in out.iter_mut().take(3) {
+                *channel = to_u8(*channel as f32 * factor);
+            }
+            out
+        });
+    }
+
+    fn shader_params(&self, params: &mut ShaderParams) {
+        params.vignette = self.strength;
+        params.vignette_radius = self.radius;
+    }
+}
+
+/// Brightness, contrast, saturation and tint
+pub struct ColorGrade {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    /// Multiplier of each channel
+    pub tint: [f32; 3],
+}
+
+impl Default for ColorGrade {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            tint: [1.0; 3],
+        }
+    }
+}
+
+impl PostEffect for ColorGrade {
+    fn name(&self) -> &'static str {
+        "grade"
+    }
+
+    fn apply(&self, source: &Framebuffer, target: &mut Framebuffer) {
+        map_pixels(source, target, |_, _, pixel| {
+            let color = [0, 1, 2].map(|channel| pixel[channel] as f32 / 255.0);
+            let luma = 0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2];
+            let mut out = pixel;
+            for channel in 0..3 {
+                let value = luma + (color[channel] - luma) * self.saturation;
+                let value = (value - 0.5) * self.contrast + 0.5;
+                let value = value * self.brightness * self.tint[channel];
+                out[channel] = to_u8(value * 255.0);
+            }
+            out
+        });
+    }
+
+    fn shader_params(&self, params: &mut ShaderParams) {
+        params.brightness = self.brightness;
+        params.contrast = self.contrast;
+        params.saturation = self.saturation;
+        params.tint = self.tint;
+    }
+}
+
+/// Shakes the image, the shake fades out over its duration
+#[derive(Default)]
+pub struct Shake {
+    /// Maximum offset, in fraction of the image height
+    amount: f32,
+    duration: f32,
+    remaining: f32,
+    time: f32,
+}
+
+impl Shake {
+    /// Starts a shake, `amount` is the maximum offset in fraction of the image height
+    pub fn start(&mut self, amount: f32, duration: f32) {
+        self.amount = amount;
+        self.duration = duration.max(f32::EPSILON);
+        self.remaining = duration;
+    }
+
+    /// Current offset, in fraction of the image height
+    pub fn get_offset(&self) -> (f32, f32) {
+        if self.remaining <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let strength = self.amount * self.remaining / self.duration;
+        (
+            (self.time * 47.0).sin() * strength,
+            (self.time * 31.0).cos() * strength,
+        )
+    }
+}
+
+impl PostEffect for Shake {
+    fn name(&self) -> &'static str {
+        "shake"
+    }
+
+    fn update(&mut self, delta: f32) {
+        self.time += delta;
+        self.remaining = (self.remaining - delta).max(0.0);
+    }
+
+    fn is_active(&self) -> bool {
+        self.remaining > 0.0
+    }
+
+    fn apply(&self, source: &Framebuffer, target: &mut Framebuffer) {
+        let (dx, dy) = self.get_offset();
+        let height = source.get_height() as f32;
+        let (dx, dy) = ((dx * height) as isize, (dy * height) as isize);
+        map_pixels(source, target, |x, y, _| {
+            sample_clamped(source, x as isize - dx, y as isize - dy)
+        });
+    }
+
+    fn shader_params(&self, params: &mut ShaderParams) {
+        let (dx, dy) = self.get_offset();
+        params.offset = [dx, dy];
+    }
+}
+
+/// How much of the damage flash disappears every second
+const FLASH_FADE: f32 = 2.0;
+
+/// Blends the image with a color fading over time
+pub struct DamageFlash {
+    color: Pixel,
+    amount: f32,
+}
+
+impl DamageFlash {
+    pub fn new() -> Self {
+        Self {
+            color: [255, 0, 0, 255],
+            amount: 0.0,
+        }
+    }
+
+    /// Starts a flash of the given color, `amount` is its initial strength
+    pub fn start(&mut self, color: Pixel, amount: f32) {
+        self.color = color;
+        self.amount = amount.clamp(0.0, 1.0);
+    }
+}
+
+impl Default for DamageFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostEffect for DamageFlash {
+    fn name(&self) -> &'static str {
+        "flash"
+    }
+
+    fn update(&mut self, delta: f32) {
+        self.amount = (self.amount - delta * FLASH_FADE).max(0.0);
+    }
+
+    fn is_active(&self) -> bool {
+        self.amount > 0.0
+    }
+
+    fn apply(&self, source: &Framebuffer, target: &mut Framebuffer) {
+        map_pixels(source, target, |_, _, pixel| {
+            let mut out = pixel;
+            for channel in 0..3 {
+                let from = pixel[channel] as f32;
+                out[channel] = to_u8(from + (self.color[channel] as f32 - from) * self.amount);
+            }
+            out
+        });
+    }
+
+    fn shader_params(&self, params: &mut ShaderParams) {
+        let color = self.color.map(|c| c as f32 / 255.0);
+        params.flash = [color[0], color[1], color[2], self.amount];
+    }
+}
+
+struct Stage {
+    effect: Box<dyn PostEffect>,
+    enabled: bool,
+}
+
+/// A chain of post effects, applied in the order they were added
+pub struct PostProcess {
+    stages: Vec<Stage>,
+    scratch: Framebuffer,
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        Self {
+            stages: Vec::new(),
+            scratch: Framebuffer::new(0, 0),
+        }
+    }
+
+    /// Every effect of this module, only the timed effects
+    /// (shake and flash) are enabled
+    pub fn standard() -> Self {
+        let mut post = Self::new();
+        post.add(
+            ColorGrade {
+                contrast: 1.1,
+                saturation: 1.2,
+                tint: [1.05, 1.0, 0.9],
+                ..Default::default()
+            },
+            false,
+        );
+        post.add(DamageFlash::new(), true);
+        post.add(Shake::default(), true);
+        post.add(
+            Crt {
+                scanlines: 0.3,
+                curvature: 0.05,
+            },
+            false,
+        );
+        post.add(
+            Vignette {
+                strength: 0.6,
+                radius: 0.5,
+            },
+            false,
+        );
+        post.add(Dither { levels: 6 }, false);
+        post
+    }
+
+    /// Adds an effect at the end of the chain
+    pub fn add(&mut self, effect: impl PostEffect, enabled: bool) {
+        self.stages.push(Stage {
+            effect: Box::new(effect),
+            enabled,
+        });
+    }
+
+    /// Names of the effects, in order
+    pub fn get_names(&self) -> Vec<&'static str> {
+        self.stages
+            .iter()
+            .map(|stage| stage.effect.name())
+            .collect()
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.stages
+            .iter()
+            .any(|stage| stage.enabled && stage.effect.name() == name)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        for stage in self.stages.iter_mut() {
+            if stage.effect.name() == name {
+                stage.enabled = enabled;
+            }
+        }
+    }
+
+    /// Toggles the effect, returns whether it is now enabled
+    pub fn toggle(&mut self, name: &str) -> bool {
+        let enabled = !self.is_enabled(name);
+        self.set_enabled(name, enabled);
+        enabled
+    }
+
+    /// First effect of the given type, to change its settings
+    #[allow(dead_code)]
+    pub fn get<T: PostEffect>(&self) -> Option<&T> {
+        self.stages
+            .iter()
+            .find_map(|stage| (stage.effect.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    pub fn get_mut<T: PostEffect>(&mut self) -> Option<&mut T> {
+        self.stages
+            .iter_mut()
+            .find_map(|stage| (stage.effect.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        for stage in self.stages.iter_mut() {
+            stage.effect.update(delta);
+        }
+    }
+
+    /// Applies every enabled and active effect to the framebuffer
+    pub fn apply(&mut self, framebuffer: &mut Framebuffer) {
+        for stage in &self.stages {
+            if !stage.enabled || !stage.effect.is_active() {
+                continue;
+            }
+            self.scratch
+                .resize(framebuffer.get_width(), framebuffer.get_height());
+            stage.effect.apply(framebuffer, &mut self.scratch);
+            std::mem::swap(framebuffer, &mut self.scratch);
+        }
+    }
+
+    /// Uniforms of the shader path for the enabled effects
+    pub fn shader_params(&self) -> ShaderParams {
+        let mut params = ShaderParams::default();
+        for stage in self.stages.iter().filter(|stage| stage.enabled) {
+            stage.effect.shader_params(&mut params);
+        }
+        params
+    }
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod postfx_tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = (x * 255 / (width - 1)) as u8;
+                framebuffer.set_pixel(x, y, [value, value, 128, 255]);
+            }
+        }
+        framebuffer
+    }
+
+    #[test]
+    fn disabled_effects_are_skipped() {
+        let mut post = PostProcess::standard();
+        let mut framebuffer = gradient(16, 8);
+        let original = framebuffer.get_pixels().to_vec();
+        post.apply(&mut framebuffer);
+        assert!(framebuffer.get_pixels() == original.as_slice());
+
+        assert!(post.toggle("vignette"));
+        post.apply(&mut framebuffer);
+        assert!(framebuffer.get_pixels() != original.as_slice());
+    }
+
+    #[test]
+    fn neutral_grade_keeps_image() {
+        let source = gradient(16, 8);
+        let mut target = Framebuffer::new(16, 8);
+        ColorGrade::default().apply(&source, &mut target);
+        assert!(source.get_pixels() == target.get_pixels());
+    }
+
+    #[test]
+    fn dither_uses_levels() {
+        let source = gradient(16, 8);
+        let mut target = Framebuffer::new(16, 8);
+        Dither { levels: 4 }.apply(&source, &mut target);
+        for pixel in target.get_pixels() {
+            for channel in &pixel[..3] {
+                assert!([0, 85, 170, 255].contains(channel), "{}", channel);
+            }
+        }
+    }
+
+    #[test]
+    fn vignette_darkens_corners() {
+        let mut source = Framebuffer::new(21, 21);
+        source.clear([200, 200, 200, 255]);
+        let mut target = Framebuffer::new(21, 21);
+        Vignette {
+            strength: 1.0,
+            radius: 0.3,
+        }
+        .apply(&source, &mut target);
+        assert_eq!(target.get_pixel(10, 10), [200, 200, 200, 255]);
+        assert!(target.get_pixel(0, 0)[0] < 20);
+    }
+
+    #[test]
+    fn timed_effects_fade() {
+        let mut post = PostProcess::standard();
+        post.get_mut::<Shake>().unwrap().start(0.1, 0.5);
+        post.get_mut::<DamageFlash>()
+            .unwrap()
+            .start([255, 0, 0, 255], 1.0);
+        assert_ne!(post.shader_params(), ShaderParams::default());
+
+        post.update(1.0);
+        assert!(!post.get::<Shake>().unwrap().is_active());
+        assert!(!post.get::<DamageFlash>().unwrap().is_active());
+        assert_eq!(post.get::<Shake>().unwrap().get_offset(), (0.0, 0.0));
+    }
+}
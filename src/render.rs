@@ -1,3 +1,305 @@
+use crate::camera::{Camera, RayCastResult};
+use crate::framebuffer::Framebuffer;
+use crate::map::Map;
+use crate::postfx::ShaderParams;
+use crate::textures::TextureManager;
+use crate::vector::Vector;
+use macroquad::prelude::{
+	draw_rectangle, draw_texture_ex, gl_use_default_material, gl_use_material, load_material, vec2, vec3, vec4, Color,
+	DrawTextureParams, FilterMode, Material, MaterialParams, Rect, ShaderSource, Texture2D, UniformDesc, UniformType,
+	WHITE,
+};
+
+/// Transformation from world coordinates to screen coordinates
+/// used by the top-down views
+#[derive(Clone, Copy)]
+pub struct TopDownView {
+	pub origin: Vector<f32>,
+	pub scale: f32,
+	/// Multiplies the alpha of everything drawn in the view
+	pub opacity: f32,
+}
+
+impl TopDownView {
+	/// Fits the whole map in the given viewport, centered
+	pub fn fit(map: &Map, viewport: Rect) -> Self {
+		let tile_size = map.auto_tile_size(viewport.w, viewport.h);
+		let map_width = tile_size * map.get_width() as f32;
+		let map_height = tile_size * map.get_height() as f32;
+		Self {
+			origin: Vector::new(
+				viewport.x + (viewport.w - map_width) / 2.0,
+				viewport.y + (viewport.h - map_height) / 2.0,
+			),
+			scale: tile_size / map.get_tile_size(),
+			opacity: 1.0,
+		}
+	}
+
+	pub fn project(&self, pos: Vector<f32>) -> Vector<f32> {
+		self.origin + pos * self.scale
+	}
+
+	pub fn color(&self, color: Color) -> Color {
+		Color::new(color.r, color.g, color.b, color.a * self.opacity)
+	}
+}
+
+/// Something which can be drawn in a top-down view
 pub trait Render {
-	fn render(&self);
-}
\ No newline at end of file
+	fn render(&self, view: &TopDownView);
+}
+
+/// Draws the 3D view seen by the given camera inside the given viewport,
+/// several viewports can be drawn in the same frame.
+/// Only one ray every `column_step` columns is cast and its wall slice
+/// is stretched over the skipped columns.
+/// Walls with a texture are drawn from the atlas
+pub fn draw_view(
+	map: &Map,
+	camera: &Camera,
+	textures: &TextureManager,
+	atlas: &AtlasTexture,
+	viewport: Rect,
+	column_step: usize,
+) {
+	let focal_length = camera.focal_length(viewport.w);
+	let horizon = viewport.y + camera.horizon(viewport.h);
+	let column_step = column_step.max(1);
+
+	for i in (0..viewport.w as i32).step_by(column_step) {
+		let direction = camera.ray_direction(i as f32, viewport.w);
+		match camera.raycast(map, direction) {
+			RayCastResult::NoHit => {}
+			RayCastResult::Hit(distance, tile, side) => {
+				let height = focal_length / distance;
+				let wall_top = horizon - height * (1.0 - camera.get_height());
+				let top = wall_top.max(viewport.y);
+				let bottom = (horizon + height * camera.get_height()).min(viewport.bottom());
+				if top >= bottom {
+					continue;
+				}
+				let x = viewport.x + i as f32;
+				let width = (column_step as f32).min(viewport.right() - x);
+
+				let Some(texture) = textures.get_wall(map[(tile.x, tile.y)]) else {
+					let color = if side {
+						Color::from_rgba(255, 0, 0, 255)
+					} else {
+						Color::from_rgba(190, 0, 0, 255)
+					};
+					draw_rectangle(x, top, width, bottom - top, color);
+					continue;
+				};
+
+				// only the visible part of the wall is taken from the texture
+				let rect = textures.get_atlas_rect(texture);
+				let offset = camera.wall_offset(map, direction, distance, side);
+				let texture_x = (offset * rect.width as f32).min(rect.width as f32 - 1.0).floor();
+				let texels_per_pixel = rect.height as f32 / height;
+				let source = Rect::new(
+					rect.x as f32 + texture_x,
+					rect.y as f32 + (top - wall_top) * texels_per_pixel,
+					1.0,
+					(bottom - top) * texels_per_pixel,
+				);
+				let tint = if side { WHITE } else { Color::new(0.75, 0.75, 0.75, 1.0) };
+				draw_texture_ex(
+					&atlas.0,
+					x,
+					top,
+					tint,
+					DrawTextureParams {
+						dest_size: Some(vec2(width, bottom - top)),
+						source: Some(source),
+						..Default::default()
+					},
+				);
+			}
+		}
+	}
+}
+
+/// Texture atlas uploaded on the GPU
+pub struct AtlasTexture(Texture2D);
+
+impl AtlasTexture {
+	pub fn new(textures: &TextureManager) -> Self {
+		let atlas = textures.get_atlas();
+		let texture = Texture2D::from_rgba8(
+			atlas.get_width() as u16,
+			atlas.get_height() as u16,
+			atlas.get_pixels().as_flattened(),
+		);
+		texture.set_filter(FilterMode::Nearest);
+		Self(texture)
+	}
+}
+
+/// Texture used to display a [Framebuffer] rendered on the CPU
+#[derive(Default)]
+pub struct ScreenTexture {
+	texture: Option<Texture2D>,
+}
+
+impl ScreenTexture {
+	/// Uploads the framebuffer and draws it stretched over the viewport,
+	/// through the post-processing shader if one is given
+	pub fn draw(&mut self, framebuffer: &Framebuffer, viewport: Rect, shader: Option<&PostShader>) {
+		let width = framebuffer.get_width() as u16;
+		let height = framebuffer.get_height() as u16;
+		let texture = match &self.texture {
+			Some(texture) if texture.width() as u16 == width && texture.height() as u16 == height => {
+				texture.update_from_bytes(width as u32, height as u32, framebuffer.as_bytes());
+				texture
+			}
+			_ => {
+				let texture = Texture2D::from_rgba8(width, height, framebuffer.as_bytes());
+				texture.set_filter(FilterMode::Nearest);
+				self.texture.insert(texture)
+			}
+		};
+		if let Some(shader) = shader {
+			shader.material.set_uniform("Resolution", vec2(width as f32, height as f32));
+			gl_use_material(&shader.material);
+		}
+		draw_texture_ex(
+			texture,
+			viewport.x,
+			viewport.y,
+			WHITE,
+			DrawTextureParams {
+				dest_size: Some(vec2(viewport.w, viewport.h)),
+				..Default::default()
+			},
+		);
+		if shader.is_some() {
+			gl_use_default_material();
+		}
+	}
+}
+
+const POST_VERTEX_SHADER: &str = "#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+
+varying lowp vec2 uv;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+	gl_Position = Projection * Model * vec4(position, 1);
+	uv = texcoord;
+}
+";
+
+/// Same effects as the CPU post-processing, see [crate::postfx]
+const POST_FRAGMENT_SHADER: &str = "#version 100
+precision mediump float;
+
+varying lowp vec2 uv;
+
+uniform sampler2D Texture;
+uniform vec2 Resolution;
+uniform float DitherLevels;
+uniform float Scanlines;
+uniform float Curvature;
+uniform float Vignette;
+uniform float VignetteRadius;
+uniform vec3 Grade;
+uniform vec3 Tint;
+uniform vec4 Flash;
+uniform vec2 Offset;
+
+float bayer2(vec2 p) {
+	return mod(2.0 * p.x + 3.0 * p.y, 4.0);
+}
+
+// threshold of the 4x4 Bayer matrix, between 0 and 1
+float bayer(vec2 pixel) {
+	vec2 p = mod(floor(pixel), 4.0);
+	return (4.0 * bayer2(mod(p, 2.0)) + bayer2(floor(p / 2.0)) + 0.5) / 16.0;
+}
+
+void main() {
+	vec2 pos = uv * 2.0 - 1.0;
+	pos.x *= 1.0 + pos.y * pos.y * Curvature;
+	pos.y *= 1.0 + pos.x * pos.x * Curvature;
+	if (abs(pos.x) > 1.0 || abs(pos.y) > 1.0) {
+		gl_FragColor = vec4(0.0, 0.0, 0.0, 1.0);
+		return;
+	}
+	vec2 coord = clamp((pos + 1.0) / 2.0 - Offset * vec2(Resolution.y / Resolution.x, 1.0), 0.0, 1.0);
+	vec3 color = texture2D(Texture, coord).rgb;
+
+	float luma = dot(color, vec3(0.299, 0.587, 0.114));
+	color = luma + (color - luma) * Grade.z;
+	color = ((color - 0.5) * Grade.y + 0.5) * Grade.x * Tint;
+	color = mix(color, Flash.rgb, Flash.a);
+
+	vec2 pixel = uv * Resolution;
+	if (mod(floor(pixel.y), 2.0) == 1.0) {
+		color *= 1.0 - Scanlines;
+	}
+	float distance = length(uv * 2.0 - 1.0) / sqrt(2.0);
+	float radius = min(VignetteRadius, 0.999);
+	color *= 1.0 - Vignette * smoothstep(radius, 1.0, distance);
+
+	if (DitherLevels > 0.0) {
+		float step = DitherLevels - 1.0;
+		color = floor(color * step + bayer(pixel)) / step;
+	}
+	gl_FragColor = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
+";
+
+/// GPU version of the post-processing, applied while drawing a [ScreenTexture]
+pub struct PostShader {
+	material: Material,
+}
+
+impl PostShader {
+	pub fn new() -> Result<Self, String> {
+		let uniforms = [
+			("Resolution", UniformType::Float2),
+			("DitherLevels", UniformType::Float1),
+			("Scanlines", UniformType::Float1),
+			("Curvature", UniformType::Float1),
+			("Vignette", UniformType::Float1),
+			("VignetteRadius", UniformType::Float1),
+			("Grade", UniformType::Float3),
+			("Tint", UniformType::Float3),
+			("Flash", UniformType::Float4),
+			("Offset", UniformType::Float2),
+		];
+		let material = load_material(
+			ShaderSource::Glsl {
+				vertex: POST_VERTEX_SHADER,
+				fragment: POST_FRAGMENT_SHADER,
+			},
+			MaterialParams {
+				uniforms: uniforms.iter().map(|(name, kind)| UniformDesc::new(name, *kind)).collect(),
+				..Default::default()
+			},
+		)
+		.map_err(|e| format!("{:?}", e))?;
+		Ok(Self { material })
+	}
+
+	pub fn set_params(&self, params: &ShaderParams) {
+		let material = &self.material;
+		material.set_uniform("DitherLevels", params.dither_levels);
+		material.set_uniform("Scanlines", params.scanlines);
+		material.set_uniform("Curvature", params.curvature);
+		material.set_uniform("Vignette", params.vignette);
+		material.set_uniform("VignetteRadius", params.vignette_radius);
+		material.set_uniform("Grade", vec3(params.brightness, params.contrast, params.saturation));
+		material.set_uniform("Tint", vec3(params.tint[0], params.tint[1], params.tint[2]));
+		material.set_uniform(
+			"Flash",
+			vec4(params.flash[0], params.flash[1], params.flash[2], params.flash[3]),
+		);
+		material.set_uniform("Offset", vec2(params.offset[0], params.offset[1]));
+	}
+}
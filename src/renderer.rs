@@ -0,0 +1,589 @@
+use crate::camera::{Camera, RayCastResult};
+use crate::framebuffer::{scale, Framebuffer, Pixel};
+use crate::map::Map;
+use crate::palette::{Palette, PaletteMode};
+use crate::textures::{Texture, TextureId, TextureManager};
+use crate::vector::Vector;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// Colors used when there is no texture
+pub const FLAT_COLORS: [Pixel; 4] = [
+    [255, 0, 0, 255],
+    [190, 0, 0, 255],
+    [40, 40, 60, 255],
+    [90, 90, 90, 255],
+];
+/// How fast the floor and the ceiling fade with the distance
+const FLOOR_FOG: f32 = 0.15;
+/// Brightness of the textured walls facing east or west
+const SIDE_SHADE: f32 = 0.75;
+/// Number of columns cast by a worker at once
+const COLUMN_CHUNK: usize = 32;
+/// Sprites closer than this distance to the camera, in tiles, are not drawn
+const SPRITE_NEAR: f32 = 0.05;
+/// Sprite texels with a lower alpha are transparent
+const SPRITE_ALPHA: u8 = 128;
+
+/// A billboard drawn in the 3D view, always facing the camera
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    /// World position of the bottom center of the sprite
+    pub position: Vector<f32>,
+    /// Without a texture the sprite is a disc of the given color
+    pub texture: Option<TextureId>,
+    pub color: Pixel,
+    /// Width and height, in tiles
+    pub size: f32,
+    /// Height of the bottom of the sprite above the floor, in tiles
+    pub elevation: f32,
+}
+
+/// A sprite projected on the screen, in pixels
+struct ProjectedSprite<O> {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+    /// Distance along the camera direction, compared with the walls
+    depth: f32,
+    texture: Option<TextureId>,
+    /// Shaded color of the untextured sprites
+    color: O,
+}
+
+/// Index of the [FLAT_COLORS] used for each surface
+#[derive(Clone, Copy)]
+enum Flat {
+    Wall = 0,
+    WallSide = 1,
+    Ceiling = 2,
+    Floor = 3,
+}
+
+/// Wall slice to draw in a column, in pixels
+#[derive(Clone, Copy)]
+struct Column {
+    top: f32,
+    bottom: f32,
+    /// Distance to the wall along the camera direction, in tiles
+    distance: f32,
+    flat: Flat,
+    /// Wall texture and the texture column to draw
+    texture: Option<(TextureId, usize)>,
+    dark: bool,
+    /// Ray direction, used to find the floor and ceiling positions
+    direction: Vector<f32>,
+    /// Sky texture column
+    sky_x: usize,
+}
+
+impl Column {
+    const EMPTY: Column = Column {
+        top: 0.0,
+        bottom: 0.0,
+        distance: f32::INFINITY,
+        flat: Flat::Wall,
+        texture: None,
+        dark: false,
+        direction: Vector { x: 0.0, y: 0.0 },
+        sky_x: 0,
+    };
+}
+
+/// Projection values shared by every worker during a frame
+struct Frame<'a> {
+    width: f32,
+    horizon: f32,
+    focal_length: f32,
+    eye_height: f32,
+    /// Camera position, in tiles
+    position: Vector<f32>,
+    tile_size: f32,
+    textures: &'a TextureManager,
+    floor: Option<(TextureId, &'a Texture)>,
+    ceiling: Option<(TextureId, &'a Texture)>,
+    sky: Option<(TextureId, &'a Texture)>,
+}
+
+/// Turns colors and texels lit with a given brightness into output
+/// values, either RGBA colors or palette indices
+trait Shading: Sync {
+    type Output: Copy + Send + Sync;
+
+    fn flat(&self, flat: Flat, light: f32) -> Self::Output;
+    fn color(&self, color: Pixel) -> Self::Output;
+    fn texel(&self, texture: TextureId, x: usize, y: usize, light: f32) -> Self::Output;
+}
+
+struct TrueColor<'a>(&'a TextureManager);
+
+impl Shading for TrueColor<'_> {
+    type Output = Pixel;
+
+    fn flat(&self, flat: Flat, light: f32) -> Pixel {
+        scale(FLAT_COLORS[flat as usize], light)
+    }
+
+    fn color(&self, color: Pixel) -> Pixel {
+        color
+    }
+
+    fn texel(&self, texture: TextureId, x: usize, y: usize, light: f32) -> Pixel {
+        scale(self.0.get(texture).get_pixel(x, y), light)
+    }
+}
+
+/// Lighting goes through the colormap instead of multiplying the channels
+struct Indexed<'a> {
+    mode: &'a PaletteMode,
+    flat: [u8; 4],
+}
+
+impl Shading for Indexed<'_> {
+    type Output = u8;
+
+    fn flat(&self, flat: Flat, light: f32) -> u8 {
+        self.mode
+            .get_colormap()
+            .shade(self.flat[flat as usize], light)
+    }
+
+    fn color(&self, color: Pixel) -> u8 {
+        self.mode.get_palette().nearest(color)
+    }
+
+    fn texel(&self, texture: TextureId, x: usize, y: usize, light: f32) -> u8 {
+        let index = self.mode.get_texel(texture, x, y);
+        self.mode.get_colormap().shade(index, light)
+    }
+}
+
+/// Renders the 3D view into a [Framebuffer] on the CPU.
+/// Rays are cast by chunks of columns, then the framebuffer is filled
+/// scanline by scanline, both passes can be split across a thread pool.
+/// Each worker only writes its own slice of the output so the result is
+/// the same whatever the number of threads
+pub struct Renderer {
+    threads: usize,
+    pool: Option<ThreadPool>,
+    columns: Vec<Column>,
+    /// Output of the palette mode, before the palette lookup
+    indices: Vec<u8>,
+    sprites: Vec<Sprite>,
+}
+
+fn fog(distance: f32) -> f32 {
+    1.0 / (1.0 + distance * FLOOR_FOG)
+}
+
+fn cast_column(map: &Map, camera: &Camera, frame: &Frame, x: usize) -> Column {
+    let direction = camera.ray_direction(x as f32, frame.width);
+    let sky_x = frame.sky.map_or(0, |(_, sky)| {
+        let turn = (direction.y.atan2(direction.x) / std::f32::consts::TAU).rem_euclid(1.0);
+        (turn * sky.get_width() as f32) as usize
+    });
+    match camera.raycast(map, direction) {
+        RayCastResult::NoHit => Column {
+            direction,
+            sky_x,
+            ..Column::EMPTY
+        },
+        RayCastResult::Hit(distance, tile, side) => {
+            let height = frame.focal_length / distance;
+            let texture = frame.textures.get_wall(map[(tile.x, tile.y)]).map(|id| {
+                let width = frame.textures.get(id).get_width();
+                let offset = camera.wall_offset(map, direction, distance, side);
+                (id, ((offset * width as f32) as usize).min(width - 1))
+            });
+            Column {
+                top: frame.horizon - height * (1.0 - frame.eye_height),
+                bottom: frame.horizon + height * frame.eye_height,
+                distance,
+                flat: if side { Flat::Wall } else { Flat::WallSide },
+                texture,
+                dark: !side,
+                direction,
+                sky_x,
+            }
+        }
+    }
+}
+
+fn draw_wall<S: Shading>(center: f32, column: &Column, frame: &Frame, shading: &S) -> S::Output {
+    match column.texture {
+        None => shading.flat(column.flat, 1.0),
+        Some((id, x)) => {
+            let height = frame.textures.get(id).get_height();
+            let v = (center - column.top) / (column.bottom - column.top);
+            let y = ((v * height as f32) as usize).min(height - 1);
+            let light = if column.dark { SIDE_SHADE } else { 1.0 };
+            shading.texel(id, x, y, light)
+        }
+    }
+}
+
+fn draw_row<S: Shading>(
+    y: usize,
+    row: &mut [S::Output],
+    columns: &[Column],
+    sprites: &[ProjectedSprite<S::Output>],
+    frame: &Frame,
+    shading: &S,
+) {
+    let center = y as f32 + 0.5;
+    let is_ceiling = center < frame.horizon;
+    let distance = if is_ceiling {
+        (1.0 - frame.eye_height) * frame.focal_length / (frame.horizon - center)
+    } else {
+        frame.eye_height * frame.focal_length / (center - frame.horizon)
+    };
+    let light = fog(distance);
+    let (flat, texture) = if is_ceiling {
+        (shading.flat(Flat::Ceiling, light), frame.ceiling)
+    } else {
+        (shading.flat(Flat::Floor, light), frame.floor)
+    };
+    let sky = frame.sky.filter(|_| is_ceiling).map(|(id, sky)| {
+        let v = center / frame.horizon;
+        (id, (v * sky.get_height() as f32) as usize)
+    });
+
+    for (pixel, column) in row.iter_mut().zip(columns) {
+        *pixel = if center >= column.top && center < column.bottom {
+            draw_wall(center, column, frame, shading)
+        } else if let Some((sky, sky_y)) = sky {
+            shading.texel(sky, column.sky_x, sky_y, 1.0)
+        } else if let Some((id, texture)) = texture {
+            let pos = frame.position + column.direction * distance;
+            let x = pos.x.rem_euclid(1.0) * texture.get_width() as f32;
+            let y = pos.y.rem_euclid(1.0) * texture.get_height() as f32;
+            shading.texel(id, x as usize, y as usize, light)
+        } else {
+            flat
+        };
+    }
+    for sprite in sprites {
+        draw_sprite_row(center, row, columns, sprite, frame, shading);
+    }
+}
+
+/// Projects the sprite in the view, None if it is behind the camera
+fn project_sprite<S: Shading>(
+    sprite: &Sprite,
+    camera: &Camera,
+    frame: &Frame,
+    shading: &S,
+) -> Option<ProjectedSprite<S::Output>> {
+    // position relative to the camera, as `depth * direction + x * plane`
+    let (direction, plane) = (camera.get_direction(), camera.get_plane());
+    let tile_size = frame.tile_size;
+    let relative = sprite.position / tile_size - frame.position;
+    let determinant = direction.x * plane.y - direction.y * plane.x;
+    let depth = (relative.x * plane.y - relative.y * plane.x) / determinant;
+    let x = (direction.x * relative.y - direction.y * relative.x) / determinant;
+    if depth < SPRITE_NEAR {
+        return None;
+    }
+    let center = (1.0 + x / depth) * frame.width / 2.0;
+    let size = sprite.size * frame.focal_length / depth;
+    let bottom = frame.horizon + (frame.eye_height - sprite.elevation) * frame.focal_length / depth;
+    Some(ProjectedSprite {
+        left: center - size / 2.0,
+        right: center + size / 2.0,
+        top: bottom - size,
+        bottom,
+        depth,
+        texture: sprite.texture,
+        color: shading.color(sprite.color),
+    })
+}
+
+/// Draws the part of the sprite crossing the row, behind the walls closer than it
+fn draw_sprite_row<S: Shading>(
+    center: f32,
+    row: &mut [S::Output],
+    columns: &[Column],
+    sprite: &ProjectedSprite<S::Output>,
+    frame: &Frame,
+    shading: &S,
+) {
+    if center < sprite.top || center >= sprite.bottom {
+        return;
+    }
+    let v = (center - sprite.top) / (sprite.bottom - sprite.top);
+    let first = sprite.left.max(0.0) as usize;
+    let last = (sprite.right.ceil().max(0.0) as usize).min(row.len());
+    for x in first..last {
+        let u = (x as f32 + 0.5 - sprite.left) / (sprite.right - sprite.left);
+        if !(0.0..1.0).contains(&u) || sprite.depth >= columns[x].distance {
+            continue;
+        }
+        match sprite.texture {
+            Some(id) => {
+                let texture = frame.textures.get(id);
+                let tx = ((u * texture.get_width() as f32) as usize).min(texture.get_width() - 1);
+                let ty = ((v * texture.get_height() as f32) as usize).min(texture.get_height() - 1);
+                if texture.get_pixel(tx, ty)[3] >= SPRITE_ALPHA {
+                    row[x] = shading.texel(id, tx, ty, 1.0);
+                }
+            }
+            None => {
+                let (du, dv) = (u - 0.5, v - 0.5);
+                if du * du + dv * dv < 0.25 {
+                    row[x] = sprite.color;
+                }
+            }
+        }
+    }
+}
+
+/// Runs `task` on every chunk of `data`, on the pool if there is one.
+/// `task` receives the index of the chunk
+fn for_each_chunk<T: Send>(
+    pool: Option<&ThreadPool>,
+    data: &mut [T],
+    chunk_size: usize,
+    task: impl Fn(usize, &mut [T]) + Sync,
+) {
+    match pool {
+        None => {
+            for (index, chunk) in data.chunks_mut(chunk_size).enumerate() {
+                task(index, chunk);
+            }
+        }
+        Some(pool) => pool.install(|| {
+            data.par_chunks_mut(chunk_size)
+                .enumerate()
+                .for_each(|(index, chunk)| task(index, chunk));
+        }),
+    }
+}
+
+impl Renderer {
+    /// Creates a renderer using the given number of threads,
+    /// 0 uses one thread per available core
+    pub fn new(threads: usize) -> Self {
+        let mut renderer = Self {
+            threads: 1,
+            pool: None,
+            columns: Vec::new(),
+            indices: Vec::new(),
+            sprites: Vec::new(),
+        };
+        renderer.set_threads(threads);
+        renderer
+    }
+
+    pub fn get_threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Changes the number of worker threads,
+    /// 0 uses one thread per available core
+    pub fn set_threads(&mut self, threads: usize) {
+        let threads = if threads == 0 {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            threads
+        };
+
+        self.pool = if threads > 1 {
+            ThreadPoolBuilder::new().num_threads(threads).build().ok()
+        } else {
+            None
+        };
+        self.threads = if self.pool.is_some() { threads } else { 1 };
+    }
+
+    /// Sprites drawn by the next renders, until they are replaced.
+    /// The line renderer of the `render` module doesn't draw sprites
+    pub fn set_sprites(&mut self, sprites: &[Sprite]) {
+        self.sprites.clear();
+        self.sprites.extend_from_slice(sprites);
+    }
+
+    /// Renders the view of the given camera, the whole framebuffer is overwritten
+    pub fn render(
+        &mut self,
+        map: &Map,
+        camera: &Camera,
+        textures: &TextureManager,
+        framebuffer: &mut Framebuffer,
+    ) {
+        let width = framebuffer.get_width();
+        let shading = TrueColor(textures);
+        self.render_with(
+            map,
+            camera,
+            textures,
+            &shading,
+            width,
+            framebuffer.get_pixels_mut(),
+        );
+    }
+
+    /// Renders the view of the given camera in 8-bit palette mode: textures
+    /// and lighting work on palette indices, the final colors are taken from
+    /// `palette` (the palette of the mode, possibly flashed)
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_indexed(
+        &mut self,
+        map: &Map,
+        camera: &Camera,
+        textures: &TextureManager,
+        mode: &PaletteMode,
+        palette: &Palette,
+        framebuffer: &mut Framebuffer,
+    ) {
+        let width = framebuffer.get_width();
+        let shading = Indexed {
+            mode,
+            flat: FLAT_COLORS.map(|color| mode.get_palette().nearest(color)),
+        };
+        let mut indices = std::mem::take(&mut self.indices);
+        indices.resize(width * framebuffer.get_height(), 0);
+        self.render_with(map, camera, textures, &shading, width, &mut indices);
+
+        if width > 0 {
+            let indices = &indices;
+            for_each_chunk(
+                self.pool.as_ref(),
+                framebuffer.get_pixels_mut(),
+                width,
+                |y, row| {
+                    let start = y * width;
+                    for (pixel, index) in row.iter_mut().zip(&indices[start..start + width]) {
+                        *pixel = palette.get(*index);
+                    }
+                },
+            );
+        }
+        self.indices = indices;
+    }
+
+    /// Casts the ray of every column then fills `output`, `width` values per row
+    fn render_with<S: Shading>(
+        &mut self,
+        map: &Map,
+        camera: &Camera,
+        textures: &TextureManager,
+        shading: &S,
+        width: usize,
+        output: &mut [S::Output],
+    ) {
+        if width == 0 || output.is_empty() {
+            return;
+        }
+        let height = output.len() / width;
+        let with_texture = |id: TextureId| (id, textures.get(id));
+
+        let frame = Frame {
+            width: width as f32,
+            horizon: camera.horizon(height as f32),
+            focal_length: camera.focal_length(width as f32),
+            eye_height: camera.get_height(),
+            position: camera.get_position() / map.get_tile_size(),
+            tile_size: map.get_tile_size(),
+            textures,
+            floor: textures.get_floor().map(with_texture),
+            ceiling: textures.get_ceiling().map(with_texture),
+            sky: textures.get_sky().map(with_texture),
+        };
+        self.columns.resize(width, Column::EMPTY);
+
+        let pool = self.pool.as_ref();
+        for_each_chunk(pool, &mut self.columns, COLUMN_CHUNK, |index, chunk| {
+            let start = index * COLUMN_CHUNK;
+            for (i, column) in chunk.iter_mut().enumerate() {
+                *column = cast_column(map, camera, &frame, start + i);
+            }
+        });
+        // drawn from the farthest to the closest
+        let mut sprites: Vec<_> = self
+            .sprites
+            .iter()
+            .filter_map(|sprite| project_sprite(sprite, camera, &frame, shading))
+            .collect();
+        sprites.sort_by(|a, b| b.depth.total_cmp(&a.depth));
+
+        let columns = &self.columns;
+        let sprites = &sprites;
+        for_each_chunk(pool, output, width, |y, row| {
+            draw_row(y, row, columns, sprites, &frame, shading)
+        });
+    }
+}
+
+#[cfg(test)]
+mod renderer_tests {
+    use super::*;
+
+    #[test]
+    fn multithreaded_output_is_identical() {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let textures = TextureManager::for_map(&map);
+        let camera = Camera::new(100.0, 100.0, 0.7);
+
+        let mut single = Framebuffer::new(97, 61);
+        Renderer::new(1).render(&map, &camera, &textures, &mut single);
+
+        let mut multi = Framebuffer::new(97, 61);
+        let mut renderer = Renderer::new(4);
+        renderer.render(&map, &camera, &textures, &mut multi);
+
+        assert!(single.get_pixels() == multi.get_pixels());
+    }
+
+    #[test]
+    fn indexed_output_uses_palette() {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let textures = TextureManager::for_map(&map);
+        let mode = PaletteMode::new(&textures, &FLAT_COLORS);
+        let camera = Camera::new(100.0, 100.0, 0.7);
+
+        let mut single = Framebuffer::new(97, 61);
+        let palette = mode.get_palette();
+        Renderer::new(1).render_indexed(&map, &camera, &textures, &mode, palette, &mut single);
+        let mut multi = Framebuffer::new(97, 61);
+        Renderer::new(4).render_indexed(&map, &camera, &textures, &mode, palette, &mut multi);
+
+        assert!(single.get_pixels() == multi.get_pixels());
+        assert!(single
+            .get_pixels()
+            .iter()
+            .all(|pixel| palette.get_colors().contains(pixel)));
+    }
+
+    #[test]
+    fn sprites_are_hidden_by_walls() {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1, 1,
+            1, 0, 0, 0, 0, 1,
+            1, 1, 1, 1, 1, 1,
+        ];
+        let map = Map::new("corridor".to_string(), 6, 3, 64.0, tiles);
+        let textures = TextureManager::new();
+        let camera = Camera::new(96.0, 96.0, 0.0);
+        let color = [0, 255, 0, 255];
+        let sprite = |x: f32| Sprite {
+            position: Vector::new(x, 96.0),
+            texture: None,
+            color,
+            size: 0.5,
+            elevation: 0.25,
+        };
+        let mut renderer = Renderer::new(1);
+        let mut framebuffer = Framebuffer::new(64, 40);
+
+        renderer.set_sprites(&[sprite(224.0)]);
+        renderer.render(&map, &camera, &textures, &mut framebuffer);
+        assert_eq!(framebuffer.get_pixel(32, 20), color);
+
+        // behind the end wall and behind the camera
+        renderer.set_sprites(&[sprite(400.0), sprite(32.0)]);
+        renderer.render(&map, &camera, &textures, &mut framebuffer);
+        assert!(!framebuffer.get_pixels().contains(&color));
+    }
+}
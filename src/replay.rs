@@ -0,0 +1,400 @@
+use crate::map::Map;
+use crate::player::Player;
+use crate::save::SaveGame;
+use std::io::{self, BufRead, Write};
+
+/// Version written in the replay files. Version 2 added the state of
+/// the level, the replays of version 1 can't be played anymore
+const REPLAY_VERSION: u32 = 2;
+
+/// An input action the player can do during a frame
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Forward,
+    Backward,
+    TurnLeft,
+    TurnRight,
+    ZoomIn,
+    ZoomOut,
+    LookUp,
+    LookDown,
+    Crouch,
+    Fire,
+    Use,
+}
+
+impl Action {
+    pub const ALL: [Action; 11] = [
+        Action::Forward,
+        Action::Backward,
+        Action::TurnLeft,
+        Action::TurnRight,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::LookUp,
+        Action::LookDown,
+        Action::Crouch,
+        Action::Fire,
+        Action::Use,
+    ];
+
+    /// Name used in the replay files
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Forward => "forward",
+            Action::Backward => "backward",
+            Action::TurnLeft => "turn_left",
+            Action::TurnRight => "turn_right",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+            Action::LookUp => "look_up",
+            Action::LookDown => "look_down",
+            Action::Crouch => "crouch",
+            Action::Fire => "fire",
+            Action::Use => "use",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// The actions held during a frame and the duration of the frame,
+/// everything needed to update the player
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct InputFrame {
+    pub delta: f32,
+    actions: u16,
+}
+
+impl InputFrame {
+    pub fn new(delta: f32) -> Self {
+        Self { delta, actions: 0 }
+    }
+
+    pub fn set(&mut self, action: Action, down: bool) {
+        if down {
+            self.actions |= action.bit();
+        } else {
+            self.actions &= !action.bit();
+        }
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.actions & action.bit() != 0
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Player state when a recording starts
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReplayStart {
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+    pub fov: f32,
+    pub pitch: f32,
+}
+
+/// The inputs of a session, played back from the same start state
+/// they reproduce the same player trajectory
+pub struct Replay {
+    map: String,
+    start: ReplayStart,
+    /// The level when the recording starts, written like a saved game
+    level: Vec<u8>,
+    frames: Vec<InputFrame>,
+}
+
+impl Replay {
+    /// Starts a replay from the current state of the player and of the level
+    pub fn new(player: &Player, level: &SaveGame) -> Self {
+        let camera = player.get_camera();
+        let mut data = Vec::new();
+        level
+            .write(&mut data)
+            .expect("writing to memory doesn't fail");
+        Self {
+            map: level.map.clone(),
+            start: ReplayStart {
+                x: player.get_pos().x,
+                y: player.get_pos().y,
+                angle: player.get_angle(),
+                fov: camera.get_fov(),
+                pitch: camera.get_pitch(),
+            },
+            level: data,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn get_map(&self) -> &str {
+        &self.map
+    }
+
+    #[allow(dead_code)]
+    pub fn get_start(&self) -> ReplayStart {
+        self.start
+    }
+
+    pub fn get_frames(&self) -> &[InputFrame] {
+        &self.frames
+    }
+
+    pub fn push(&mut self, frame: InputFrame) {
+        self.frames.push(frame);
+    }
+
+    /// State of the level when the recording started, restored before
+    /// playing the replay back
+    pub fn start_level(&self, map: &Map) -> io::Result<SaveGame> {
+        SaveGame::read(self.level.as_slice(), map)
+    }
+
+    /// A player in the start state of the replay, the stats and the
+    /// inventory are the ones of [Replay::start_level]
+    pub fn start_player(&self) -> Player {
+        let mut player = Player::new(self.start.x, self.start.y, self.start.angle);
+        player.get_camera_mut().set_fov(self.start.fov);
+        player.get_camera_mut().set_pitch(self.start.pitch);
+        player
+    }
+
+    /// Writes the replay: a header, the lines of the saved level, then one
+    /// line per frame with the frame duration followed by the held actions
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let start = &self.start;
+        writeln!(writer, "version = {}", REPLAY_VERSION)?;
+        writeln!(writer, "map = {}", self.map)?;
+        writeln!(writer, "start = {} {} {}", start.x, start.y, start.angle)?;
+        writeln!(writer, "fov = {}", start.fov)?;
+        writeln!(writer, "pitch = {}", start.pitch)?;
+        let level = String::from_utf8_lossy(&self.level);
+        writeln!(writer, "level = {}", level.lines().count())?;
+        writeln!(writer, "---")?;
+        for line in level.lines() {
+            writeln!(writer, "{}", line)?;
+        }
+        for frame in &self.frames {
+            // floats are written with the shortest exact representation
+            write!(writer, "{}", frame.delta)?;
+            for action in Action::ALL.into_iter().filter(|a| frame.is_down(*a)) {
+                write!(writer, " {}", action.name())?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a replay written by [Replay::write]
+    pub fn read(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader.lines().enumerate();
+        let mut header = std::collections::HashMap::new();
+        for (number, line) in lines.by_ref() {
+            let line = line?;
+            if line.trim() == "---" {
+                break;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                invalid_data(format!("line {}: expected key = value", number + 1))
+            })?;
+            header.insert(key.trim().to_string(), value.trim().to_string());
+        }
+
+        let field = |name: &str| {
+            header
+                .get(name)
+                .ok_or_else(|| invalid_data(format!("missing field {}", name)))
+        };
+        let float = |name: &str, value: &str| {
+            value
+                .parse::<f32>()
+                .map_err(|_| invalid_data(format!("invalid {}: {}", name, value)))
+        };
+        if field("version")? != &REPLAY_VERSION.to_string() {
+            return Err(invalid_data(format!(
+                "unsupported replay version {}",
+                field("version")?
+            )));
+        }
+        let start: Vec<&str> = field("start")?.split_whitespace().collect();
+        if start.len() != 3 {
+            return Err(invalid_data("start must be x y angle".to_string()));
+        }
+
+        let level_lines = field("level")?;
+        let level_lines: usize = level_lines
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid level: {}", level_lines)))?;
+        let mut level = Vec::new();
+        for _ in 0..level_lines {
+            let (_, line) = lines
+                .next()
+                .ok_or_else(|| invalid_data("missing level lines".to_string()))?;
+            level.extend_from_slice(line?.as_bytes());
+            level.push(b'\n');
+        }
+
+        let mut replay = Replay {
+            map: field("map")?.clone(),
+            start: ReplayStart {
+                x: float("start", start[0])?,
+                y: float("start", start[1])?,
+                angle: float("start", start[2])?,
+                fov: float("fov", field("fov")?)?,
+                pitch: float("pitch", field("pitch")?)?,
+            },
+            level,
+            frames: Vec::new(),
+        };
+        for (number, line) in lines {
+            let line = line?;
+            let mut words = line.split_whitespace();
+            let Some(delta) = words.next() else {
+                continue;
+            };
+            let delta = float(&format!("delta line {}", number + 1), delta)?;
+            // the player and the weapon bobbing divide by the frame duration
+            if !delta.is_finite() || delta <= 0.0 {
+                return Err(invalid_data(format!(
+                    "line {}: invalid delta {}",
+                    number + 1,
+                    delta
+                )));
+            }
+            let mut frame = InputFrame::new(delta);
+            for word in words {
+                let action = Action::from_name(word).ok_or_else(|| {
+                    invalid_data(format!("line {}: unknown action {}", number + 1, word))
+                })?;
+                frame.set(action, true);
+            }
+            replay.push(frame);
+        }
+        Ok(replay)
+    }
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use crate::automap::Automap;
+    use crate::entity::Entities;
+    use crate::trigger::Triggers;
+
+    fn room() -> Map {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1,
+            1, 0, 0, 0, 1,
+            1, 0, 1, 0, 1,
+            1, 0, 0, 0, 1,
+            1, 1, 1, 1, 1,
+        ];
+        Map::new("room".to_string(), 5, 5, 64.0, tiles)
+    }
+
+    fn level(map: &Map, player: &Player) -> SaveGame {
+        let (triggers, _) = Triggers::from_map(map);
+        let entities = Entities::new(map);
+        SaveGame::capture(
+            map,
+            player,
+            &entities,
+            &triggers,
+            None,
+            &Automap::new(map),
+            0.0,
+        )
+    }
+
+    /// Inputs running into walls with irregular frame times
+    fn session() -> Vec<InputFrame> {
+        (0..300)
+            .map(|i| {
+                let mut frame = InputFrame::new(0.01 + (i % 7) as f32 * 0.003);
+                frame.set(Action::Forward, i % 5 != 0);
+                frame.set(Action::TurnLeft, i % 40 < 10);
+                frame.set(Action::LookUp, i % 3 == 0);
+                frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_read() -> io::Result<()> {
+        let mut map = room();
+        let mut player = Player::new(96.0, 96.0, 0.25);
+        player.damage(30);
+        // a door opened before the recording
+        map[(2, 2)] = 0;
+        let mut replay = Replay::new(&player, &level(&map, &player));
+        for frame in session() {
+            replay.push(frame);
+        }
+
+        let mut data = Vec::new();
+        replay.write(&mut data)?;
+        let loaded = Replay::read(data.as_slice())?;
+        assert_eq!(loaded.get_map(), "room");
+        assert_eq!(loaded.get_start(), replay.get_start());
+        assert_eq!(loaded.get_frames(), replay.get_frames());
+        let start = loaded.start_level(&room())?;
+        assert_eq!(start.tiles[2 * 5 + 2], 0);
+        assert_eq!(start.health, player.get_health());
+        Ok(())
+    }
+
+    #[test]
+    fn playback_reproduces_trajectory() -> io::Result<()> {
+        let map = room();
+        let mut player = Player::new(96.0, 96.0, 0.25);
+        let mut replay = Replay::new(&player, &level(&map, &player));
+        for frame in session() {
+            replay.push(frame);
+            player.update(&frame, &map);
+        }
+
+        let mut data = Vec::new();
+        replay.write(&mut data)?;
+        let loaded = Replay::read(data.as_slice())?;
+        let mut replayed = loaded.start_player();
+        for frame in loaded.get_frames() {
+            replayed.update(frame, &map);
+        }
+        assert_eq!(replayed.get_pos().x.to_bits(), player.get_pos().x.to_bits());
+        assert_eq!(replayed.get_pos().y.to_bits(), player.get_pos().y.to_bits());
+        assert_eq!(replayed.get_angle().to_bits(), player.get_angle().to_bits());
+        assert_eq!(
+            replayed.get_camera().get_pitch(),
+            player.get_camera().get_pitch()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_replays() {
+        let header = "version = 2\nmap = room\nstart = 1 2 3\nfov = 1\npitch = 0\nlevel = 0\n---\n";
+        let replay = Replay::read(header.as_bytes()).unwrap();
+        // read, but there is no level to start from
+        assert!(replay.start_level(&room()).is_err());
+        assert!(Replay::read(header.replace("level = 0", "level = 3").as_bytes()).is_err());
+        assert!(Replay::read(header.replace("level = 0\n", "").as_bytes()).is_err());
+        assert!(Replay::read(format!("{}0.1 jump\n", header).as_bytes()).is_err());
+        assert!(Replay::read(format!("{}fast\n", header).as_bytes()).is_err());
+        for delta in ["0", "-0.1", "NaN", "inf"] {
+            let replay = format!("{}0.1\n{} forward\n", header, delta);
+            assert!(Replay::read(replay.as_bytes()).is_err(), "{}", delta);
+        }
+        assert!(Replay::read(header.replace("version = 2", "version = 1").as_bytes()).is_err());
+        assert!(Replay::read(header.replace("start = 1 2 3", "start = 1 2").as_bytes()).is_err());
+    }
+}
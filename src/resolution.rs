@@ -0,0 +1,125 @@
+/// Size of the image produced by the renderer,
+/// the image is then stretched over the window
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RenderResolution {
+    /// One pixel per window pixel
+    Window,
+    /// A fraction of the window size
+    Scale(f32),
+    /// A fixed size whatever the window size (e.g. 320x200)
+    Fixed(usize, usize),
+}
+
+impl RenderResolution {
+    /// Render size for the given window size, multiplied by `scale`
+    /// (used by the dynamic resolution), never smaller than 1x1
+    pub fn size(&self, window_width: f32, window_height: f32, scale: f32) -> (usize, usize) {
+        let (width, height) = match *self {
+            RenderResolution::Window => (window_width, window_height),
+            RenderResolution::Scale(factor) => (window_width * factor, window_height * factor),
+            RenderResolution::Fixed(width, height) => (width as f32, height as f32),
+        };
+        (
+            ((width * scale) as usize).max(1),
+            ((height * scale) as usize).max(1),
+        )
+    }
+}
+
+/// Lowest scale the dynamic resolution goes to
+const MIN_SCALE: f32 = 0.25;
+/// Weight of a new measure in the frame time average
+const SMOOTHING: f32 = 0.1;
+/// Frame time ratios (measured / target) outside of which the scale changes,
+/// the gap between them avoids oscillations
+const SLOW_RATIO: f32 = 1.1;
+const FAST_RATIO: f32 = 0.8;
+
+/// Adapts the render scale to keep the frame time close to a target
+pub struct DynamicResolution {
+    target_frame_time: f32,
+    average_frame_time: f32,
+    scale: f32,
+    enabled: bool,
+}
+
+impl DynamicResolution {
+    /// Creates a disabled dynamic resolution aiming at the given frame time (in seconds)
+    pub fn new(target_frame_time: f32) -> Self {
+        Self {
+            target_frame_time,
+            average_frame_time: target_frame_time,
+            scale: 1.0,
+            enabled: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enables or disables the adaptation, disabling it resets the scale
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.scale = 1.0;
+        self.average_frame_time = self.target_frame_time;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Feeds the duration of the last frame (as given by `get_frame_time`)
+    /// and returns the scale to use for the next frame
+    pub fn update(&mut self, frame_time: f32) -> f32 {
+        if !self.enabled {
+            return self.scale;
+        }
+
+        self.average_frame_time += (frame_time - self.average_frame_time) * SMOOTHING;
+        let ratio = self.average_frame_time / self.target_frame_time;
+        if ratio > SLOW_RATIO {
+            self.scale = (self.scale * 0.95).max(MIN_SCALE);
+        } else if ratio < FAST_RATIO {
+            self.scale = (self.scale * 1.02).min(1.0);
+        }
+        self.scale
+    }
+}
+
+#[cfg(test)]
+mod resolution_tests {
+    use super::*;
+
+    #[test]
+    fn fixed_resolution_ignores_window() {
+        let resolution = RenderResolution::Fixed(320, 200);
+        assert_eq!(resolution.size(800.0, 800.0, 1.0), (320, 200));
+        assert_eq!(resolution.size(1920.0, 1080.0, 0.5), (160, 100));
+    }
+
+    #[test]
+    fn scaled_resolution() {
+        let resolution = RenderResolution::Scale(0.5);
+        assert_eq!(resolution.size(800.0, 600.0, 1.0), (400, 300));
+        assert_eq!(RenderResolution::Window.size(0.0, 0.0, 1.0), (1, 1));
+    }
+
+    #[test]
+    fn dynamic_resolution_adapts() {
+        let mut dynamic = DynamicResolution::new(1.0 / 60.0);
+        assert_eq!(dynamic.update(1.0), 1.0);
+
+        dynamic.set_enabled(true);
+        for _ in 0..200 {
+            dynamic.update(1.0 / 20.0);
+        }
+        assert_eq!(dynamic.get_scale(), MIN_SCALE);
+
+        for _ in 0..200 {
+            dynamic.update(1.0 / 200.0);
+        }
+        assert_eq!(dynamic.get_scale(), 1.0);
+    }
+}
@@ -0,0 +1,568 @@
+use crate::automap::Automap;
+use crate::enemy::create_entity;
+use crate::entity::{Entities, EntityId};
+use crate::inventory::Inventory;
+use crate::map::{Map, Placement};
+use crate::pathfinding::NavGrid;
+use crate::player::Player;
+use crate::script::{Script, Value};
+use crate::textures::TextureManager;
+use crate::trigger::{set_tile, Triggers};
+use crate::vector::Vector;
+use crate::weapon::find_weapon;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+/// Version written in the save files. The saves of the older versions are
+/// still read: the fields added by a new version must be optional.
+/// Version 2 added the `time` field, version 3 the state of the entities
+pub const SAVE_VERSION: u32 = 3;
+
+/// Name of the map usable as a file name, for the saves and the captures: the
+/// characters other than letters, digits, `-` and `_` are replaced by `_`
+pub fn save_file_stem(map: &Map) -> String {
+    let stem: String = map
+        .get_name()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if stem.is_empty() {
+        "unnamed".to_string()
+    } else {
+        stem
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid {}: {}", name, value)))
+}
+
+/// An entity of a saved game, created again from its placement
+#[derive(Clone, PartialEq, Debug)]
+pub struct SavedEntity {
+    pub id: EntityId,
+    pub pos: Vector<f32>,
+    /// In radians
+    pub angle: f32,
+    pub health: Option<i32>,
+    /// State of the behaviour, see [crate::entity::Think::save_state]
+    pub think: Option<String>,
+    pub placement: Placement,
+}
+
+/// State of a level in progress: the tiles changed since the start, the
+/// player, the entities, the triggers, the variables of the level script
+/// and the explored tiles. The enemies keep what they were doing, the
+/// projectiles and the effects are not saved
+pub struct SaveGame {
+    /// Name of the map, the save is only loaded on the same map
+    pub map: String,
+    /// Time played on the level, in seconds
+    pub time: f32,
+    /// Width of the map, in tiles
+    pub width: usize,
+    /// Tiles of the map, row by row
+    pub tiles: Vec<u8>,
+    pub pos: Vector<f32>,
+    /// In radians
+    pub angle: f32,
+    pub health: i32,
+    pub armor: i32,
+    pub inventory: Inventory,
+    /// Sorted by id
+    pub entities: Vec<SavedEntity>,
+    pub next_entity: EntityId,
+    /// Name, fired and inside state of each trigger
+    pub triggers: Vec<(String, bool, bool)>,
+    /// Global variables of the level script, sorted by name
+    pub globals: Vec<(String, Value)>,
+    pub automap: Automap,
+}
+
+impl SaveGame {
+    pub fn capture(
+        map: &Map,
+        player: &Player,
+        entities: &Entities,
+        triggers: &Triggers,
+        script: Option<&Script>,
+        automap: &Automap,
+        time: f32,
+    ) -> Self {
+        let tiles = (0..map.get_height())
+            .flat_map(|y| (0..map.get_width()).map(move |x| (x, y)))
+            .map(|tile| map[tile])
+            .collect();
+        let saved = entities
+            .iter()
+            .filter(|entity| !entity.is_removed())
+            .filter_map(|entity| {
+                Some(SavedEntity {
+                    id: entity.get_id(),
+                    pos: entity.pos,
+                    angle: entity.angle,
+                    health: entity.health,
+                    think: entity.get_think_state(),
+                    placement: entity.placement.clone()?,
+                })
+            })
+            .collect();
+        let globals = script.map_or(Vec::new(), |script| {
+            script
+                .get_globals()
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect()
+        });
+        Self {
+            map: map.get_name().to_string(),
+            time,
+            width: map.get_width(),
+            tiles,
+            pos: player.get_pos(),
+            angle: player.get_angle(),
+            health: player.get_health(),
+            armor: player.get_armor(),
+            inventory: player.get_inventory().clone(),
+            entities: saved,
+            next_entity: entities.get_next_id(),
+            triggers: triggers
+                .iter()
+                .map(|t| (t.get_name().to_string(), t.has_fired(), t.is_inside()))
+                .collect(),
+            globals,
+            automap: automap.clone(),
+        }
+    }
+
+    /// Puts back the tiles and returns the entities of the saved level,
+    /// nothing is changed if an entity can't be created
+    pub fn restore_level(
+        &self,
+        map: &mut Map,
+        nav: &mut NavGrid,
+        textures: &TextureManager,
+    ) -> Result<Entities, String> {
+        let mut entities = Entities::new(map);
+        for saved in &self.entities {
+            let mut entity = create_entity(map, textures, &saved.placement)?;
+            entity.pos = saved.pos;
+            entity.angle = saved.angle;
+            entity.health = saved.health;
+            if let Some(state) = &saved.think {
+                entity.set_think_state(state)?;
+            }
+            entities.set_next_id(saved.id);
+            entities.spawn(entity);
+        }
+        entities.set_next_id(self.next_entity);
+        for (index, tile) in self.tiles.iter().enumerate() {
+            set_tile(map, nav, (index % self.width, index / self.width), *tile);
+        }
+        Ok(entities)
+    }
+
+    /// Puts back the position, the angle and the stats of the player,
+    /// the view settings are kept
+    pub fn restore_player(&self, player: &mut Player) {
+        player.teleport(self.pos, Some(self.angle));
+        player.set_health(self.health);
+        player.set_armor(self.armor);
+        *player.get_inventory_mut() = self.inventory.clone();
+    }
+
+    /// Puts back the triggers state and the script variables
+    pub fn restore_logic(&self, triggers: &mut Triggers, script: Option<&mut Script>) {
+        for (name, fired, inside) in &self.triggers {
+            triggers.set_state(name, *fired, *inside);
+        }
+        if let Some(script) = script {
+            for (name, value) in &self.globals {
+                script.set_global(name, value.clone());
+            }
+        }
+    }
+
+    /// Writes the save: a header, then the tiles, the explored tiles
+    /// and the entities, separated by `---`
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "version = {}", SAVE_VERSION)?;
+        writeln!(writer, "map = {}", self.map)?;
+        writeln!(writer, "time = {}", self.time)?;
+        writeln!(
+            writer,
+            "player = {} {} {}",
+            self.pos.x, self.pos.y, self.angle
+        )?;
+        writeln!(writer, "health = {}", self.health)?;
+        writeln!(writer, "armor = {}", self.armor)?;
+        let inventory = &self.inventory;
+        let weapons: Vec<String> = inventory
+            .get_weapons()
+            .iter()
+            .map(|weapon| format!("{} {}", weapon.get_def().name, weapon.get_ammo()))
+            .collect();
+        writeln!(writer, "weapons = {}", weapons.join(" "))?;
+        if let Some(weapon) = inventory.get_weapon() {
+            writeln!(writer, "weapon = {}", weapon.get_def().name)?;
+        }
+        let keys: Vec<&str> = inventory.get_keys().collect();
+        writeln!(writer, "keys = {}", keys.join(" "))?;
+        let items: Vec<String> = inventory
+            .get_items()
+            .map(|(name, count)| format!("{} {}", name, count))
+            .collect();
+        writeln!(writer, "items = {}", items.join(" "))?;
+        writeln!(writer, "next_entity = {}", self.next_entity)?;
+        for (name, fired, inside) in &self.triggers {
+            writeln!(writer, "trigger.{} = {} {}", name, fired, inside)?;
+        }
+        for (name, value) in &self.globals {
+            writeln!(writer, "global.{} = {}", name, value.to_literal())?;
+        }
+        writeln!(writer, "---")?;
+        for row in self.tiles.chunks(self.width) {
+            let row: Vec<String> = row.iter().map(|tile| tile.to_string()).collect();
+            writeln!(writer, "{}", row.join(" "))?;
+        }
+        writeln!(writer, "---")?;
+        self.automap.write(writer)?;
+        writeln!(writer, "---")?;
+        for entity in &self.entities {
+            let health = entity.health.map_or("-".to_string(), |h| h.to_string());
+            let think = entity.think.as_deref().unwrap_or("-");
+            writeln!(
+                writer,
+                "{} {} {} {} {} {} {}",
+                entity.id,
+                entity.pos.x,
+                entity.pos.y,
+                entity.angle,
+                health,
+                think,
+                entity.placement
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads a save written by [SaveGame::write] for the given map
+    pub fn read(reader: impl BufRead, map: &Map) -> io::Result<Self> {
+        let lines = reader.lines().collect::<io::Result<Vec<String>>>()?;
+        let mut sections = lines.split(|line| line.trim() == "---");
+        let mut section = || sections.next().unwrap_or_default();
+        let (header, tile_rows, automap, entity_lines) =
+            (section(), section(), section(), section());
+
+        let mut fields = HashMap::new();
+        for (number, line) in header.iter().enumerate() {
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                invalid_data(format!("line {}: expected key = value", number + 1))
+            })?;
+            fields.insert(key.trim(), value.trim());
+        }
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .ok_or_else(|| invalid_data(format!("missing field {}", name)))
+        };
+        let version: u32 = parse("version", field("version")?)?;
+        if !(1..=SAVE_VERSION).contains(&version) {
+            return Err(invalid_data(format!(
+                "unsupported save version {}",
+                version
+            )));
+        }
+        if field("map")? != map.get_name() {
+            return Err(invalid_data(format!(
+                "the save is for the map {}",
+                field("map")?
+            )));
+        }
+
+        let player: Vec<&str> = field("player")?.split_whitespace().collect();
+        let [x, y, angle] = player[..] else {
+            return Err(invalid_data("player must be x y angle".to_string()));
+        };
+        let mut inventory = Inventory::new();
+        let weapons: Vec<&str> = field("weapons")?.split_whitespace().collect();
+        for pair in weapons.chunks(2) {
+            let [name, ammo] = pair else {
+                return Err(invalid_data("weapons must be name ammo pairs".to_string()));
+            };
+            let def = find_weapon(name)
+                .ok_or_else(|| invalid_data(format!("unknown weapon {}", name)))?;
+            inventory.add_weapon(def, parse("ammo", ammo)?);
+        }
+        if let Ok(weapon) = field("weapon") {
+            inventory.select_weapon(weapon);
+        }
+        for key in field("keys")?.split_whitespace() {
+            inventory.add_key(key);
+        }
+        let items: Vec<&str> = field("items")?.split_whitespace().collect();
+        for pair in items.chunks(2) {
+            let [name, count] = pair else {
+                return Err(invalid_data("items must be name count pairs".to_string()));
+            };
+            inventory.add_item(name, parse("count", count)?);
+        }
+
+        let mut triggers = Vec::new();
+        let mut globals = Vec::new();
+        for (key, value) in &fields {
+            if let Some(name) = key.strip_prefix("trigger.") {
+                let state = match value.split_once(' ') {
+                    Some((fired, inside)) => fired.parse().ok().zip(inside.parse().ok()),
+                    None => None,
+                };
+                let (fired, inside) =
+                    state.ok_or_else(|| invalid_data(format!("invalid trigger {}", name)))?;
+                triggers.push((name.to_string(), fired, inside));
+            } else if let Some(name) = key.strip_prefix("global.") {
+                let value = Value::parse_literal(value)
+                    .ok_or_else(|| invalid_data(format!("invalid global {}", name)))?;
+                globals.push((name.to_string(), value));
+            }
+        }
+        triggers.sort_by(|a, b| a.0.cmp(&b.0));
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let tiles = tile_rows
+            .iter()
+            .flat_map(|row| row.split_whitespace())
+            .map(|tile| {
+                tile.parse::<u8>()
+                    .map_err(|_| invalid_data(format!("invalid tile {}", tile)))
+            })
+            .collect::<io::Result<Vec<u8>>>()?;
+        if tiles.len() != map.get_width() * map.get_height() {
+            return Err(invalid_data("the tiles don't match the map".to_string()));
+        }
+        let automap = Automap::read(automap.join("\n").as_bytes(), map)?;
+
+        let next_entity: EntityId = parse("next_entity", field("next_entity")?)?;
+        let mut entities: Vec<SavedEntity> = Vec::new();
+        for line in entity_lines.iter().filter(|line| !line.trim().is_empty()) {
+            let error = |message: String| invalid_data(format!("entity \"{}\": {}", line, message));
+            // the state of the behaviour came with the version 3
+            let with_state = version >= 3;
+            let mut values: Vec<&str> = line.splitn(6 + with_state as usize, ' ').collect();
+            let think = match values.len() {
+                7 => Some(values.remove(5)).filter(|think| *think != "-"),
+                _ => None,
+            };
+            let [id, x, y, angle, health, placement] = values[..] else {
+                return Err(error(
+                    "expected id x y angle health state placement".to_string(),
+                ));
+            };
+            let id: EntityId = parse("id", id)?;
+            if entities.last().is_some_and(|last| last.id >= id) || id >= next_entity {
+                return Err(error("the ids must increase".to_string()));
+            }
+            let health = match health {
+                "-" => None,
+                health => Some(parse("health", health)?),
+            };
+            entities.push(SavedEntity {
+                id,
+                pos: Vector::new(parse("x", x)?, parse("y", y)?),
+                angle: parse("angle", angle)?,
+                health,
+                think: think.map(str::to_string),
+                placement: Placement::parse(placement, map).map_err(error)?,
+            });
+        }
+
+        let time = match field("time") {
+            Ok(time) => parse("time", time)?,
+            Err(_) => 0.0,
+        };
+
+        Ok(Self {
+            map: map.get_name().to_string(),
+            time,
+            width: map.get_width(),
+            tiles,
+            pos: Vector::new(parse("x", x)?, parse("y", y)?),
+            angle: parse("angle", angle)?,
+            health: parse("health", field("health")?)?,
+            armor: parse("armor", field("armor")?)?,
+            inventory,
+            entities,
+            next_entity,
+            triggers,
+            globals,
+            automap,
+        })
+    }
+}
+
+#[cfg(test)]
+mod save_tests {
+    use super::*;
+    use crate::enemy::spawn_map_entities;
+    use crate::pathfinding::Connectivity;
+
+    fn test_map() -> (Map, NavGrid, Entities) {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let nav = NavGrid::new(&map, Connectivity::Eight);
+        let mut entities = Entities::new(&map);
+        spawn_map_entities(&map, &TextureManager::new(), &mut entities);
+        (map, nav, entities)
+    }
+
+    #[test]
+    fn write_read_restore() -> io::Result<()> {
+        let (mut map, mut nav, mut entities) = test_map();
+        let textures = TextureManager::new();
+        set_tile(&mut map, &mut nav, (3, 1), 0);
+        let guard = entities.iter().next().unwrap().get_id();
+        entities.get_mut(guard).unwrap().health = Some(5);
+        // the medkit was picked up
+        let medkit = entities.iter().nth(3).unwrap().get_id();
+        entities.get_mut(medkit).unwrap().remove();
+        let mut player = Player::new(100.0, 150.0, 0.5);
+        player.damage(40);
+        player.collect(&crate::inventory::Pickup::Key("red".to_string()));
+        player.collect(&crate::inventory::Pickup::Weapon("launcher".to_string()));
+        player.get_inventory_mut().select_weapon("pistol");
+        let (mut triggers, _) = Triggers::from_map(&map);
+        triggers.set_state("welcome", true, false);
+        let mut script = Script::parse("test.script", "").unwrap();
+        script.set_global("count", Value::Number(-2.5));
+        script.set_global("far", Value::Number(f64::INFINITY));
+        script.set_global("name", Value::Str("a \"b\"".to_string()));
+        let mut automap = Automap::new(&map);
+        automap.reveal(4, 5);
+
+        let save = SaveGame::capture(
+            &map,
+            &player,
+            &entities,
+            &triggers,
+            Some(&script),
+            &automap,
+            42.5,
+        );
+        let mut data = Vec::new();
+        save.write(&mut data)?;
+        let (mut fresh_map, mut fresh_nav, _) = test_map();
+        let loaded = SaveGame::read(data.as_slice(), &fresh_map)?;
+        assert_eq!(loaded.entities, save.entities);
+        assert_eq!(loaded.globals, save.globals);
+        assert_eq!(loaded.time, 42.5);
+
+        let restored = loaded
+            .restore_level(&mut fresh_map, &mut fresh_nav, &textures)
+            .unwrap();
+        assert!(!fresh_map.is_wall(3, 1) && fresh_nav.is_walkable(3, 1));
+        assert_eq!(restored.len(), entities.len() - 1);
+        assert!(restored.get(medkit).is_none());
+        assert_eq!(restored.get(guard).unwrap().health, Some(5));
+        assert_eq!(
+            restored.get(guard).unwrap().get_think_state(),
+            entities.get(guard).unwrap().get_think_state()
+        );
+        assert_eq!(restored.get_next_id(), entities.get_next_id());
+
+        let mut fresh_player = Player::new(0.0, 0.0, 0.0);
+        loaded.restore_player(&mut fresh_player);
+        assert_eq!(fresh_player.get_pos(), player.get_pos());
+        assert_eq!(fresh_player.get_health(), player.get_health());
+        let inventory = fresh_player.get_inventory();
+        assert!(inventory.has_key("red"));
+        assert_eq!(inventory.get_weapons().len(), 2);
+        assert_eq!(inventory.get_weapon().unwrap().get_def().name, "pistol");
+
+        let (mut fresh_triggers, _) = Triggers::from_map(&fresh_map);
+        let mut fresh_script = Script::parse("test.script", "").unwrap();
+        loaded.restore_logic(&mut fresh_triggers, Some(&mut fresh_script));
+        let welcome = fresh_triggers.iter().find(|t| t.get_name() == "welcome");
+        assert!(welcome.unwrap().has_fired());
+        assert_eq!(
+            fresh_script.get_global("name"),
+            Some(&Value::Str("a \"b\"".to_string()))
+        );
+        assert!(loaded.automap.is_explored(4, 5) && !loaded.automap.is_explored(5, 5));
+        Ok(())
+    }
+
+    #[test]
+    fn file_stems() {
+        let stem = |name: &str| save_file_stem(&Map::new(name.to_string(), 1, 1, 64.0, vec![0]));
+        assert_eq!(stem("E1M1_start-2"), "E1M1_start-2");
+        assert_eq!(stem("../../etc/passwd"), "______etc_passwd");
+        assert_eq!(stem("Level 3: the end"), "Level_3__the_end");
+        assert_eq!(stem(""), "unnamed");
+    }
+
+    #[test]
+    fn invalid_saves() {
+        let (map, _, entities) = test_map();
+        let player = Player::new(100.0, 150.0, 0.5);
+        let (triggers, _) = Triggers::from_map(&map);
+        let automap = Automap::new(&map);
+        let save = SaveGame::capture(&map, &player, &entities, &triggers, None, &automap, 7.0);
+        let mut data = Vec::new();
+        save.write(&mut data).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        assert!(SaveGame::read(text.as_bytes(), &map).is_ok());
+        // the saves of the first version have no time and no entity states
+        let (header, entity_lines) = text.rsplit_once("---\n").unwrap();
+        let entity_lines: String = entity_lines
+            .lines()
+            .map(|line| {
+                let mut values: Vec<&str> = line.splitn(7, ' ').collect();
+                values.remove(5);
+                values.join(" ") + "\n"
+            })
+            .collect();
+        let header = header
+            .replacen("version = 3", "version = 1", 1)
+            .replacen("time = 7\n", "", 1);
+        let old = format!("{}---\n{}", header, entity_lines);
+        let old = SaveGame::read(old.as_bytes(), &map).unwrap();
+        assert_eq!(old.time, 0.0);
+        assert_eq!(old.entities.len(), save.entities.len());
+        assert!(old.entities.iter().all(|entity| entity.think.is_none()));
+
+        // read, but the enemy can't be created again
+        let broken = text.replacen(" patrol,", " fly,", 1);
+        let broken = SaveGame::read(broken.as_bytes(), &map).unwrap();
+        let (mut fresh_map, mut fresh_nav, _) = test_map();
+        let textures = TextureManager::new();
+        assert!(broken
+            .restore_level(&mut fresh_map, &mut fresh_nav, &textures)
+            .is_err());
+
+        for (from, to) in [
+            ("version = 3", "version = 4"),
+            ("time = 7", "time = soon"),
+            ("map = test", "map = other"),
+            ("health = 100", "health = full"),
+            ("weapons = pistol 30", "weapons = bfg 30"),
+            ("---\n1 1 1", "---\n1 1"),
+            ("guard 17 1", "dragon 17 1"),
+        ] {
+            assert!(text.contains(from), "{}", from);
+            let text = text.replacen(from, to, 1);
+            assert!(SaveGame::read(text.as_bytes(), &map).is_err(), "{}", to);
+        }
+    }
+}
@@ -0,0 +1,1054 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// Statements and expressions evaluated by a callback before it is stopped,
+/// so a script looping forever can't freeze the game
+const MAX_STEPS: u32 = 100_000;
+/// Nested function calls allowed
+const MAX_DEPTH: usize = 64;
+/// Nested blocks and expressions allowed, when parsing and when running,
+/// so a deeply nested script can't overflow the stack
+const MAX_NESTING: usize = 200;
+
+/// A value of the script language
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+}
+
+impl Value {
+    /// Only nil and false are false
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// The value written like in a script, read back by [Value::parse_literal]
+    pub fn to_literal(&self) -> String {
+        match self {
+            Value::Str(string) => {
+                let escaped = string
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                format!("\"{}\"", escaped)
+            }
+            value => value.to_string(),
+        }
+    }
+
+    /// Reads a number, string, `true`, `false` or `nil` literal, and the
+    /// `inf`, `-inf` and `NaN` numbers a script can compute but not write
+    pub fn parse_literal(text: &str) -> Option<Value> {
+        let tokens = tokenize(text).ok()?;
+        let tokens: Vec<&Token> = tokens.iter().map(|(token, _)| token).collect();
+        match tokens[..] {
+            [Token::Number(number)] => Some(Value::Number(*number)),
+            [Token::Symbol("-"), Token::Number(number)] => Some(Value::Number(-number)),
+            [Token::Symbol("-"), Token::Ident(name)] if name == "inf" => {
+                Some(Value::Number(f64::NEG_INFINITY))
+            }
+            [Token::Str(string)] => Some(Value::Str(string.clone())),
+            [Token::Ident(name)] => match name.as_str() {
+                "true" => Some(Value::Bool(true)),
+                "false" => Some(Value::Bool(false)),
+                "nil" => Some(Value::Nil),
+                "inf" => Some(Value::Number(f64::INFINITY)),
+                "NaN" => Some(Value::Number(f64::NAN)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Error of a script, with the place where it happened
+#[derive(Clone, PartialEq, Debug)]
+pub struct ScriptError {
+    pub file: String,
+    pub line: u32,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// Functions given by the game to the scripts
+pub trait ScriptHost {
+    /// Calls a host function, None if there is no function with this name.
+    /// The errors are reported at the line of the call
+    fn call(&mut self, name: &str, args: &[Value]) -> Option<Result<Value, String>>;
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    /// Operators and punctuation
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "{", "}", ",", ";", "=", "<", ">", "+", "-", "*",
+    "/", "%", "!",
+];
+
+const KEYWORDS: [&str; 9] = [
+    "let", "fn", "if", "else", "while", "return", "true", "false", "nil",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, u32)>, (u32, String)> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.peek().is_some_and(|(_, c)| *c != '\n') {
+                chars.next();
+            }
+        } else if c.is_ascii_digit() {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_ascii_digit() && c != '.' {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let number = source[start..end]
+                .parse()
+                .map_err(|_| (line, format!("invalid number \"{}\"", &source[start..end])))?;
+            tokens.push((Token::Number(number), line));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push((Token::Ident(source[start..end].to_string()), line));
+        } else if c == '"' {
+            chars.next();
+            let string_line = line;
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => string.push('\n'),
+                        Some((_, c @ ('"' | '\\'))) => string.push(c),
+                        _ => return Err((line, "invalid escape in string".to_string())),
+                    },
+                    Some((_, c)) => {
+                        if c == '\n' {
+                            line += 1;
+                        }
+                        string.push(c);
+                    }
+                    None => return Err((string_line, "unterminated string".to_string())),
+                }
+            }
+            tokens.push((Token::Str(string), string_line));
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| source[start..].starts_with(**symbol))
+                .ok_or_else(|| (line, format!("unexpected character '{}'", c)))?;
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((Token::Symbol(symbol), line));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Var(String, u32),
+    Unary(&'static str, Box<Expr>, u32),
+    Binary(&'static str, Box<Expr>, Box<Expr>, u32),
+    Call(String, Vec<Expr>, u32),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Let(String, Expr),
+    Assign(String, Expr, u32),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>, u32),
+    While(Expr, Vec<Stmt>, u32),
+    Return(Option<Expr>),
+}
+
+#[derive(Debug)]
+struct Function {
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, u32)>,
+    position: usize,
+    /// Blocks and expressions being parsed
+    depth: usize,
+}
+
+type ParseResult<T> = Result<T, (u32, String)>;
+type Functions = HashMap<String, Rc<Function>>;
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    /// Line of the next token, or of the last one at the end of the file
+    fn line(&self) -> u32 {
+        self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(i)) if i == keyword)
+    }
+
+    /// Goes one level deeper, the caller restores the depth
+    fn enter(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err((self.line(), "too deeply nested".to_string()));
+        }
+        Ok(())
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        self.enter()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> ParseResult<()> {
+        if self.is_symbol(symbol) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err((self.line(), format!("expected '{}'", symbol)))
+        }
+    }
+
+    fn expect_name(&mut self) -> ParseResult<String> {
+        let line = self.line();
+        match self.next() {
+            Some(Token::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            _ => Err((line, "expected a name".to_string())),
+        }
+    }
+
+    /// Statements and functions of the whole file
+    fn parse_file(&mut self) -> ParseResult<(Vec<Stmt>, Functions)> {
+        let mut statements = Vec::new();
+        let mut functions = HashMap::new();
+        while self.peek().is_some() {
+            if self.is_keyword("fn") {
+                self.position += 1;
+                let line = self.line();
+                let name = self.expect_name()?;
+                self.expect_symbol("(")?;
+                let mut params = Vec::new();
+                while !self.is_symbol(")") {
+                    params.push(self.expect_name()?);
+                    if !self.is_symbol(")") {
+                        self.expect_symbol(",")?;
+                    }
+                }
+                self.position += 1;
+                let body = self.parse_block()?;
+                if functions
+                    .insert(name.clone(), Rc::new(Function { params, body }))
+                    .is_some()
+                {
+                    return Err((line, format!("function {} defined twice", name)));
+                }
+            } else {
+                statements.push(self.parse_statement()?);
+            }
+        }
+        Ok((statements, functions))
+    }
+
+    fn parse_block(&mut self) -> ParseResult<Vec<Stmt>> {
+        self.expect_symbol("{")?;
+        self.nested(|parser| {
+            let mut statements = Vec::new();
+            while !parser.is_symbol("}") {
+                if parser.peek().is_none() {
+                    return Err((parser.line(), "expected '}'".to_string()));
+                }
+                statements.push(parser.parse_statement()?);
+            }
+            parser.position += 1;
+            Ok(statements)
+        })
+    }
+
+    fn parse_statement(&mut self) -> ParseResult<Stmt> {
+        while self.is_symbol(";") {
+            self.position += 1;
+        }
+        let line = self.line();
+        let statement = if self.is_keyword("let") {
+            self.position += 1;
+            let name = self.expect_name()?;
+            self.expect_symbol("=")?;
+            Stmt::Let(name, self.parse_expr()?)
+        } else if self.is_keyword("if") {
+            self.parse_if(line)?
+        } else if self.is_keyword("while") {
+            self.position += 1;
+            let condition = self.parse_expr()?;
+            Stmt::While(condition, self.parse_block()?, line)
+        } else if self.is_keyword("return") {
+            self.position += 1;
+            // the value has to be on the same line
+            let value = match self.tokens.get(self.position) {
+                Some((token, next_line))
+                    if *next_line == line
+                        && *token != Token::Symbol("}")
+                        && *token != Token::Symbol(";") =>
+                {
+                    Some(self.parse_expr()?)
+                }
+                _ => None,
+            };
+            Stmt::Return(value)
+        } else if self.is_keyword("fn") {
+            return Err((
+                line,
+                "functions can only be defined at the top level".to_string(),
+            ));
+        } else if matches!(
+            (self.peek(), self.tokens.get(self.position + 1)),
+            (Some(Token::Ident(_)), Some((Token::Symbol("="), _)))
+        ) {
+            let name = self.expect_name()?;
+            self.position += 1;
+            Stmt::Assign(name, self.parse_expr()?, line)
+        } else {
+            Stmt::Expr(self.parse_expr()?)
+        };
+        if self.is_symbol(";") {
+            self.position += 1;
+        }
+        Ok(statement)
+    }
+
+    fn parse_if(&mut self, line: u32) -> ParseResult<Stmt> {
+        self.position += 1;
+        let condition = self.parse_expr()?;
+        let then = self.parse_block()?;
+        let otherwise = if self.is_keyword("else") {
+            self.position += 1;
+            if self.is_keyword("if") {
+                let line = self.line();
+                vec![self.nested(|parser| parser.parse_if(line))?]
+            } else {
+                self.parse_block()?
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(Stmt::If(condition, then, otherwise, line))
+    }
+
+    fn parse_expr(&mut self) -> ParseResult<Expr> {
+        self.parse_binary(0)
+    }
+
+    /// Binary operators of the precedence `level` and above, the operators
+    /// of a same level are left associative
+    fn parse_binary(&mut self, level: usize) -> ParseResult<Expr> {
+        const LEVELS: [&[&str]; 6] = [
+            &["||"],
+            &["&&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        let mut left = self.parse_unary()?;
+        let depth = self.depth;
+        while let Some(Token::Symbol(symbol)) = self.peek() {
+            let operator = LEVELS
+                .iter()
+                .enumerate()
+                .skip(level)
+                .find_map(|(level, ops)| {
+                    ops.iter().find(|op| *op == symbol).map(|op| (level, *op))
+                });
+            let Some((op_level, op)) = operator else {
+                break;
+            };
+            // each operator nests the left side one level deeper
+            self.enter()?;
+            let line = self.line();
+            self.position += 1;
+            let right = self.parse_binary(op_level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right), line);
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Expr> {
+        self.nested(|parser| {
+            let line = parser.line();
+            for op in ["!", "-"] {
+                if parser.is_symbol(op) {
+                    parser.position += 1;
+                    return Ok(Expr::Unary(op, Box::new(parser.parse_unary()?), line));
+                }
+            }
+            parser.parse_primary()
+        })
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        let line = self.line();
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Literal(Value::Number(number))),
+            Some(Token::Str(string)) => Ok(Expr::Literal(Value::Str(string))),
+            Some(Token::Symbol("(")) => {
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "nil" => Ok(Expr::Literal(Value::Nil)),
+                _ if KEYWORDS.contains(&name.as_str()) => {
+                    Err((line, format!("unexpected '{}'", name)))
+                }
+                _ if self.is_symbol("(") => {
+                    self.position += 1;
+                    let mut args = Vec::new();
+                    while !self.is_symbol(")") {
+                        args.push(self.parse_expr()?);
+                        if !self.is_symbol(")") {
+                            self.expect_symbol(",")?;
+                        }
+                    }
+                    self.position += 1;
+                    Ok(Expr::Call(name, args, line))
+                }
+                _ => Ok(Expr::Var(name, line)),
+            },
+            Some(Token::Symbol(symbol)) => Err((line, format!("unexpected '{}'", symbol))),
+            None => Err((line, "unexpected end of file".to_string())),
+        }
+    }
+}
+
+/// How a statement ended
+enum Flow {
+    Next,
+    Return(Value),
+}
+
+/// State of a callback being run
+struct Run<'a> {
+    host: &'a mut dyn ScriptHost,
+    functions: &'a Functions,
+    globals: &'a mut HashMap<String, Value>,
+    /// Variables of the functions being called, the last one is the current function
+    frames: Vec<HashMap<String, Value>>,
+    steps: u32,
+    /// Blocks and expressions being run, across the function calls
+    depth: usize,
+}
+
+type RunResult<T> = Result<T, (u32, String)>;
+
+fn number(value: &Value, line: u32) -> RunResult<f64> {
+    match value {
+        Value::Number(number) => Ok(*number),
+        _ => Err((
+            line,
+            format!("expected a number, got {}", value.type_name()),
+        )),
+    }
+}
+
+/// Functions of the language itself, before the ones of the host
+fn builtin(name: &str, args: &[Value], line: u32) -> Option<RunResult<Value>> {
+    let math = |f: fn(f64) -> f64| match args {
+        [value] => number(value, line).map(|value| Value::Number(f(value))),
+        _ => Err((line, format!("{} takes 1 argument", name))),
+    };
+    let result = match name {
+        "floor" => math(f64::floor),
+        "abs" => math(f64::abs),
+        "min" | "max" => match args {
+            [a, b] => {
+                let (a, b) = (number(a, line), number(b, line));
+                a.and_then(|a| {
+                    let b = b?;
+                    Ok(Value::Number(if name == "min" {
+                        a.min(b)
+                    } else {
+                        a.max(b)
+                    }))
+                })
+            }
+            _ => Err((line, format!("{} takes 2 arguments", name))),
+        },
+        _ => return None,
+    };
+    Some(result)
+}
+
+impl Run<'_> {
+    fn step(&mut self, line: u32) -> RunResult<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err((
+                line,
+                "too many steps, is there an infinite loop?".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn nested<T>(
+        &mut self,
+        line: u32,
+        run: impl FnOnce(&mut Self) -> RunResult<T>,
+    ) -> RunResult<T> {
+        if self.depth >= MAX_NESTING {
+            return Err((line, "too deeply nested".to_string()));
+        }
+        self.depth += 1;
+        let result = run(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> RunResult<Flow> {
+        for statement in statements {
+            if let Flow::Return(value) = self.statement(statement)? {
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> RunResult<Flow> {
+        match statement {
+            Stmt::Let(name, expr) => {
+                let value = self.expr(expr)?;
+                match self.frames.last_mut() {
+                    Some(frame) => frame.insert(name.clone(), value),
+                    None => self.globals.insert(name.clone(), value),
+                };
+            }
+            Stmt::Assign(name, expr, line) => {
+                let value = self.expr(expr)?;
+                let variable = match self.frames.last_mut() {
+                    Some(frame) if frame.contains_key(name) => frame.get_mut(name),
+                    _ => self.globals.get_mut(name),
+                };
+                match variable {
+                    Some(variable) => *variable = value,
+                    None => return Err((*line, format!("undefined variable {}", name))),
+                }
+            }
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            }
+            Stmt::If(condition, then, otherwise, line) => {
+                let branch = if self.expr(condition)?.is_truthy() {
+                    then
+                } else {
+                    otherwise
+                };
+                return self.nested(*line, |run| run.block(branch));
+            }
+            Stmt::While(condition, body, line) => {
+                while self.expr(condition)?.is_truthy() {
+                    self.step(*line)?;
+                    if let Flow::Return(value) = self.nested(*line, |run| run.block(body))? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            Stmt::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => self.expr(expr)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> RunResult<Value> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Var(name, line) => self
+                .frames
+                .last()
+                .and_then(|frame| frame.get(name))
+                .or_else(|| self.globals.get(name))
+                .cloned()
+                .ok_or_else(|| (*line, format!("undefined variable {}", name))),
+            Expr::Unary(op, operand, line) => {
+                self.step(*line)?;
+                let value = self.nested(*line, |run| run.expr(operand))?;
+                match *op {
+                    "!" => Ok(Value::Bool(!value.is_truthy())),
+                    _ => Ok(Value::Number(-number(&value, *line)?)),
+                }
+            }
+            Expr::Binary(op, left, right, line) => {
+                self.step(*line)?;
+                self.nested(*line, |run| {
+                    let left = run.expr(left)?;
+                    // the logical operators only evaluate what they need
+                    match *op {
+                        "&&" if !left.is_truthy() => return Ok(left),
+                        "||" if left.is_truthy() => return Ok(left),
+                        "&&" | "||" => return run.expr(right),
+                        _ => {}
+                    }
+                    let right = run.expr(right)?;
+                    binary(op, left, right, *line)
+                })
+            }
+            Expr::Call(name, args, line) => {
+                self.step(*line)?;
+                self.nested(*line, |run| {
+                    let args = args
+                        .iter()
+                        .map(|arg| run.expr(arg))
+                        .collect::<RunResult<Vec<_>>>()?;
+                    run.call(name, &args, *line)
+                })
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Value], line: u32) -> RunResult<Value> {
+        if let Some(function) = self.functions.get(name).cloned() {
+            if args.len() != function.params.len() {
+                return Err((
+                    line,
+                    format!(
+                        "{} takes {} arguments, got {}",
+                        name,
+                        function.params.len(),
+                        args.len()
+                    ),
+                ));
+            }
+            if self.frames.len() >= MAX_DEPTH {
+                return Err((line, "too many nested calls".to_string()));
+            }
+            let frame = function.params.iter().cloned().zip(args.iter().cloned());
+            self.frames.push(frame.collect());
+            let result = self.block(&function.body);
+            self.frames.pop();
+            return match result? {
+                Flow::Return(value) => Ok(value),
+                Flow::Next => Ok(Value::Nil),
+            };
+        }
+        if let Some(result) = builtin(name, args, line) {
+            return result;
+        }
+        match self.host.call(name, args) {
+            Some(result) => result.map_err(|message| (line, format!("{}: {}", name, message))),
+            None => Err((line, format!("undefined function {}", name))),
+        }
+    }
+}
+
+fn binary(op: &str, left: Value, right: Value, line: u32) -> RunResult<Value> {
+    match (op, &left, &right) {
+        ("==", _, _) => Ok(Value::Bool(left == right)),
+        ("!=", _, _) => Ok(Value::Bool(left != right)),
+        ("+", Value::Str(_), _) | ("+", _, Value::Str(_)) => {
+            Ok(Value::Str(format!("{}{}", left, right)))
+        }
+        (_, Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (*a, *b);
+            Ok(match op {
+                "+" => Value::Number(a + b),
+                "-" => Value::Number(a - b),
+                "*" => Value::Number(a * b),
+                "/" | "%" if b == 0.0 => return Err((line, "division by zero".to_string())),
+                "/" => Value::Number(a / b),
+                "%" => Value::Number(a.rem_euclid(b)),
+                "<" => Value::Bool(a < b),
+                "<=" => Value::Bool(a <= b),
+                ">" => Value::Bool(a > b),
+                _ => Value::Bool(a >= b),
+            })
+        }
+        _ => Err((
+            line,
+            format!(
+                "can't apply {} to {} and {}",
+                op,
+                left.type_name(),
+                right.type_name()
+            ),
+        )),
+    }
+}
+
+/// A script file of the small level scripting language: variables, `if`,
+/// `while`, functions and calls to the functions given by the game, see the
+/// README. The scripts only reach the game through the [ScriptHost]
+pub struct Script {
+    file: String,
+    /// Top level statements, run by [Script::init]
+    statements: Vec<Stmt>,
+    functions: Functions,
+    globals: HashMap<String, Value>,
+    /// Callbacks stopped after an error, so it isn't reported on every tick
+    disabled: HashSet<String>,
+}
+
+impl Script {
+    /// Parses the source, `file` is only used in the errors
+    pub fn parse(file: &str, source: &str) -> Result<Self, ScriptError> {
+        let error = |(line, message)| ScriptError {
+            file: file.to_string(),
+            line,
+            message,
+        };
+        let tokens = tokenize(source).map_err(error)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let (statements, functions) = parser.parse_file().map_err(error)?;
+        Ok(Self {
+            file: file.to_string(),
+            statements,
+            functions,
+            globals: HashMap::new(),
+            disabled: HashSet::new(),
+        })
+    }
+
+    fn run<T>(
+        &mut self,
+        host: &mut dyn ScriptHost,
+        run: impl FnOnce(&mut Run) -> RunResult<T>,
+    ) -> Result<T, ScriptError> {
+        let mut state = Run {
+            host,
+            functions: &self.functions,
+            globals: &mut self.globals,
+            frames: Vec::new(),
+            steps: 0,
+            depth: 0,
+        };
+        run(&mut state).map_err(|(line, message)| ScriptError {
+            file: self.file.clone(),
+            line,
+            message,
+        })
+    }
+
+    /// Runs the top level statements, which define the global variables
+    pub fn init(&mut self, host: &mut dyn ScriptHost) -> Result<(), ScriptError> {
+        let statements = std::mem::take(&mut self.statements);
+        let result = self.run(host, |run| run.block(&statements).map(|_| ()));
+        self.statements = statements;
+        result
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Calls a function of the script, nil if it isn't defined. A function
+    /// failing is disabled, the next calls do nothing
+    pub fn call(
+        &mut self,
+        host: &mut dyn ScriptHost,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, ScriptError> {
+        if !self.has_function(name) || self.disabled.contains(name) {
+            return Ok(Value::Nil);
+        }
+        let result = self.run(host, |run| run.call(name, args, 0));
+        if result.is_err() {
+            self.disabled.insert(name.to_string());
+        }
+        result
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    /// Global variables, sorted by name
+    pub fn get_globals(&self) -> Vec<(&str, &Value)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        globals.sort_by_key(|(name, _)| *name);
+        globals
+    }
+}
+
+#[cfg(test)]
+mod script_tests {
+    use super::*;
+
+    /// Records the calls, `twice(x)` returns 2x
+    #[derive(Default)]
+    struct Host {
+        calls: Vec<(String, Vec<Value>)>,
+    }
+
+    impl ScriptHost for Host {
+        fn call(&mut self, name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+            match (name, args) {
+                ("twice", [Value::Number(x)]) => Some(Ok(Value::Number(x * 2.0))),
+                ("twice", _) => Some(Err("expected a number".to_string())),
+                ("log", _) => {
+                    self.calls.push((name.to_string(), args.to_vec()));
+                    Some(Ok(Value::Nil))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    fn script(source: &str) -> (Script, Host) {
+        let mut host = Host::default();
+        let mut script = Script::parse("test.script", source).unwrap();
+        script.init(&mut host).unwrap();
+        (script, host)
+    }
+
+    #[test]
+    fn functions_and_control_flow() {
+        let (mut script, mut host) = script(
+            r#"
+            let count = 0
+            # sum of the numbers up to n
+            fn sum(n) {
+                let total = 0
+                let i = 1
+                while i <= n { total = total + i; i = i + 1 }
+                return total
+            }
+            fn on_trigger(name) {
+                count = count + 1
+                if name == "door" && count < 3 {
+                    log("door " + count, twice(sum(3)))
+                } else if !(name == "door") {
+                    log(name)
+                } else {
+                    return -1
+                }
+            }
+            "#,
+        );
+        let door = [Value::Str("door".to_string())];
+        script.call(&mut host, "on_trigger", &door).unwrap();
+        script.call(&mut host, "on_trigger", &door).unwrap();
+        let result = script.call(&mut host, "on_trigger", &door).unwrap();
+        assert_eq!(result, Value::Number(-1.0));
+        script
+            .call(&mut host, "on_trigger", &[Value::Str("exit".to_string())])
+            .unwrap();
+        assert_eq!(
+            host.calls,
+            [
+                (
+                    "log".to_string(),
+                    vec![Value::Str("door 1".to_string()), Value::Number(12.0)]
+                ),
+                (
+                    "log".to_string(),
+                    vec![Value::Str("door 2".to_string()), Value::Number(12.0)]
+                ),
+                ("log".to_string(), vec![Value::Str("exit".to_string())]),
+            ]
+        );
+        assert_eq!(script.get_global("count"), Some(&Value::Number(4.0)));
+        // missing callbacks do nothing
+        assert_eq!(script.call(&mut host, "on_tick", &[]), Ok(Value::Nil));
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        for (source, line, message) in [
+            ("let a = 1\nlet b = \"open", 2, "unterminated string"),
+            ("fn f() {\n  let = 2\n}", 2, "expected a name"),
+            ("let a = (1 + 2", 1, "expected ')'"),
+            ("fn f() {}\nfn f() {}", 2, "function f defined twice"),
+        ] {
+            let error = Script::parse("level.script", source).err().unwrap();
+            assert_eq!((error.line, error.message.as_str()), (line, message));
+        }
+
+        let (mut script, mut host) = script(
+            "fn on_tick(dt) {\n  let x = dt\n  x = x + missing\n}\nfn loop() {\n  while true {}\n}\nfn bad() {\n  twice(\"a\")\n}",
+        );
+        let error = script
+            .call(&mut host, "on_tick", &[Value::Number(0.1)])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.script:3: undefined variable missing"
+        );
+        // disabled after the error
+        assert_eq!(
+            script.call(&mut host, "on_tick", &[Value::Number(0.1)]),
+            Ok(Value::Nil)
+        );
+        let error = script.call(&mut host, "loop", &[]).unwrap_err();
+        assert_eq!(error.line, 6);
+        let error = script.call(&mut host, "bad", &[]).unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (9, "twice: expected a number")
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let deep = format!("let a = {}1{}", "(".repeat(10000), ")".repeat(10000));
+        let error = Script::parse("test.script", &deep).err().unwrap();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (1, "too deeply nested")
+        );
+        for source in [
+            format!("let a = {}1", "-".repeat(10000)),
+            format!("let a = 1{}", " + 1".repeat(100000)),
+            format!("{}{}", "if true {\n".repeat(10000), "}\n".repeat(10000)),
+        ] {
+            assert!(Script::parse("test.script", &source).is_err());
+        }
+        let (shallow, _) = script(&format!("let a = {}1{}", "(".repeat(50), ")".repeat(50)));
+        assert_eq!(shallow.get_global("a"), Some(&Value::Number(1.0)));
+
+        // recursive calls nest too
+        let (mut script, mut host) =
+            script("fn f(n) {\n  if n > 0 {\n    return 1 + -f(n - 1)\n  }\n  return 0\n}");
+        assert!(script.call(&mut host, "f", &[Value::Number(40.0)]).is_ok());
+        let error = script
+            .call(&mut host, "f", &[Value::Number(60.0)])
+            .unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (3, "too deeply nested")
+        );
+    }
+
+    #[test]
+    fn literals() {
+        for value in [
+            Value::Nil,
+            Value::Bool(true),
+            Value::Number(-2.5),
+            Value::Number(f64::INFINITY),
+            Value::Number(f64::NEG_INFINITY),
+            Value::Str("a \"b\"\n".to_string()),
+        ] {
+            assert_eq!(Value::parse_literal(&value.to_literal()), Some(value));
+        }
+        // the overflows of a script are saved too
+        let (script, _) = script(&format!("let a = 1{} * 10\nlet b = a - a", "0".repeat(308)));
+        assert_eq!(script.get_global("a"), Some(&Value::Number(f64::INFINITY)));
+        let b = script.get_global("b").unwrap().to_literal();
+        assert!(matches!(Value::parse_literal(&b), Some(Value::Number(n)) if n.is_nan()));
+        assert_eq!(Value::parse_literal("infinity"), None);
+    }
+
+    #[test]
+    fn operators() {
+        let (script, _) = script(
+            "let a = 7 % 3 * 2 - -1\nlet b = 1 < 2 && 2 >= 2 || missing\nlet c = \"x\" + 1.5\nlet d = min(floor(2.7), abs(-5))\nlet e = nil == false",
+        );
+        assert_eq!(script.get_global("a"), Some(&Value::Number(3.0)));
+        assert_eq!(script.get_global("b"), Some(&Value::Bool(true)));
+        assert_eq!(
+            script.get_global("c"),
+            Some(&Value::Str("x1.5".to_string()))
+        );
+        assert_eq!(script.get_global("d"), Some(&Value::Number(2.0)));
+        assert_eq!(script.get_global("e"), Some(&Value::Bool(false)));
+
+        let mut host = Host::default();
+        let mut script = Script::parse("test.script", "let a = 1 / 0").unwrap();
+        assert!(script.init(&mut host).is_err());
+        let mut script = Script::parse("test.script", "let a = \"a\" - 1").unwrap();
+        assert!(script.init(&mut host).is_err());
+    }
+}
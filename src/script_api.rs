@@ -0,0 +1,425 @@
+use crate::enemy::create_entity;
+use crate::entity::{Entities, EntityId};
+use crate::inventory::Pickup;
+use crate::map::{Map, Placement};
+use crate::pathfinding::{NavGrid, Tile};
+use crate::player::Player;
+use crate::script::{Script, ScriptError, ScriptHost, Value};
+use crate::textures::TextureManager;
+use crate::trigger::{set_tile, EventQueue, MapEvent};
+use crate::vector::Vector;
+
+/// Reads the script of the map, None if the map has no `script` field
+pub fn load_script(map: &Map) -> Option<Result<Script, ScriptError>> {
+    let path = map.get_script()?;
+    let file = path.display().to_string();
+    Some(match std::fs::read_to_string(path) {
+        Ok(source) => Script::parse(&file, &source),
+        Err(e) => Err(ScriptError {
+            file,
+            line: 0,
+            message: e.to_string(),
+        }),
+    })
+}
+
+/// Arguments of a host function, with the conversions reporting the errors
+struct Args<'a>(&'a [Value]);
+
+impl Args<'_> {
+    fn count(&self, min: usize, max: usize) -> Result<(), String> {
+        if (min..=max).contains(&self.0.len()) {
+            Ok(())
+        } else if min == max {
+            Err(format!("takes {} arguments, got {}", min, self.0.len()))
+        } else {
+            Err(format!(
+                "takes {} to {} arguments, got {}",
+                min,
+                max,
+                self.0.len()
+            ))
+        }
+    }
+
+    fn number(&self, index: usize) -> Result<f64, String> {
+        match &self.0[index] {
+            Value::Number(number) => Ok(*number),
+            value => Err(format!(
+                "argument {} must be a number, not {}",
+                index + 1,
+                value
+            )),
+        }
+    }
+
+    fn string(&self, index: usize) -> Result<&str, String> {
+        match &self.0[index] {
+            Value::Str(string) => Ok(string),
+            value => Err(format!(
+                "argument {} must be a string, not {}",
+                index + 1,
+                value
+            )),
+        }
+    }
+
+    /// Integer at least 0
+    fn integer(&self, index: usize) -> Result<usize, String> {
+        let number = self.number(index)?;
+        if number < 0.0 || number.fract() != 0.0 {
+            return Err(format!("invalid argument {}: {}", index + 1, number));
+        }
+        Ok(number as usize)
+    }
+
+    /// Integer at least 0 which fits the type
+    fn integer_as<T: TryFrom<usize>>(&self, index: usize) -> Result<T, String> {
+        let integer = self.integer(index)?;
+        T::try_from(integer).map_err(|_| format!("invalid argument {}: {}", index + 1, integer))
+    }
+
+    /// Tile coordinates at `index` and `index + 1`, in the map
+    fn tile(&self, map: &Map, index: usize) -> Result<Tile, String> {
+        let (x, y) = (self.integer(index)?, self.integer(index + 1)?);
+        if x >= map.get_width() || y >= map.get_height() {
+            return Err(format!("tile {} {} outside of the map", x, y));
+        }
+        Ok((x, y))
+    }
+
+    fn id(&self, index: usize) -> Result<EntityId, String> {
+        self.integer_as(index)
+    }
+}
+
+/// The game as seen by the level script during a callback. Tiles are
+/// integer coordinates, positions are in tiles too but fractional:
+/// 2.5 is the center of the tile 2
+pub struct GameHost<'a> {
+    pub map: &'a mut Map,
+    pub nav: &'a mut NavGrid,
+    pub player: &'a mut Player,
+    pub entities: &'a mut Entities,
+    pub textures: &'a TextureManager,
+    /// Receives the messages, sounds, teleports and level end, so they are
+    /// handled like the ones of the triggers
+    pub events: &'a mut EventQueue,
+}
+
+impl<'a> GameHost<'a> {
+    pub fn new(
+        map: &'a mut Map,
+        nav: &'a mut NavGrid,
+        player: &'a mut Player,
+        entities: &'a mut Entities,
+        textures: &'a TextureManager,
+        events: &'a mut EventQueue,
+    ) -> Self {
+        Self {
+            map,
+            nav,
+            player,
+            entities,
+            textures,
+            events,
+        }
+    }
+
+    fn to_tiles(&self, pos: Vector<f32>) -> (Value, Value) {
+        let size = self.map.get_tile_size();
+        (
+            Value::Number((pos.x / size) as f64),
+            Value::Number((pos.y / size) as f64),
+        )
+    }
+
+    /// Calls a function of the game, Ok(None) if it doesn't exist
+    fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Option<Value>, String> {
+        let args = Args(args);
+        let value = match name {
+            "get_tile" => {
+                args.count(2, 2)?;
+                let tile = args.tile(self.map, 0)?;
+                Value::Number(self.map[tile] as f64)
+            }
+            "set_tile" => {
+                args.count(3, 3)?;
+                let tile = args.tile(self.map, 0)?;
+                let value = args.integer(2)?;
+                let value = u8::try_from(value).map_err(|_| format!("invalid tile {}", value))?;
+                set_tile(self.map, self.nav, tile, value);
+                Value::Nil
+            }
+            "player_x" | "player_y" => {
+                args.count(0, 0)?;
+                let (x, y) = self.to_tiles(self.player.get_pos());
+                if name == "player_x" {
+                    x
+                } else {
+                    y
+                }
+            }
+            "player_angle" => {
+                args.count(0, 0)?;
+                Value::Number(self.player.get_angle().to_degrees() as f64)
+            }
+            "teleport" => {
+                args.count(2, 3)?;
+                let (x, y) = args.tile(self.map, 0)?;
+                if self.map.is_wall(x, y) {
+                    return Err(format!("tile {} {} is a wall", x, y));
+                }
+                let angle = match args.0.len() {
+                    3 => Some((args.number(2)? as f32).to_radians()),
+                    _ => None,
+                };
+                self.events.push(MapEvent::Teleport { x, y, angle });
+                Value::Nil
+            }
+            "player_health" => {
+                args.count(0, 0)?;
+                Value::Number(self.player.get_health() as f64)
+            }
+            "damage_player" => {
+                args.count(1, 1)?;
+                self.player.damage(args.integer_as(0)?);
+                Value::Nil
+            }
+            "give" => {
+                args.count(1, 1)?;
+                let pickup = Pickup::parse(args.string(0)?)?;
+                Value::Bool(self.player.collect(&pickup))
+            }
+            "has_key" => {
+                args.count(1, 1)?;
+                let inventory = self.player.get_inventory();
+                Value::Bool(inventory.has_key(args.string(0)?))
+            }
+            "item_count" => {
+                args.count(1, 1)?;
+                let inventory = self.player.get_inventory();
+                Value::Number(inventory.get_item_count(args.string(0)?) as f64)
+            }
+            "take_item" => {
+                args.count(1, 2)?;
+                let count = match args.0.len() {
+                    2 => args.integer_as(1)?,
+                    _ => 1,
+                };
+                let inventory = self.player.get_inventory_mut();
+                Value::Bool(inventory.remove_item(args.string(0)?, count))
+            }
+            "spawn" => {
+                args.count(3, 4)?;
+                let kind = args.string(0)?;
+                let (x, y) = args.tile(self.map, 1)?;
+                let angle = match args.0.len() {
+                    4 => (args.number(3)? as f32).to_radians(),
+                    _ => 0.0,
+                };
+                let placement = Placement {
+                    kind: kind.to_string(),
+                    x,
+                    y,
+                    angle,
+                    waypoints: Vec::new(),
+                };
+                let entity = create_entity(self.map, self.textures, &placement)?;
+                Value::Number(self.entities.spawn(entity) as f64)
+            }
+            "entity_x" | "entity_y" => {
+                args.count(1, 1)?;
+                match self.entities.get(args.id(0)?) {
+                    Some(entity) if !entity.is_removed() => {
+                        let (x, y) = self.to_tiles(entity.pos);
+                        if name == "entity_x" {
+                            x
+                        } else {
+                            y
+                        }
+                    }
+                    _ => Value::Nil,
+                }
+            }
+            "entity_health" => {
+                args.count(1, 1)?;
+                let health = self.entities.get(args.id(0)?).and_then(|e| e.health);
+                health.map_or(Value::Nil, |health| Value::Number(health as f64))
+            }
+            "move_entity" => {
+                args.count(3, 3)?;
+                let size = self.map.get_tile_size();
+                let pos = Vector::new(args.number(1)? as f32, args.number(2)? as f32) * size;
+                if !self.map.in_map(&pos) {
+                    return Err("position outside of the map".to_string());
+                }
+                let entity = self.entities.get_mut(args.id(0)?);
+                let entity = entity.ok_or_else(|| format!("no entity {}", args.0[0]))?;
+                entity.pos = pos;
+                Value::Nil
+            }
+            "remove_entity" => {
+                args.count(1, 1)?;
+                if let Some(entity) = self.entities.get_mut(args.id(0)?) {
+                    entity.remove();
+                }
+                Value::Nil
+            }
+            "message" | "sound" => {
+                args.count(1, 1)?;
+                let text = args.0[0].to_string();
+                self.events.push(if name == "message" {
+                    MapEvent::Message(text)
+                } else {
+                    MapEvent::Sound(text)
+                });
+                Value::Nil
+            }
+            "end_level" => {
+                args.count(0, 0)?;
+                self.events.push(MapEvent::EndLevel);
+                Value::Nil
+            }
+            "print" => {
+                let values: Vec<String> = args.0.iter().map(Value::to_string).collect();
+                // like the script errors, stdout is left to the game
+                eprintln!("Script: {}", values.join(" "));
+                Value::Nil
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(value))
+    }
+}
+
+impl ScriptHost for GameHost<'_> {
+    fn call(&mut self, name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+        self.call_function(name, args).transpose()
+    }
+}
+
+#[cfg(test)]
+mod script_api_tests {
+    use super::*;
+    use crate::pathfinding::Connectivity;
+
+    struct Game {
+        map: Map,
+        nav: NavGrid,
+        player: Player,
+        entities: Entities,
+        textures: TextureManager,
+        events: EventQueue,
+    }
+
+    impl Game {
+        fn new(map: Map) -> Self {
+            Self {
+                nav: NavGrid::new(&map, Connectivity::Four),
+                entities: Entities::new(&map),
+                player: Player::new(96.0, 96.0, 0.0),
+                textures: TextureManager::new(),
+                events: EventQueue::new(),
+                map,
+            }
+        }
+
+        fn host(&mut self) -> GameHost<'_> {
+            GameHost::new(
+                &mut self.map,
+                &mut self.nav,
+                &mut self.player,
+                &mut self.entities,
+                &self.textures,
+                &mut self.events,
+            )
+        }
+
+        fn call(&mut self, script: &mut Script, name: &str) -> Result<Value, ScriptError> {
+            script.call(&mut self.host(), name, &[])
+        }
+    }
+
+    #[test]
+    fn changes_the_game() {
+        let text = "name = room\nsize = 5x4\nentity.lamp.color = 255,255,0\n---\n1 1 1 1 1\n1 0 0 2 1\n1 0 0 0 1\n1 1 1 1 1\n";
+        let mut game = Game::new(Map::parse(text.as_bytes(), 64.0).unwrap());
+        let mut script = Script::parse(
+            "test.script",
+            r#"
+            fn run() {
+                set_tile(3, 1, get_tile(3, 1) - 2)
+                let lamp = spawn("lamp", 2, 2)
+                move_entity(lamp, entity_x(lamp) + 0.5, 2.5)
+                give("key red")
+                damage_player(10)
+                if has_key("red") && player_x() == 1.5 {
+                    message("health " + player_health())
+                    teleport(2, 1, 90)
+                }
+                return lamp
+            }
+            fn outside() { set_tile(5, 0, 1) }
+            fn unknown() { spawn("dragon", 1, 1) }
+            fn overflow() { damage_player(4294967196) }
+            fn take_all() { take_item("coin", 4294967296) }
+            "#,
+        )
+        .unwrap();
+        let lamp = game.call(&mut script, "run").unwrap();
+        assert!(!game.map.is_wall(3, 1) && game.nav.is_walkable(3, 1));
+        let Value::Number(lamp) = lamp else {
+            panic!("{:?}", lamp)
+        };
+        let entity = game.entities.get(lamp as EntityId).unwrap();
+        assert_eq!(entity.pos, Vector::new(192.0, 160.0));
+        assert_eq!(game.player.get_health(), 90);
+        assert_eq!(
+            game.events.pop(),
+            Some(MapEvent::Message("health 90".to_string()))
+        );
+        assert_eq!(
+            game.events.pop(),
+            Some(MapEvent::Teleport {
+                x: 2,
+                y: 1,
+                angle: Some(90f32.to_radians())
+            })
+        );
+
+        let error = game.call(&mut script, "outside").unwrap_err();
+        assert_eq!(error.message, "set_tile: tile 5 0 outside of the map");
+        let error = game.call(&mut script, "unknown").unwrap_err();
+        assert_eq!(error.message, "spawn: unknown entity kind \"dragon\"");
+        let error = game.call(&mut script, "overflow").unwrap_err();
+        assert_eq!(
+            error.message,
+            "damage_player: invalid argument 1: 4294967196"
+        );
+        assert_eq!(game.player.get_health(), 90);
+        let error = game.call(&mut script, "take_all").unwrap_err();
+        assert_eq!(error.message, "take_item: invalid argument 2: 4294967296");
+    }
+
+    #[test]
+    fn test_map_script() {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let mut script = load_script(&map).unwrap().unwrap();
+        let mut game = Game::new(map);
+        script.init(&mut game.host()).unwrap();
+        assert_eq!(game.entities.len(), 1);
+        for _ in 0..10 {
+            let dt = [Value::Number(0.5)];
+            script.call(&mut game.host(), "on_tick", &dt).unwrap();
+        }
+        let ambush = [Value::Str("ambush".to_string())];
+        script
+            .call(&mut game.host(), "on_trigger", &ambush)
+            .unwrap();
+        let wall = [Value::Number(3.0), Value::Number(1.0)];
+        script.call(&mut game.host(), "on_use", &wall).unwrap();
+        assert_eq!(game.entities.len(), 2);
+        assert!(!game.map.is_wall(3, 1));
+    }
+}
@@ -0,0 +1,391 @@
+use crate::framebuffer::Pixel;
+use crate::map::{Map, MapTextures};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub type TextureId = usize;
+
+/// Texture used when a texture is missing or invalid, always loaded first
+pub const FALLBACK_TEXTURE: TextureId = 0;
+const FALLBACK_SIZE: usize = 64;
+/// File extensions of the images loaded from the texture directory
+const EXTENSIONS: [&str; 2] = ["png", "bmp"];
+
+/// Pixels of a texture, stored row by row
+pub struct Texture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Pixel>) -> Self {
+        assert!(pixels.len() == width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Magenta and black checkerboard used in place of missing textures
+    pub fn checkerboard(size: usize) -> Self {
+        let cell = (size / 8).max(1);
+        let pixels = (0..size * size)
+            .map(|i| {
+                if ((i % size) / cell + (i / size) / cell).is_multiple_of(2) {
+                    [255, 0, 255, 255]
+                } else {
+                    [0, 0, 0, 255]
+                }
+            })
+            .collect();
+        Self::new(size, size, pixels)
+    }
+
+    /// Loads a PNG or BMP image
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .to_rgba8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image.pixels().map(|pixel| pixel.0).collect();
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixel at the given coordinates, wrapping around the edges
+    pub fn get_pixel(&self, x: usize, y: usize) -> Pixel {
+        self.pixels[(y % self.height) * self.width + x % self.width]
+    }
+
+    pub fn get_pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+}
+
+/// Textures must be square or have power of two sides
+fn valid_size(width: usize, height: usize) -> bool {
+    width > 0
+        && height > 0
+        && (width == height || (width.is_power_of_two() && height.is_power_of_two()))
+}
+
+/// Position of a texture in the atlas, in pixels
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AtlasRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Loads the textures used by a map and gives access to them by name or id.
+/// Every texture is also packed in a single atlas image so the GPU renderer
+/// only has to upload one texture. Missing or invalid textures are replaced
+/// by a checkerboard and the problem is recorded in [TextureManager::get_errors]
+pub struct TextureManager {
+    names: HashMap<String, TextureId>,
+    textures: Vec<Texture>,
+    atlas: Texture,
+    rects: Vec<AtlasRect>,
+    walls: HashMap<u8, TextureId>,
+    floor: Option<TextureId>,
+    ceiling: Option<TextureId>,
+    sky: Option<TextureId>,
+    errors: Vec<String>,
+}
+
+impl TextureManager {
+    /// Creates a manager containing only the fallback texture
+    pub fn new() -> Self {
+        let mut manager = Self {
+            names: HashMap::new(),
+            textures: vec![Texture::checkerboard(FALLBACK_SIZE)],
+            atlas: Texture::new(0, 0, Vec::new()),
+            rects: Vec::new(),
+            walls: HashMap::new(),
+            floor: None,
+            ceiling: None,
+            sky: None,
+            errors: Vec::new(),
+        };
+        manager.pack_atlas();
+        manager
+    }
+
+    /// Loads the texture directory declared in the map header
+    /// and resolves the wall, floor, ceiling and sky textures
+    pub fn for_map(map: &Map) -> Self {
+        let mut manager = Self::new();
+        let textures = map.get_textures();
+        if let Some(directory) = &textures.directory {
+            manager.load_directory(directory);
+        }
+        manager.assign(textures);
+        manager.pack_atlas();
+        manager
+    }
+
+    /// Loads every PNG and BMP image of the directory,
+    /// the name of a texture is its file name without extension
+    pub fn load_directory(&mut self, directory: &Path) {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.errors.push(format!("{}: {}", directory.display(), e));
+                return;
+            }
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                    })
+            })
+            .collect();
+        // sorted so the ids don't depend on the file system
+        paths.sort();
+
+        for path in paths {
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            match Texture::load(&path) {
+                Ok(texture) => {
+                    self.add(&name, texture);
+                }
+                Err(e) => self.errors.push(e),
+            }
+        }
+    }
+
+    /// Adds a texture, returns its id. If its size is invalid the name
+    /// is bound to the fallback texture instead.
+    /// [TextureManager::pack_atlas] must be called before using the atlas
+    pub fn add(&mut self, name: &str, texture: Texture) -> TextureId {
+        if !valid_size(texture.width, texture.height) {
+            self.errors.push(format!(
+                "Texture \"{}\" is {}x{}, textures must be square or have power of two sides",
+                name, texture.width, texture.height
+            ));
+            self.names.insert(name.to_string(), FALLBACK_TEXTURE);
+            return FALLBACK_TEXTURE;
+        }
+        self.textures.push(texture);
+        let id = self.textures.len() - 1;
+        self.names.insert(name.to_string(), id);
+        id
+    }
+
+    /// Id of the texture with the given name, or the fallback texture
+    /// (and an error) if there is no such texture
+    pub fn resolve(&mut self, name: &str) -> TextureId {
+        match self.names.get(name) {
+            Some(id) => *id,
+            None => {
+                self.errors.push(format!("Missing texture \"{}\"", name));
+                self.names.insert(name.to_string(), FALLBACK_TEXTURE);
+                FALLBACK_TEXTURE
+            }
+        }
+    }
+
+    fn assign(&mut self, textures: &MapTextures) {
+        let mut walls: Vec<_> = textures.walls.iter().collect();
+        walls.sort();
+        for (tile, name) in walls {
+            let id = self.resolve(name);
+            self.walls.insert(*tile, id);
+        }
+        self.floor = textures.floor.as_ref().map(|name| self.resolve(name));
+        self.ceiling = textures.ceiling.as_ref().map(|name| self.resolve(name));
+        self.sky = textures.sky.as_ref().map(|name| self.resolve(name));
+    }
+
+    /// Packs every texture in the atlas, using rows of textures
+    /// sorted by height
+    pub fn pack_atlas(&mut self) {
+        let area: usize = self.textures.iter().map(|t| t.width * t.height).sum();
+        let widest = self.textures.iter().map(|t| t.width).max().unwrap_or(1);
+        let atlas_width = ((area as f32).sqrt().ceil() as usize)
+            .max(widest)
+            .next_power_of_two();
+
+        let mut order: Vec<TextureId> = (0..self.textures.len()).collect();
+        order.sort_by_key(|id| std::cmp::Reverse(self.textures[*id].height));
+
+        let mut rects = vec![
+            AtlasRect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0
+            };
+            self.textures.len()
+        ];
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for id in order {
+            let texture = &self.textures[id];
+            if x + texture.width > atlas_width {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            rects[id] = AtlasRect {
+                x,
+                y,
+                width: texture.width,
+                height: texture.height,
+            };
+            x += texture.width;
+            row_height = row_height.max(texture.height);
+        }
+        let atlas_height = (y + row_height).next_power_of_two();
+
+        let mut pixels = vec![[0, 0, 0, 0]; atlas_width * atlas_height];
+        for (texture, rect) in self.textures.iter().zip(&rects) {
+            for (row, line) in texture.pixels.chunks(texture.width).enumerate() {
+                let start = (rect.y + row) * atlas_width + rect.x;
+                pixels[start..start + texture.width].copy_from_slice(line);
+            }
+        }
+        self.atlas = Texture::new(atlas_width, atlas_height, pixels);
+        self.rects = rects;
+    }
+
+    pub fn get_id(&self, name: &str) -> Option<TextureId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn get(&self, id: TextureId) -> &Texture {
+        &self.textures[id]
+    }
+
+    /// Every loaded texture, indexed by id
+    pub fn get_textures(&self) -> &[Texture] {
+        &self.textures
+    }
+
+    /// Texture drawn on the given wall tile, if any
+    pub fn get_wall(&self, tile: u8) -> Option<TextureId> {
+        self.walls.get(&tile).copied()
+    }
+
+    pub fn get_floor(&self) -> Option<TextureId> {
+        self.floor
+    }
+
+    pub fn get_ceiling(&self) -> Option<TextureId> {
+        self.ceiling
+    }
+
+    pub fn get_sky(&self) -> Option<TextureId> {
+        self.sky
+    }
+
+    pub fn get_atlas(&self) -> &Texture {
+        &self.atlas
+    }
+
+    pub fn get_atlas_rect(&self, id: TextureId) -> AtlasRect {
+        self.rects[id]
+    }
+
+    /// Problems met while loading the textures
+    pub fn get_errors(&self) -> &[String] {
+        &self.errors
+    }
+}
+
+impl Default for TextureManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod textures_tests {
+    use super::*;
+
+    fn plain(width: usize, height: usize, color: Pixel) -> Texture {
+        Texture::new(width, height, vec![color; width * height])
+    }
+
+    #[test]
+    fn size_validation() {
+        assert!(valid_size(64, 64));
+        assert!(valid_size(48, 48));
+        assert!(valid_size(256, 64));
+        assert!(!valid_size(48, 64));
+        assert!(!valid_size(0, 0));
+
+        let mut manager = TextureManager::new();
+        assert_eq!(
+            manager.add("bad", plain(48, 64, [1, 2, 3, 255])),
+            FALLBACK_TEXTURE
+        );
+        assert_eq!(manager.get_errors().len(), 1);
+    }
+
+    #[test]
+    fn missing_texture_falls_back() {
+        let mut manager = TextureManager::new();
+        assert_eq!(manager.resolve("nothing"), FALLBACK_TEXTURE);
+        assert_eq!(manager.get_errors().len(), 1);
+        // the error is only reported once
+        assert_eq!(manager.resolve("nothing"), FALLBACK_TEXTURE);
+        assert_eq!(manager.get_errors().len(), 1);
+    }
+
+    #[test]
+    fn atlas_contains_every_texture() {
+        let mut manager = TextureManager::new();
+        let red = manager.add("red", plain(32, 32, [255, 0, 0, 255]));
+        let green = manager.add("green", plain(128, 16, [0, 255, 0, 255]));
+        let blue = manager.add("blue", plain(16, 16, [0, 0, 255, 255]));
+        manager.pack_atlas();
+
+        let atlas = manager.get_atlas();
+        assert!(atlas.get_width().is_power_of_two());
+        assert!(atlas.get_height().is_power_of_two());
+        for id in [FALLBACK_TEXTURE, red, green, blue] {
+            let rect = manager.get_atlas_rect(id);
+            let texture = manager.get(id);
+            assert_eq!((rect.width, rect.height), (texture.width, texture.height));
+            for y in 0..rect.height {
+                for x in 0..rect.width {
+                    assert_eq!(
+                        atlas.get_pixel(rect.x + x, rect.y + y),
+                        texture.get_pixel(x, y)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn load_test_map_textures() {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let manager = TextureManager::for_map(&map);
+        assert!(
+            manager.get_errors().is_empty(),
+            "{:?}",
+            manager.get_errors()
+        );
+        assert!(manager.get_id("stone").is_some());
+        assert_ne!(manager.get_wall(1), Some(FALLBACK_TEXTURE));
+    }
+}
@@ -0,0 +1,456 @@
+use crate::map::Map;
+use crate::pathfinding::{NavGrid, Tile};
+use std::collections::{HashMap, VecDeque};
+
+/// Something happening to the map or the game, fired by the triggers
+#[derive(Clone, PartialEq, Debug)]
+pub enum MapEvent {
+    /// Changes a tile, 0 opens it (`open <x> <y>` or `tile <x> <y> <value>` actions)
+    SetTile { x: usize, y: usize, tile: u8 },
+    /// Moves the player to the center of a tile, the angle is in radians
+    /// (`teleport <x> <y> [angle in degrees]` action)
+    Teleport {
+        x: usize,
+        y: usize,
+        angle: Option<f32>,
+    },
+    /// `sound <name>` action
+    Sound(String),
+    /// `message <text>` action
+    Message(String),
+    /// `end` action
+    EndLevel,
+    /// A trigger fired, given before its actions to the `on_trigger`
+    /// callback of the level script
+    Triggered(String),
+    /// The use action was pressed facing a tile, for the `on_use` callback
+    Used { x: usize, y: usize },
+}
+
+/// Events waiting to be handled, drained by the game loop each tick
+#[derive(Default, Debug)]
+pub struct EventQueue {
+    events: VecDeque<MapEvent>,
+}
+
+impl EventQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, event: MapEvent) {
+        self.events.push_back(event);
+    }
+
+    /// Oldest event of the queue
+    pub fn pop(&mut self) -> Option<MapEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// When a trigger fires
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Activation {
+    /// The player walks into the area
+    Enter,
+    /// The player walks out of the area
+    Exit,
+    /// The player uses the area, from inside or facing it
+    Use,
+}
+
+/// A rectangle of tiles firing events, declared with the
+/// `trigger.<name>.<property>` fields of the map
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    name: String,
+    /// Inclusive corners of the area
+    min: Tile,
+    max: Tile,
+    activation: Activation,
+    events: Vec<MapEvent>,
+    /// Fires only the first time
+    once: bool,
+    /// Counted in the secrets found of the level
+    secret: bool,
+    fired: bool,
+    /// The player was in the area at the last update
+    inside: bool,
+}
+
+/// Reads pairs of tile coordinates, checking they are in the map
+fn parse_tiles(map: &Map, values: &[&str]) -> Result<Vec<Tile>, String> {
+    values
+        .chunks(2)
+        .map(|pair| {
+            let coordinate = |value: &str| {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid coordinate \"{}\"", value))
+            };
+            let [x, y] = pair else {
+                return Err("missing coordinate".to_string());
+            };
+            let (x, y) = (coordinate(x)?, coordinate(y)?);
+            if x >= map.get_width() || y >= map.get_height() {
+                return Err(format!("tile {} {} outside of the map", x, y));
+            }
+            Ok((x, y))
+        })
+        .collect()
+}
+
+fn parse_event(map: &Map, action: &str) -> Result<MapEvent, String> {
+    let action = action.trim();
+    let (name, arguments) = action.split_once(' ').unwrap_or((action, ""));
+    let values: Vec<&str> = arguments.split_whitespace().collect();
+    match (name, values.len()) {
+        ("open", 2) => {
+            let (x, y) = parse_tiles(map, &values)?[0];
+            Ok(MapEvent::SetTile { x, y, tile: 0 })
+        }
+        ("tile", 3) => {
+            let (x, y) = parse_tiles(map, &values[..2])?[0];
+            let tile = values[2]
+                .parse()
+                .map_err(|_| format!("invalid tile \"{}\"", values[2]))?;
+            Ok(MapEvent::SetTile { x, y, tile })
+        }
+        ("teleport", 2 | 3) => {
+            let (x, y) = parse_tiles(map, &values[..2])?[0];
+            if map.is_wall(x, y) {
+                return Err(format!("teleport into the wall {} {}", x, y));
+            }
+            let angle = match values.get(2) {
+                Some(angle) => Some(
+                    angle
+                        .parse::<f32>()
+                        .map_err(|_| format!("invalid angle \"{}\"", angle))?
+                        .to_radians(),
+                ),
+                None => None,
+            };
+            Ok(MapEvent::Teleport { x, y, angle })
+        }
+        ("sound", 1) => Ok(MapEvent::Sound(values[0].to_string())),
+        ("message", _) if !arguments.trim().is_empty() => {
+            Ok(MapEvent::Message(arguments.trim().to_string()))
+        }
+        ("end", 0) => Ok(MapEvent::EndLevel),
+        _ => Err(format!("invalid action \"{}\"", action)),
+    }
+}
+
+impl Trigger {
+    /// Reads a trigger from its properties: `area = <x0> <y0> [<x1> <y1>]`,
+    /// `on = enter|exit|use` (enter by default), `once = true|false`,
+    /// `secret = true|false` and `action`, the actions separated by `;`.
+    /// Without actions the trigger is only handled by the level script
+    pub fn parse(
+        map: &Map,
+        name: &str,
+        properties: &HashMap<String, String>,
+    ) -> Result<Self, String> {
+        for property in properties.keys() {
+            if !["area", "on", "once", "secret", "action"].contains(&property.as_str()) {
+                return Err(format!("unknown property \"{}\"", property));
+            }
+        }
+        let area = properties.get("area").ok_or("missing area")?;
+        let values: Vec<&str> = area.split_whitespace().collect();
+        let (min, max) = match parse_tiles(map, &values)?[..] {
+            [tile] => (tile, tile),
+            [a, b] => ((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1))),
+            _ => return Err(format!("invalid area \"{}\"", area)),
+        };
+        let activation = match properties.get("on").map(String::as_str) {
+            None | Some("enter") => Activation::Enter,
+            Some("exit") => Activation::Exit,
+            Some("use") => Activation::Use,
+            Some(on) => return Err(format!("invalid activation \"{}\"", on)),
+        };
+        let flag = |name: &str| match properties.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("invalid {} \"{}\"", name, value)),
+            None => Ok(false),
+        };
+        let once = flag("once")?;
+        let secret = flag("secret")?;
+        let events = match properties.get("action") {
+            Some(actions) => actions
+                .split(';')
+                .map(|action| parse_event(map, action))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            min,
+            max,
+            activation,
+            events,
+            once,
+            secret,
+            fired: false,
+            inside: false,
+        })
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The trigger fired at least once
+    pub fn has_fired(&self) -> bool {
+        self.fired
+    }
+
+    /// The trigger is a secret area, found once fired
+    pub fn is_secret(&self) -> bool {
+        self.secret
+    }
+
+    /// The player was in the area at the last update
+    pub fn is_inside(&self) -> bool {
+        self.inside
+    }
+
+    pub fn contains(&self, (x, y): Tile) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&y)
+    }
+
+    fn fire(&mut self, queue: &mut EventQueue) {
+        if self.once && self.fired {
+            return;
+        }
+        self.fired = true;
+        queue.push(MapEvent::Triggered(self.name.clone()));
+        for event in &self.events {
+            queue.push(event.clone());
+        }
+    }
+}
+
+/// Every trigger of the map
+#[derive(Clone, Debug, Default)]
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+    /// The use action was held at the last update, it fires on the press only
+    using: bool,
+}
+
+impl Triggers {
+    /// Reads the triggers of the map, sorted by name, and the errors of
+    /// the ones which couldn't be read
+    pub fn from_map(map: &Map) -> (Self, Vec<String>) {
+        let mut names: Vec<_> = map.get_triggers().keys().collect();
+        names.sort();
+        let mut triggers = Vec::new();
+        let mut errors = Vec::new();
+        for name in names {
+            match Trigger::parse(map, name, &map.get_triggers()[name]) {
+                Ok(trigger) => triggers.push(trigger),
+                Err(e) => errors.push(format!("Trigger {}: {}", name, e)),
+            }
+        }
+        (
+            Self {
+                triggers,
+                using: false,
+            },
+            errors,
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Trigger> {
+        self.triggers.iter()
+    }
+
+    /// Restores the state of a trigger from a saved game,
+    /// returns false if there is no trigger with this name
+    pub fn set_state(&mut self, name: &str, fired: bool, inside: bool) -> bool {
+        match self.triggers.iter_mut().find(|t| t.name == name) {
+            Some(trigger) => {
+                trigger.fired = fired;
+                trigger.inside = inside;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fires the triggers the player entered or left since the last update,
+    /// and the use triggers if the use action was just pressed. `facing`
+    /// is the tile in front of the player, for the switches on the walls,
+    /// it is also given with [MapEvent::Used] after the triggers
+    pub fn update(
+        &mut self,
+        player: Option<Tile>,
+        facing: Option<Tile>,
+        using: bool,
+        queue: &mut EventQueue,
+    ) {
+        let used = using && !self.using;
+        self.using = using;
+        for trigger in &mut self.triggers {
+            let inside = player.is_some_and(|tile| trigger.contains(tile));
+            let fires = match trigger.activation {
+                Activation::Enter => inside && !trigger.inside,
+                Activation::Exit => !inside && trigger.inside,
+                Activation::Use => {
+                    used && (inside || facing.is_some_and(|tile| trigger.contains(tile)))
+                }
+            };
+            trigger.inside = inside;
+            if fires {
+                trigger.fire(queue);
+            }
+        }
+        if let (true, Some((x, y))) = (used, facing) {
+            queue.push(MapEvent::Used { x, y });
+        }
+    }
+}
+
+/// Changes a tile of the map, and whether it can be walked through
+pub fn set_tile(map: &mut Map, nav: &mut NavGrid, (x, y): Tile, tile: u8) {
+    map[(x, y)] = tile;
+    nav.set_walkable(x, y, tile == 0);
+}
+
+#[cfg(test)]
+mod trigger_tests {
+    use super::*;
+    use crate::pathfinding::Connectivity;
+
+    fn room(triggers: &str) -> Map {
+        let text = format!(
+            "name = room\nsize = 5x4\n{}---\n1 1 1 1 1\n1 0 0 2 1\n1 0 0 0 1\n1 1 1 1 1\n",
+            triggers
+        );
+        Map::parse(text.as_bytes(), 64.0).unwrap()
+    }
+
+    fn drain(queue: &mut EventQueue) -> Vec<MapEvent> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn parse_triggers() {
+        let map = room("trigger.door.area = 1 1 2 2\ntrigger.door.action = open 3 1; message The door opens\ntrigger.door.once = true\ntrigger.bad.area = 9 9\ntrigger.bad.action = end\n");
+        let (triggers, errors) = Triggers::from_map(&map);
+        assert_eq!(errors.len(), 1);
+        let door = triggers.iter().next().unwrap();
+        assert_eq!(door.get_name(), "door");
+        assert!(door.contains((2, 2)) && !door.contains((3, 1)));
+        assert_eq!(
+            door.events,
+            [
+                MapEvent::SetTile {
+                    x: 3,
+                    y: 1,
+                    tile: 0
+                },
+                MapEvent::Message("The door opens".to_string())
+            ]
+        );
+
+        for action in [
+            "open 3",
+            "tile 1 1 x",
+            "teleport 0 0",
+            "sound",
+            "message",
+            "end now",
+            "dance",
+        ] {
+            let map = room(&format!(
+                "trigger.t.area = 1 1\ntrigger.t.action = {}\n",
+                action
+            ));
+            assert_eq!(Triggers::from_map(&map).1.len(), 1, "{}", action);
+        }
+    }
+
+    #[test]
+    fn test_map_triggers() {
+        let map = Map::open("maps/testMap.map", 64.0).unwrap();
+        let (triggers, errors) = Triggers::from_map(&map);
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(triggers.iter().count(), 5);
+    }
+
+    #[test]
+    fn enter_exit_and_once() {
+        let map = room("trigger.a.area = 2 1 2 2\ntrigger.a.action = sound beep\ntrigger.b.area = 2 1 2 2\ntrigger.b.on = exit\ntrigger.b.once = true\ntrigger.b.action = end\n");
+        let (mut triggers, errors) = Triggers::from_map(&map);
+        assert!(errors.is_empty());
+        let mut queue = EventQueue::new();
+        triggers.update(Some((1, 1)), None, false, &mut queue);
+        assert!(queue.is_empty());
+        triggers.update(Some((2, 1)), None, false, &mut queue);
+        // moving inside the area doesn't fire again
+        triggers.update(Some((2, 2)), None, false, &mut queue);
+        let beep = [
+            MapEvent::Triggered("a".to_string()),
+            MapEvent::Sound("beep".to_string()),
+        ];
+        assert_eq!(drain(&mut queue), beep);
+        triggers.update(Some((1, 2)), None, false, &mut queue);
+        assert_eq!(
+            drain(&mut queue),
+            [MapEvent::Triggered("b".to_string()), MapEvent::EndLevel]
+        );
+        triggers.update(Some((2, 2)), None, false, &mut queue);
+        triggers.update(Some((1, 2)), None, false, &mut queue);
+        assert_eq!(drain(&mut queue), beep);
+    }
+
+    #[test]
+    fn use_from_inside_or_facing() {
+        let map = room("trigger.switch.area = 3 1\ntrigger.switch.on = use\ntrigger.switch.action = tile 3 1 0; teleport 1 2 90\n");
+        let (mut triggers, _) = Triggers::from_map(&map);
+        let mut queue = EventQueue::new();
+        triggers.update(Some((2, 1)), Some((2, 2)), true, &mut queue);
+        assert_eq!(drain(&mut queue), [MapEvent::Used { x: 2, y: 2 }]);
+        // held since the last update
+        triggers.update(Some((2, 1)), Some((3, 1)), true, &mut queue);
+        assert!(queue.is_empty());
+        triggers.update(Some((2, 1)), Some((3, 1)), false, &mut queue);
+        triggers.update(Some((2, 1)), Some((3, 1)), true, &mut queue);
+        assert_eq!(queue.pop(), Some(MapEvent::Triggered("switch".to_string())));
+        assert_eq!(
+            queue.pop(),
+            Some(MapEvent::SetTile {
+                x: 3,
+                y: 1,
+                tile: 0
+            })
+        );
+        assert_eq!(
+            queue.pop(),
+            Some(MapEvent::Teleport {
+                x: 1,
+                y: 2,
+                angle: Some(90f32.to_radians())
+            })
+        );
+        assert_eq!(queue.pop(), Some(MapEvent::Used { x: 3, y: 1 }));
+    }
+
+    #[test]
+    fn tile_changes() {
+        let mut map = room("");
+        let mut nav = NavGrid::new(&map, Connectivity::Four);
+        assert!(!nav.is_walkable(3, 1));
+        set_tile(&mut map, &mut nav, (3, 1), 0);
+        assert!(!map.is_wall(3, 1) && nav.is_walkable(3, 1));
+        set_tile(&mut map, &mut nav, (2, 2), 1);
+        assert!(map.is_wall(2, 2) && !nav.is_walkable(2, 2));
+    }
+}
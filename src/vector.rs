@@ -1,5 +1,6 @@
 use std::ops::{Add, Sub, Mul, AddAssign, SubAssign, MulAssign, Div};
 
+#[derive(PartialEq, Debug)]
 pub struct Vector<T> {
     pub x: T,
     pub y: T,
@@ -20,21 +21,21 @@ impl Vector<f32> {
 	}
 
 	#[allow(dead_code)]
-	pub fn to_i32(&self) -> Vector<i32> {
+	pub fn to_i32(self) -> Vector<i32> {
 		Vector::new(self.x as i32, self.y as i32)
 	}
 }
 
 impl Vector<i32> {
 	#[allow(dead_code)]
-	pub fn to_f32(&self) -> Vector<f32> {
+	pub fn to_f32(self) -> Vector<f32> {
 		Vector::new(self.x as f32, self.y as f32)
 	}
 }
 
 impl<T> Clone for Vector<T> where T: Copy {
 	fn clone(&self) -> Self {
-		Self { x: self.x, y: self.y }
+		*self
 	}
 }
 
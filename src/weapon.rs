@@ -0,0 +1,532 @@
+use crate::entity::{Entities, Entity, EntityEvent, EntityId, SpriteDef, ThinkContext};
+use crate::framebuffer::Pixel;
+use crate::map::Map;
+use crate::vector::Vector;
+use macroquad::prelude::{draw_circle, draw_rectangle, Color, Rect};
+
+/// Size of the impact puffs, in tiles
+const PUFF_SIZE: f32 = 0.12;
+/// How long the impact puffs stay, in seconds
+const PUFF_DURATION: f32 = 0.3;
+const WALL_PUFF_COLOR: Pixel = [200, 200, 180, 255];
+const BLOOD_PUFF_COLOR: Pixel = [180, 0, 0, 255];
+/// Distance the puffs are pulled back from the impact toward the shooter,
+/// in world units, just enough for the walls not to hide them
+const PUFF_EPSILON: f32 = 0.01;
+/// Size of the projectiles, in tiles
+const PROJECTILE_SIZE: f32 = 0.15;
+/// Bob cycles per second while walking at full speed
+const BOB_FREQUENCY: f32 = 1.8;
+/// How long the weapon stays pushed back after a shot, in seconds
+const RECOIL_DURATION: f32 = 0.15;
+
+/// How a weapon reaches its target
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FireMode {
+    /// Instant hit along a ray
+    Hitscan,
+    /// Shoots an entity moving at the given speed, in world units per second
+    Projectile { speed: f32 },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WeaponDef {
+    pub name: &'static str,
+    pub mode: FireMode,
+    pub damage: i32,
+    /// Shots per second
+    pub fire_rate: f32,
+    /// In tiles
+    pub range: f32,
+    pub max_ammo: u32,
+    /// How far the shots are heard, in tiles, see [crate::pathfinding::Noise]
+    pub loudness: u32,
+    /// Color of the weapon and of its projectiles
+    pub color: Pixel,
+}
+
+pub const PISTOL: WeaponDef = WeaponDef {
+    name: "pistol",
+    mode: FireMode::Hitscan,
+    damage: 10,
+    fire_rate: 3.0,
+    range: 20.0,
+    max_ammo: 50,
+    loudness: 12,
+    color: [120, 120, 130, 255],
+};
+
+pub const LAUNCHER: WeaponDef = WeaponDef {
+    name: "launcher",
+    mode: FireMode::Projectile { speed: 400.0 },
+    damage: 40,
+    fire_rate: 1.0,
+    range: 16.0,
+    max_ammo: 10,
+    loudness: 16,
+    color: [220, 120, 30, 255],
+};
+
+/// Every weapon, in the switching order
+pub const WEAPONS: [WeaponDef; 2] = [PISTOL, LAUNCHER];
+
+pub fn find_weapon(name: &str) -> Option<WeaponDef> {
+    WEAPONS.into_iter().find(|def| def.name == name)
+}
+
+/// A weapon carried by the player, with its ammo
+#[derive(Clone, Debug)]
+pub struct Weapon {
+    def: WeaponDef,
+    ammo: u32,
+    /// Time before the next shot can be fired, in seconds
+    cooldown: f32,
+}
+
+impl Weapon {
+    pub fn new(def: WeaponDef, ammo: u32) -> Self {
+        Self {
+            def,
+            ammo: ammo.min(def.max_ammo),
+            cooldown: 0.0,
+        }
+    }
+
+    pub fn get_def(&self) -> &WeaponDef {
+        &self.def
+    }
+
+    pub fn get_ammo(&self) -> u32 {
+        self.ammo
+    }
+
+    /// Adds ammo up to the maximum, returns the amount actually added
+    pub fn add_ammo(&mut self, amount: u32) -> u32 {
+        let added = amount.min(self.def.max_ammo - self.ammo);
+        self.ammo += added;
+        added
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        self.cooldown = (self.cooldown - delta).max(0.0);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.cooldown <= 0.0 && self.ammo > 0
+    }
+
+    /// Uses a round and starts the cooldown, returns false if the weapon isn't ready
+    fn trigger(&mut self) -> bool {
+        if !self.is_ready() {
+            return false;
+        }
+        self.ammo -= 1;
+        self.cooldown = 1.0 / self.def.fire_rate;
+        true
+    }
+}
+
+/// Where a shot stopped
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Impact {
+    pub point: Vector<f32>,
+    /// The entity hit, None for a wall
+    pub target: Option<EntityId>,
+}
+
+/// Parameter along the segment where it enters the circle, between 0 and 1
+fn segment_circle(
+    from: Vector<f32>,
+    to: Vector<f32>,
+    center: Vector<f32>,
+    radius: f32,
+) -> Option<f32> {
+    let direction = to - from;
+    let offset = from - center;
+    let a = direction.x * direction.x + direction.y * direction.y;
+    let b = 2.0 * (offset.x * direction.x + offset.y * direction.y);
+    let c = offset.x * offset.x + offset.y * offset.y - radius * radius;
+    if c <= 0.0 {
+        // starts inside the circle
+        return Some(0.0);
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if a == 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / (2.0 * a);
+    (0.0..=1.0).contains(&t).then_some(t)
+}
+
+/// First thing hit along the segment: a wall, found with the same grid
+/// traversal as the rendering, or a living entity which can be damaged,
+/// among the ones of the cells crossed. `ignore` is the entity shooting, if any
+pub fn trace(
+    map: &Map,
+    entities: &Entities,
+    from: Vector<f32>,
+    to: Vector<f32>,
+    ignore: Option<EntityId>,
+) -> Option<Impact> {
+    let wall = map.cast_segment(from, to);
+    let end = wall.map_or(to, |hit| hit.point);
+    let target = entities
+        .along(from, end)
+        .filter(|entity| Some(entity.get_id()) != ignore && entity.health.is_some())
+        .filter(|entity| !entity.is_dead() && !entity.is_removed())
+        .filter_map(|entity| {
+            Some((
+                entity,
+                segment_circle(from, end, entity.pos, entity.radius)?,
+            ))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    match (target, wall) {
+        (Some((entity, t)), _) => Some(Impact {
+            point: from + (end - from) * t,
+            target: Some(entity.get_id()),
+        }),
+        (None, Some(hit)) => Some(Impact {
+            point: hit.point,
+            target: None,
+        }),
+        (None, None) => None,
+    }
+}
+
+/// Spawns a short-lived sprite where a shot hit
+pub fn spawn_puff(entities: &mut Entities, point: Vector<f32>, height: f32, color: Pixel) {
+    let mut puff = Entity::new(point, 1.0);
+    puff.solid = false;
+    puff.sprite = Some(SpriteDef {
+        texture: None,
+        color,
+        size: PUFF_SIZE,
+        elevation: height - PUFF_SIZE / 2.0,
+    });
+    let mut remaining = PUFF_DURATION;
+    puff.set_think(move |entity: &mut Entity, context: &mut ThinkContext| {
+        remaining -= context.delta;
+        if remaining <= 0.0 {
+            entity.remove();
+        }
+    });
+    entities.spawn(puff);
+}
+
+/// Damages what the shot hit and marks the impact with a puff.
+/// `height` is the height of the shot, in tiles
+pub fn apply_impact(
+    entities: &mut Entities,
+    from: Vector<f32>,
+    impact: &Impact,
+    damage: i32,
+    height: f32,
+) {
+    let color = match impact.target.and_then(|id| entities.get_mut(id)) {
+        Some(entity) => {
+            entity.damage(damage);
+            BLOOD_PUFF_COLOR
+        }
+        None => WALL_PUFF_COLOR,
+    };
+    let back = from - impact.point;
+    let length = (back.x * back.x + back.y * back.y).sqrt();
+    let point = if length > 0.0 {
+        impact.point + back * (PUFF_EPSILON.min(length) / length)
+    } else {
+        impact.point
+    };
+    spawn_puff(entities, point, height, color);
+}
+
+/// State of a projectile entity, which reports its moves with
+/// [EntityEvent::ProjectileMoved] so they can be checked by [move_projectile]
+struct Projectile {
+    damage: i32,
+    height: f32,
+    /// Time before the projectile vanishes, in seconds
+    lifetime: f32,
+}
+
+impl crate::entity::Think for Projectile {
+    fn think(&mut self, entity: &mut Entity, context: &mut ThinkContext) {
+        self.lifetime -= context.delta;
+        if self.lifetime <= 0.0 {
+            entity.remove();
+            return;
+        }
+        context.events.push(EntityEvent::ProjectileMoved {
+            source: entity.get_id(),
+            from: entity.pos,
+            to: entity.pos + entity.velocity * context.delta,
+            damage: self.damage,
+            height: self.height,
+        });
+    }
+}
+
+/// Checks the move of a projectile, reported by [EntityEvent::ProjectileMoved]:
+/// if it hits something the impact is applied and the projectile removed
+pub fn move_projectile(
+    map: &Map,
+    entities: &mut Entities,
+    source: EntityId,
+    from: Vector<f32>,
+    to: Vector<f32>,
+    damage: i32,
+    height: f32,
+) {
+    if let Some(impact) = trace(map, entities, from, to, Some(source)) {
+        if let Some(projectile) = entities.get_mut(source) {
+            projectile.remove();
+        }
+        apply_impact(entities, from, &impact, damage, height);
+    }
+}
+
+/// Fires the weapon from `origin` toward `angle`, at `height` tiles above
+/// the floor. Returns false if the weapon isn't ready
+pub fn fire(
+    weapon: &mut Weapon,
+    map: &Map,
+    entities: &mut Entities,
+    origin: Vector<f32>,
+    angle: f32,
+    height: f32,
+) -> bool {
+    if !weapon.trigger() {
+        return false;
+    }
+    let def = weapon.def;
+    let direction = Vector::new(angle.cos(), angle.sin());
+    let range = def.range * map.get_tile_size();
+    match def.mode {
+        FireMode::Hitscan => {
+            if let Some(impact) = trace(map, entities, origin, origin + direction * range, None) {
+                apply_impact(entities, origin, &impact, def.damage, height);
+            }
+        }
+        FireMode::Projectile { speed } => {
+            // a point, so it reaches the walls its moves are traced against
+            let mut projectile = Entity::new(origin, 0.0);
+            projectile.solid = false;
+            projectile.angle = angle;
+            projectile.velocity = direction * speed;
+            projectile.sprite = Some(SpriteDef {
+                texture: None,
+                color: def.color,
+                size: PROJECTILE_SIZE,
+                elevation: height - PROJECTILE_SIZE / 2.0,
+            });
+            projectile.set_think(Projectile {
+                damage: def.damage,
+                height,
+                lifetime: range / speed,
+            });
+            entities.spawn(projectile);
+        }
+    }
+    true
+}
+
+/// Offset of the weapon sprite for a bob phase, in fractions of the weapon
+/// size: a figure of eight, swinging sideways and dipping twice per cycle
+pub fn bob_offset(phase: f32, amount: f32) -> Vector<f32> {
+    let angle = phase * std::f32::consts::TAU;
+    Vector::new(
+        angle.sin() * amount,
+        (angle * 2.0).sin().abs() * amount * 0.5,
+    )
+}
+
+/// First-person weapon drawn over the 3D view
+#[derive(Default)]
+pub struct WeaponView {
+    /// Position in the bob cycle, between 0 and 1
+    phase: f32,
+    /// How much the weapon bobs, follows the player speed
+    amount: f32,
+    /// Time left before the weapon is back in place after a shot
+    recoil: f32,
+}
+
+impl WeaponView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `speed` is the player speed relative to its walking speed
+    pub fn update(&mut self, delta: f32, speed: f32) {
+        let speed = speed.clamp(0.0, 1.0);
+        self.phase = (self.phase + BOB_FREQUENCY * speed * delta).fract();
+        // eases toward the new amount so stopping doesn't snap the weapon
+        self.amount += (speed * 0.1 - self.amount) * (delta * 8.0).min(1.0);
+        self.recoil = (self.recoil - delta).max(0.0);
+    }
+
+    pub fn shoot(&mut self) {
+        self.recoil = RECOIL_DURATION;
+    }
+
+    /// Draws the weapon at the bottom of the viewport
+    pub fn draw(&self, weapon: &Weapon, viewport: Rect) {
+        let size = viewport.w.min(viewport.h) * 0.3;
+        let bob = bob_offset(self.phase, self.amount);
+        let recoil = self.recoil / RECOIL_DURATION;
+        let x = viewport.x + viewport.w / 2.0 + bob.x * size;
+        let y = viewport.y + viewport.h - size * (0.8 - bob.y - recoil * 0.1);
+        let [r, g, b, _] = weapon.def.color;
+        let color = Color::from_rgba(r, g, b, 255);
+        let dark = Color::from_rgba(r / 2, g / 2, b / 2, 255);
+
+        if recoil > 0.5 {
+            draw_circle(
+                x,
+                y,
+                size * 0.15 * recoil,
+                Color::from_rgba(255, 220, 120, 220),
+            );
+        }
+        // barrel, then the body and the grip
+        draw_rectangle(x - size * 0.06, y, size * 0.12, size * 0.5, color);
+        draw_rectangle(
+            x - size * 0.15,
+            y + size * 0.35,
+            size * 0.3,
+            size * 0.25,
+            dark,
+        );
+        draw_rectangle(
+            x - size * 0.08,
+            y + size * 0.55,
+            size * 0.16,
+            size * 0.3,
+            dark,
+        );
+    }
+}
+
+#[cfg(test)]
+mod weapon_tests {
+    use super::*;
+    use crate::pathfinding::{Connectivity, FlowField, NavGrid};
+
+    /// A corridor along x, 64 world units per tile
+    fn corridor() -> Map {
+        #[rustfmt::skip]
+        let tiles = vec![
+            1, 1, 1, 1, 1, 1, 1, 1,
+            1, 0, 0, 0, 0, 0, 0, 1,
+            1, 1, 1, 1, 1, 1, 1, 1,
+        ];
+        Map::new("corridor".to_string(), 8, 3, 64.0, tiles)
+    }
+
+    fn target(entities: &mut Entities, x: f32, health: i32) -> EntityId {
+        let mut entity = Entity::new(Vector::new(x, 96.0), 10.0);
+        entity.health = Some(health);
+        entities.spawn(entity)
+    }
+
+    /// Runs the entities, moving the projectiles like the game does
+    fn run(map: &Map, entities: &mut Entities, ticks: usize) {
+        let nav = NavGrid::new(map, Connectivity::Four);
+        let field = FlowField::new();
+        for _ in 0..ticks {
+            let mut context =
+                ThinkContext::new(map, &nav, &field, Vector::new(80.0, 96.0), 1.0 / 60.0);
+            entities.update(&mut context);
+            for event in context.events {
+                if let EntityEvent::ProjectileMoved {
+                    source,
+                    from,
+                    to,
+                    damage,
+                    height,
+                } = event
+                {
+                    move_projectile(map, entities, source, from, to, damage, height);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn trace_hits_walls_and_entities() {
+        let map = corridor();
+        let mut entities = Entities::new(&map);
+        let from = Vector::new(80.0, 96.0);
+        let impact = trace(&map, &entities, from, Vector::new(1000.0, 96.0), None).unwrap();
+        assert!((impact.point.x - 448.0).abs() < 1e-3);
+        assert_eq!(impact.target, None);
+
+        // the closest one is hit, even if spawned last
+        let far = target(&mut entities, 300.0, 10);
+        let near = target(&mut entities, 200.0, 10);
+        let impact = trace(&map, &entities, from, Vector::new(1000.0, 96.0), None).unwrap();
+        assert_eq!(impact.target, Some(near));
+        assert!((impact.point.x - 190.0).abs() < 1e-3);
+        let impact = trace(&map, &entities, from, Vector::new(1000.0, 96.0), Some(near));
+        assert_eq!(impact.unwrap().target, Some(far));
+        // out of range
+        assert_eq!(
+            trace(&map, &entities, from, Vector::new(150.0, 96.0), None),
+            None
+        );
+    }
+
+    #[test]
+    fn hitscan_uses_ammo_and_fire_rate() {
+        let map = corridor();
+        let mut entities = Entities::new(&map);
+        let id = target(&mut entities, 300.0, 25);
+        let mut pistol = Weapon::new(PISTOL, 2);
+        let origin = Vector::new(80.0, 96.0);
+        assert!(fire(&mut pistol, &map, &mut entities, origin, 0.0, 0.5));
+        assert_eq!(entities.get(id).unwrap().health, Some(15));
+        // a puff was spawned in front of the target, at the impact
+        assert_eq!(entities.len(), 2);
+        let puff = entities.iter().last().unwrap().pos;
+        assert!(puff.x < 290.0 && puff.x > 290.0 - 2.0 * PUFF_EPSILON);
+        assert!(!fire(&mut pistol, &map, &mut entities, origin, 0.0, 0.5));
+        pistol.update(1.0 / PISTOL.fire_rate);
+        assert!(fire(&mut pistol, &map, &mut entities, origin, 0.0, 0.5));
+        assert_eq!(pistol.get_ammo(), 0);
+        pistol.update(1.0);
+        assert!(!pistol.is_ready());
+        assert_eq!(pistol.add_ammo(100), PISTOL.max_ammo);
+    }
+
+    #[test]
+    fn projectiles_fly_then_hit() {
+        let map = corridor();
+        let mut entities = Entities::new(&map);
+        let id = target(&mut entities, 400.0, 100);
+        let mut launcher = Weapon::new(LAUNCHER, 5);
+        let origin = Vector::new(80.0, 96.0);
+        assert!(fire(&mut launcher, &map, &mut entities, origin, 0.0, 0.5));
+        run(&map, &mut entities, 10);
+        assert_eq!(entities.get(id).unwrap().health, Some(100));
+        run(&map, &mut entities, 50);
+        assert_eq!(entities.get(id).unwrap().health, Some(60));
+        // the projectile and the puff are gone
+        run(&map, &mut entities, 60);
+        assert_eq!(entities.len(), 1);
+
+        // against a wall
+        entities.get_mut(id).unwrap().remove();
+        launcher.update(1.0);
+        fire(&mut launcher, &map, &mut entities, origin, 0.0, 0.5);
+        run(&map, &mut entities, 90);
+        assert!(entities.is_empty());
+    }
+
+    #[test]
+    fn bobbing() {
+        assert_eq!(bob_offset(0.0, 0.1), Vector::new(0.0, 0.0));
+        let quarter = bob_offset(0.25, 0.1);
+        assert!((quarter.x - 0.1).abs() < 1e-6 && quarter.y.abs() < 1e-6);
+        assert_eq!(bob_offset(0.3, 0.0), Vector::new(0.0, 0.0));
+    }
+}